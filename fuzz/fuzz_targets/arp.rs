@@ -0,0 +1,22 @@
+//! ARP packets, after the Ethernet header.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use virtual_ip_host::arp::{ArpResolve, EtherIpResolver};
+use virtual_ip_host::ether::MacAddress;
+use virtual_ip_host::ip::IpAddress;
+use virtual_ip_host::Destination;
+
+fuzz_target!(|data: &[u8]| {
+    let mut resolver = EtherIpResolver::new(
+        MacAddress::new([0x02, 0, 0, 0, 0, 1]),
+        IpAddress::new_be_bytes([10, 0, 0, 1]),
+    );
+    // A pending request lets a reply resolve it.
+    let _ = resolver.resolve(
+        IpAddress::new_be_bytes([10, 0, 0, 2]),
+        IpAddress::new_be_bytes([10, 0, 0, 1]),
+    );
+    for &dst in &[Destination::ToMyself, Destination::Broadcast, Destination::Promisc] {
+        let _ = resolver.parse(data, dst);
+    }
+});
@@ -0,0 +1,53 @@
+//! Frames as the driver sees them: offload resolution, restored VLAN tags, the MAC header
+//! and the tags. The first three bytes choose what the kernel reported with the frame.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use virtual_ip_host::ether::header::{self, MacHeader};
+use virtual_ip_host::ether::offload;
+use virtual_ip_host::packet::PacketBuf;
+use virtual_ip_host::socket::{ChecksumStatus, FrameInfo, Gso, GsoType};
+
+use map_struct::Mappable;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 3 {
+        return;
+    }
+    let (report, frame) = data.split_at(3);
+    let gso_type = match report[0] & 0x0C {
+        0x04 => Some(GsoType::TcpV4),
+        0x08 => Some(GsoType::UdpL4),
+        0x0C => Some(GsoType::Udp),
+        _ => None,
+    };
+    let info = FrameInfo {
+        checksum: if report[0] & 0x01 != 0 {
+            ChecksumStatus::Partial(None)
+        } else {
+            ChecksumStatus::Unknown
+        },
+        gso: gso_type.map(|gso_type| Gso {
+            gso_type,
+            size: u16::from_be_bytes([report[1], report[2]]) as usize,
+            ecn: false,
+        }),
+        vlan_tci: if report[0] & 0x02 != 0 {
+            Some(u16::from_be_bytes([report[1], report[2]]))
+        } else {
+            None
+        },
+        ..FrameInfo::default()
+    };
+
+    let frames = match offload::resolve(PacketBuf::from_payload(frame), info) {
+        Ok(frames) => frames,
+        Err(_) => return,
+    };
+    for (mut frame, mut info) in frames {
+        let _ = header::frame_vlan_tags(&frame, &info);
+        header::restore_vlan_tag(&mut frame, &mut info);
+        if let Some((mac_header, rest)) = MacHeader::mapped(&frame) {
+            let _ = header::parse_vlan_tags(mac_header.ether_type(), rest);
+        }
+    }
+});
@@ -0,0 +1,16 @@
+//! ICMP messages, after the IPv4 header.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use virtual_ip_host::ip::icmp::IcmpDriver;
+use virtual_ip_host::ip::IpAddress;
+use virtual_ip_host::Destination;
+
+fuzz_target!(|data: &[u8]| {
+    let mut driver = IcmpDriver::new();
+    // A registered echo lets replies reach its receiver.
+    let _echo = driver.register_echo(b"ping");
+    let from = IpAddress::new_be_bytes([10, 0, 0, 2]);
+    for &dst in &[Destination::ToMyself, Destination::Promisc] {
+        let _ = driver.parse(from, dst, data);
+    }
+});
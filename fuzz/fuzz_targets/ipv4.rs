@@ -0,0 +1,18 @@
+//! IPv4 packets, after the Ethernet header.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use virtual_ip_host::ip::options::{parse_options, write_options};
+use virtual_ip_host::ip::{IpAddress, IpDriver, IpParse};
+use virtual_ip_host::Destination;
+
+fuzz_target!(|data: &[u8]| {
+    let mut driver = IpDriver::new(IpAddress::new_be_bytes([10, 0, 0, 1]));
+    for &dst in &[Destination::ToMyself, Destination::Multicast, Destination::Promisc] {
+        let _ = driver.parse(data, dst);
+    }
+
+    // The checksum keeps most options from the driver, so they are fed on their own too.
+    if let Ok(options) = parse_options(data) {
+        assert_eq!(parse_options(&write_options(&options)).ok(), Some(options));
+    }
+});
@@ -1,17 +1,60 @@
+use crate::wire::U16Be;
 use map_struct::Mappable;
 
 #[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
+#[repr(C)]
 pub struct ArpHeader {
-    pub hard_addr_space: u16,
-    pub proto_addr_space: u16,
-    pub hard_addr_len: u8,
-    pub proto_addr_len: u8,
-    pub op_code: u16,
+    hard_addr_space: U16Be,
+    proto_addr_space: U16Be,
+    hard_addr_len: u8,
+    proto_addr_len: u8,
+    op_code: U16Be,
 }
 
 unsafe impl Mappable for ArpHeader {}
 
+impl ArpHeader {
+    pub fn hard_addr_space(&self) -> u16 {
+        self.hard_addr_space.get()
+    }
+
+    pub fn set_hard_addr_space(&mut self, hard_addr_space: u16) {
+        self.hard_addr_space.set(hard_addr_space);
+    }
+
+    pub fn proto_addr_space(&self) -> u16 {
+        self.proto_addr_space.get()
+    }
+
+    pub fn set_proto_addr_space(&mut self, proto_addr_space: u16) {
+        self.proto_addr_space.set(proto_addr_space);
+    }
+
+    pub fn hard_addr_len(&self) -> u8 {
+        self.hard_addr_len
+    }
+
+    pub fn set_hard_addr_len(&mut self, hard_addr_len: u8) {
+        self.hard_addr_len = hard_addr_len;
+    }
+
+    pub fn proto_addr_len(&self) -> u8 {
+        self.proto_addr_len
+    }
+
+    pub fn set_proto_addr_len(&mut self, proto_addr_len: u8) {
+        self.proto_addr_len = proto_addr_len;
+    }
+
+    pub fn op_code(&self) -> u16 {
+        self.op_code.get()
+    }
+
+    pub fn set_op_code(&mut self, op_code: u16) {
+        self.op_code.set(op_code);
+    }
+}
+
 pub const ARPHRD_ETHER: u16 = 1;
 pub const ETHERTYPE_IP: u16 = 0x0800;
 pub const ARPOP_REQUEST: u16 = 1;
@@ -1,5 +1,7 @@
+use crate::builder::ArpBuilder;
 use crate::ether::{self, MacAddress};
 use crate::ip::IpAddress;
+use crate::packet::PacketBuf;
 use futures::channel::oneshot::{channel, Sender};
 use futures::prelude::*;
 use map_struct::Mappable;
@@ -7,6 +9,7 @@ use std::collections::HashMap;
 use std::future::Future;
 use std::mem;
 use std::pin::Pin;
+use std::time::{Duration, Instant};
 
 use crate::Destination;
 use error::ArpError;
@@ -15,35 +18,85 @@ use header::*;
 pub mod error;
 pub mod header;
 
+/// How long a request waits for its reply before its result completes with `None`.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
 pub enum ArpReply<T> {
-    Reply { dst: T, data: Vec<u8> },
+    Reply { dst: T, data: PacketBuf },
     Nop,
 }
 
-#[repr(C, packed)]
+#[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct EtherIpPayload {
-    pub sender_mac_addr: MacAddress,
-    pub sender_ip_addr: IpAddress,
-    pub target_mac_addr: MacAddress,
-    pub target_ip_addr: IpAddress,
+    sender_mac_addr: MacAddress,
+    sender_ip_addr: IpAddress,
+    target_mac_addr: MacAddress,
+    target_ip_addr: IpAddress,
 }
 
 pub enum ResolveResult<T> {
     Found(T),
     NotFound {
-        packet_to_send: Vec<u8>,
+        packet_to_send: PacketBuf,
         result: Pin<Box<dyn Future<Output = Option<T>> + Send>>,
     },
 }
 
 unsafe impl Mappable for EtherIpPayload {}
 
+impl EtherIpPayload {
+    pub fn sender_mac_addr(&self) -> MacAddress {
+        self.sender_mac_addr
+    }
+
+    pub fn set_sender_mac_addr(&mut self, sender_mac_addr: MacAddress) {
+        self.sender_mac_addr = sender_mac_addr;
+    }
+
+    pub fn sender_ip_addr(&self) -> IpAddress {
+        self.sender_ip_addr
+    }
+
+    pub fn set_sender_ip_addr(&mut self, sender_ip_addr: IpAddress) {
+        self.sender_ip_addr = sender_ip_addr;
+    }
+
+    pub fn target_mac_addr(&self) -> MacAddress {
+        self.target_mac_addr
+    }
+
+    pub fn set_target_mac_addr(&mut self, target_mac_addr: MacAddress) {
+        self.target_mac_addr = target_mac_addr;
+    }
+
+    pub fn target_ip_addr(&self) -> IpAddress {
+        self.target_ip_addr
+    }
+
+    pub fn set_target_ip_addr(&mut self, target_ip_addr: IpAddress) {
+        self.target_ip_addr = target_ip_addr;
+    }
+}
+
 pub trait ArpResolve {
     type InternetAddress;
     type LinkAddress;
     fn new(my_hard_addr: Self::LinkAddress, my_inet_addr: Self::InternetAddress) -> Self;
-    fn resolve(&mut self, key: Self::InternetAddress) -> ResolveResult<Self::LinkAddress>;
+    /// Looks `key` up, or else makes the request for it, sent from `src`.
+    fn resolve(
+        &mut self,
+        key: Self::InternetAddress,
+        src: Self::InternetAddress,
+    ) -> ResolveResult<Self::LinkAddress>;
+    /// Answers requests for `addr` too.
+    fn add_address(&mut self, addr: Self::InternetAddress);
+    /// The announcement of `addr` to broadcast.
+    fn announce(&self, addr: Self::InternetAddress) -> PacketBuf;
+    /// Stops answering requests for `addr`.
+    fn remove_address(&mut self, addr: Self::InternetAddress);
+    /// Gives up the requests left unanswered by `now`, completing their results with `None`.
+    fn expire_requests(&mut self, now: Instant);
     fn parse(
         &mut self,
         data: &[u8],
@@ -54,18 +107,14 @@ pub trait ArpResolve {
 pub struct EtherIpResolver {
     arp_table: HashMap<IpAddress, MacAddress>,
     my_mac_addr: MacAddress,
-    my_ip_addr: IpAddress,
-    requests: HashMap<IpAddress, Sender<MacAddress>>,
+    /// The addresses answered for.
+    my_ip_addrs: Vec<IpAddress>,
+    requests: HashMap<IpAddress, PendingRequest>,
 }
 
-impl EtherIpResolver {
-    fn set_header(arp_header: &mut ArpHeader, op_code: u16) {
-        arp_header.hard_addr_space = u16::to_be(ARPHRD_ETHER);
-        arp_header.proto_addr_space = u16::to_be(ETHERTYPE_IP);
-        arp_header.hard_addr_len = u8::to_be(6);
-        arp_header.proto_addr_len = u8::to_be(4);
-        arp_header.op_code = u16::to_be(op_code);
-    }
+struct PendingRequest {
+    sender: Sender<MacAddress>,
+    deadline: Instant,
 }
 
 impl ArpResolve for EtherIpResolver {
@@ -76,31 +125,31 @@ impl ArpResolve for EtherIpResolver {
         EtherIpResolver {
             arp_table: HashMap::new(),
             my_mac_addr: mac_addr,
-            my_ip_addr: ip_addr,
+            my_ip_addrs: vec![ip_addr],
             requests: HashMap::new(),
         }
     }
 
-    fn resolve(&mut self, key: Self::InternetAddress) -> ResolveResult<MacAddress> {
+    fn resolve(&mut self, key: IpAddress, src: IpAddress) -> ResolveResult<MacAddress> {
         if self.arp_table.contains_key(&key) {
             let value = self.arp_table[&key];
             return ResolveResult::Found(value);
         }
 
-        let mut packet = vec![0u8; mem::size_of::<ArpHeader>() + mem::size_of::<EtherIpPayload>()];
-
-        {
-            let (arp_header, mut payload) = ArpHeader::mapped_mut(&mut packet).unwrap();
-            let (ether_ip_payload, _) = EtherIpPayload::mapped_mut(&mut payload).unwrap();
-            EtherIpResolver::set_header(arp_header, ARPOP_REQUEST);
-            ether_ip_payload.sender_mac_addr = self.my_mac_addr;
-            ether_ip_payload.sender_ip_addr = IpAddress::to_be(self.my_ip_addr);
-            ether_ip_payload.target_mac_addr = ether::BROADCAST_MAC_ADDR;
-            ether_ip_payload.target_ip_addr = IpAddress::to_be(key);
-        }
+        // From the unspecified address, the request is a probe (RFC 5227, 2.1.1).
+        let packet = ArpBuilder::new(ARPOP_REQUEST)
+            .sender(self.my_mac_addr, src)
+            .target(ether::BROADCAST_MAC_ADDR, key)
+            .build();
 
         let (sender, receiver) = channel();
-        self.requests.insert(key, sender);
+        self.requests.insert(
+            key,
+            PendingRequest {
+                sender,
+                deadline: Instant::now() + REQUEST_TIMEOUT,
+            },
+        );
 
         ResolveResult::NotFound {
             packet_to_send: packet,
@@ -108,6 +157,30 @@ impl ArpResolve for EtherIpResolver {
         }
     }
 
+    fn add_address(&mut self, addr: IpAddress) {
+        if !self.my_ip_addrs.contains(&addr) {
+            self.my_ip_addrs.push(addr);
+        }
+    }
+
+    /// The announcement is a request whose sender and target are both `addr`
+    /// (RFC 5227, 2.3), which updates the caches of the hosts on the link.
+    fn announce(&self, addr: IpAddress) -> PacketBuf {
+        ArpBuilder::new(ARPOP_REQUEST)
+            .sender(self.my_mac_addr, addr)
+            .target(MacAddress::new([0; 6]), addr)
+            .build()
+    }
+
+    fn remove_address(&mut self, addr: IpAddress) {
+        self.my_ip_addrs.retain(|&owned| owned != addr);
+    }
+
+    /// Dropping the sender of a request cancels its result.
+    fn expire_requests(&mut self, now: Instant) {
+        self.requests.retain(|_, request| request.deadline > now);
+    }
+
     fn parse(
         &mut self,
         data: &[u8],
@@ -117,44 +190,51 @@ impl ArpResolve for EtherIpResolver {
         let (header, payload) = ArpHeader::mapped(&data).ok_or(ArpError::InvalidArpPacket)?;
         println!("- {:?}", &header);
 
-        let has = u16::from_be(header.hard_addr_space);
+        let has = header.hard_addr_space();
         if has != ARPHRD_ETHER {
             return Err(ArpError::UnsupportedHardwareAddressSpace(has));
         }
 
-        let pas = u16::from_be(header.proto_addr_space);
+        let pas = header.proto_addr_space();
         if pas != ETHERTYPE_IP {
             return Err(ArpError::UnsupportedProtocolAddressSpace(pas));
         }
 
-        match u16::from_be(header.op_code) {
+        // The payload is laid out for Ethernet and IPv4 addresses only.
+        if header.hard_addr_len() as usize != mem::size_of::<MacAddress>()
+            || header.proto_addr_len() as usize != mem::size_of::<IpAddress>()
+        {
+            return Err(ArpError::InvalidArpPacket);
+        }
+
+        match header.op_code() {
             ARPOP_REPLY => {
                 let (payload, _) =
                     EtherIpPayload::mapped(payload).ok_or(ArpError::InvalidArpPacket)?;
 
-                if payload.target_mac_addr == self.my_mac_addr
-                    && IpAddress::from_be(payload.target_ip_addr) == self.my_ip_addr
+                if payload.target_mac_addr() == self.my_mac_addr
+                    && self.my_ip_addrs.contains(&payload.target_ip_addr())
                 {
-                    let ip_addr = IpAddress::from_be(payload.sender_ip_addr);
+                    let ip_addr = payload.sender_ip_addr();
                     println!("- Registered IP Address: {:?}", ip_addr);
-                    self.arp_table.insert(ip_addr, payload.sender_mac_addr);
+                    self.arp_table.insert(ip_addr, payload.sender_mac_addr());
                 } else if dst != Destination::Promisc {
                     return Err(ArpError::InvalidArpPacket);
                 }
 
-                let sender_ip = IpAddress::from_be(payload.sender_ip_addr);
+                let sender_ip = payload.sender_ip_addr();
 
                 println!(
                     "- ARP Reply from {sender_ip:?} ({sender_mac:?}) to {target_ip:?} ({target_mac:?})",
                     sender_ip = sender_ip,
-                    sender_mac = payload.sender_mac_addr,
-                    target_ip = IpAddress::from_be(payload.target_ip_addr),
-                    target_mac = payload.target_mac_addr
+                    sender_mac = payload.sender_mac_addr(),
+                    target_ip = payload.target_ip_addr(),
+                    target_mac = payload.target_mac_addr()
                 );
 
-                if let Some(sender) = self.requests.remove(&sender_ip) {
+                if let Some(request) = self.requests.remove(&sender_ip) {
                     println!("- Waiting ARP request is found. Resolving Future...",);
-                    let _ = sender.send(payload.sender_mac_addr);
+                    let _ = request.sender.send(payload.sender_mac_addr());
                 }
 
                 Ok(ArpReply::Nop)
@@ -165,13 +245,14 @@ impl ArpResolve for EtherIpResolver {
 
                 println!(
                     "- ARP Request from {sender_ip:?} ({sender_mac:?}) to {target_ip:?} ({target_mac:?})",
-                    sender_ip = IpAddress::from_be(payload.sender_ip_addr),
-                    sender_mac = payload.sender_mac_addr,
-                    target_ip = IpAddress::from_be(payload.target_ip_addr),
-                    target_mac = payload.target_mac_addr
+                    sender_ip = payload.sender_ip_addr(),
+                    sender_mac = payload.sender_mac_addr(),
+                    target_ip = payload.target_ip_addr(),
+                    target_mac = payload.target_mac_addr()
                 );
 
-                if IpAddress::from_be(payload.target_ip_addr) != self.my_ip_addr {
+                let target_ip = payload.target_ip_addr();
+                if !self.my_ip_addrs.contains(&target_ip) {
                     println!("- ARP Request to the other machine. Ignoring...");
                     return Ok(ArpReply::Nop);
                 } else if dst == Destination::Promisc {
@@ -180,26 +261,17 @@ impl ArpResolve for EtherIpResolver {
 
                 println!(
                     "- Sending ARP Reply to {target_ip:?} ({target_mac:?})",
-                    target_ip = IpAddress::from_be(payload.sender_ip_addr),
-                    target_mac = payload.sender_mac_addr
+                    target_ip = payload.sender_ip_addr(),
+                    target_mac = payload.sender_mac_addr()
                 );
 
-                let mut result =
-                    vec![0u8; mem::size_of::<ArpHeader>() + mem::size_of::<EtherIpPayload>()];
-
-                {
-                    let (arp_header, mut payload2) = ArpHeader::mapped_mut(&mut result).unwrap();
-                    let (ether_ip_payload, _) = EtherIpPayload::mapped_mut(&mut payload2).unwrap();
-
-                    EtherIpResolver::set_header(arp_header, ARPOP_REPLY);
-                    ether_ip_payload.sender_mac_addr = self.my_mac_addr;
-                    ether_ip_payload.sender_ip_addr = IpAddress::to_be(self.my_ip_addr);
-                    ether_ip_payload.target_mac_addr = payload.sender_mac_addr;
-                    ether_ip_payload.target_ip_addr = payload.sender_ip_addr;
-                }
+                let result = ArpBuilder::new(ARPOP_REPLY)
+                    .sender(self.my_mac_addr, target_ip)
+                    .target(payload.sender_mac_addr(), payload.sender_ip_addr())
+                    .build();
 
                 Ok(ArpReply::Reply {
-                    dst: payload.sender_mac_addr,
+                    dst: payload.sender_mac_addr(),
                     data: result,
                 })
             }
@@ -207,3 +279,108 @@ impl ArpResolve for EtherIpResolver {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::arp::*;
+    use crate::builder::ArpBuilder;
+    use crate::reactor::block_on;
+
+    #[test]
+    fn test_request_timeout() {
+        let my_ip = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let silent = IpAddress::new_be_bytes([10, 0, 0, 2]);
+        let mut resolver = EtherIpResolver::new(MacAddress::new([0x02, 0, 0, 0, 0, 1]), my_ip);
+        let result = match resolver.resolve(silent, my_ip) {
+            ResolveResult::NotFound { result, .. } => result,
+            ResolveResult::Found(_) => panic!("an unknown address is resolved"),
+        };
+
+        // An unanswered request is given up, rather than waited for forever.
+        resolver.expire_requests(Instant::now());
+        assert_eq!(resolver.requests.len(), 1);
+        resolver.expire_requests(Instant::now() + REQUEST_TIMEOUT);
+        assert!(resolver.requests.is_empty());
+        assert_eq!(block_on(result), None);
+    }
+
+    #[test]
+    fn test_hostile_packets() {
+        let my_mac = MacAddress::new([0x02, 0, 0, 0, 0, 1]);
+        let my_ip = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let peer_mac = MacAddress::new([0x02, 0, 0, 0, 0, 2]);
+        let peer_ip = IpAddress::new_be_bytes([10, 0, 0, 2]);
+        let mut resolver = EtherIpResolver::new(my_mac, my_ip);
+        let request = ArpBuilder::new(ARPOP_REQUEST)
+            .sender(peer_mac, peer_ip)
+            .target(ether::BROADCAST_MAC_ADDR, my_ip);
+
+        assert!(resolver
+            .parse(&request.clone().build(), Destination::Broadcast)
+            .is_ok());
+        // Lengths other than those of Ethernet and IPv4 addresses are refused.
+        let long = request.clone().addr_lens(6, 16).build();
+        assert!(resolver.parse(&long, Destination::Broadcast).is_err());
+        let packet = request.build();
+        for len in 0..packet.len() {
+            assert!(resolver
+                .parse(&packet[..len], Destination::Broadcast)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_multiple_addresses() {
+        let my_mac = MacAddress::new([0x02, 0, 0, 0, 0, 1]);
+        let my_ip = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let secondary = IpAddress::new_be_bytes([172, 16, 0, 1]);
+        let peer_mac = MacAddress::new([0x02, 0, 0, 0, 0, 2]);
+        let peer_ip = IpAddress::new_be_bytes([172, 16, 0, 2]);
+        let mut resolver = EtherIpResolver::new(my_mac, my_ip);
+        let request = ArpBuilder::new(ARPOP_REQUEST)
+            .sender(peer_mac, peer_ip)
+            .target(ether::BROADCAST_MAC_ADDR, secondary)
+            .build();
+        assert!(matches!(
+            resolver.parse(&request, Destination::Broadcast),
+            Ok(ArpReply::Nop)
+        ));
+
+        resolver.add_address(secondary);
+        let announcement = resolver.announce(secondary);
+        let (header, payload) = ArpHeader::mapped(&announcement).unwrap();
+        let (payload, _) = EtherIpPayload::mapped(payload).unwrap();
+        assert_eq!(header.op_code(), ARPOP_REQUEST);
+        assert_eq!(payload.sender_mac_addr(), my_mac);
+        assert_eq!(payload.sender_ip_addr(), secondary);
+        assert_eq!(payload.target_ip_addr(), secondary);
+
+        // The reply comes from the address asked for.
+        match resolver.parse(&request, Destination::Broadcast) {
+            Ok(ArpReply::Reply { dst, data }) => {
+                assert_eq!(dst, peer_mac);
+                let (_, payload) = ArpHeader::mapped(&data).unwrap();
+                let (payload, _) = EtherIpPayload::mapped(payload).unwrap();
+                assert_eq!(payload.sender_ip_addr(), secondary);
+                assert_eq!(payload.sender_mac_addr(), my_mac);
+            }
+            _ => panic!("no reply to a request for the secondary address"),
+        }
+
+        // Requests are sent from the address given.
+        match resolver.resolve(IpAddress::new_be_bytes([172, 16, 0, 3]), secondary) {
+            ResolveResult::NotFound { packet_to_send, .. } => {
+                let (_, payload) = ArpHeader::mapped(&packet_to_send).unwrap();
+                let (payload, _) = EtherIpPayload::mapped(payload).unwrap();
+                assert_eq!(payload.sender_ip_addr(), secondary);
+            }
+            ResolveResult::Found(_) => panic!("an unknown address is resolved"),
+        }
+
+        resolver.remove_address(secondary);
+        assert!(matches!(
+            resolver.parse(&request, Destination::Broadcast),
+            Ok(ArpReply::Nop)
+        ));
+    }
+}
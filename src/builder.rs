@@ -0,0 +1,518 @@
+//! Builders composing frames layer by layer, Ethernet → VLAN → ARP or IPv4 → ICMP, e.g.
+//!
+//! ```
+//! use virtual_ip_host::builder::{EthernetBuilder, IcmpBuilder, Ipv4Builder};
+//! use virtual_ip_host::ether::MacAddress;
+//! use virtual_ip_host::ip::IpAddress;
+//! use virtual_ip_host::packet::PacketBuf;
+//!
+//! let frame = EthernetBuilder::new(
+//!     MacAddress::new([0x02, 0, 0, 0, 0, 1]),
+//!     MacAddress::new([0x02, 0, 0, 0, 0, 2]),
+//! )
+//! .ipv4(Ipv4Builder::new(
+//!     IpAddress::new_be_bytes([10, 0, 0, 1]),
+//!     IpAddress::new_be_bytes([10, 0, 0, 2]),
+//! ))
+//! .icmp(IcmpBuilder::echo_request(1, 1))
+//! .build(PacketBuf::from_payload(b"ping"));
+//! assert_eq!(frame.len(), 60);
+//! ```
+//!
+//! `build` emits the whole frame, ready for `EthernetDriver::send`. Lengths, checksums,
+//! ether types and protocol numbers follow from the layers, unless overridden to craft a
+//! malformed frame. A builder not given to an outer layer emits its layer alone.
+
+use crate::arp::header::{ArpHeader, ARPHRD_ETHER};
+use crate::arp::EtherIpPayload;
+use crate::ether::header::{MacHeader, VlanTags, ETHERTYPE_ARP, ETHERTYPE_IP};
+use crate::ether::MacAddress;
+use crate::ip::header::{IpHeaderWithoutOptions, IP_FLAG_DF, IP_VERSION_4};
+use crate::ip::icmp::echo::EchoPacketWithoutData;
+use crate::ip::icmp::header::IcmpHeader;
+use crate::ip::icmp::{ECHO_CODE, ECHO_REPLY_TYPE, ECHO_TYPE, ICMP_PROTOCOL_NUMBER};
+use crate::ip::options::{write_options, IpOption};
+use crate::ip::{IpAddress, DEFAULT_TTL};
+use crate::packet::PacketBuf;
+use crate::utils;
+use libc::ETH_ZLEN;
+use map_struct::Mappable;
+use std::mem;
+
+/// The Ethernet header, with the VLAN tags if any.
+#[derive(Debug, Clone)]
+pub struct EthernetBuilder {
+    src: MacAddress,
+    dst: MacAddress,
+    vlan: VlanTags,
+    ether_type: Option<u16>,
+    padding: bool,
+}
+
+impl EthernetBuilder {
+    pub fn new(src: MacAddress, dst: MacAddress) -> Self {
+        EthernetBuilder {
+            src,
+            dst,
+            vlan: VlanTags::default(),
+            ether_type: None,
+            padding: true,
+        }
+    }
+
+    pub fn vlan(mut self, vlan: VlanTags) -> Self {
+        self.vlan = vlan;
+        self
+    }
+
+    /// Overrides the ether type of the payload.
+    pub fn ether_type(mut self, ether_type: u16) -> Self {
+        self.ether_type = Some(ether_type);
+        self
+    }
+
+    /// Whether short frames are padded to the minimum length, which they are by default.
+    pub fn padding(mut self, padding: bool) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn arp(self, arp: ArpBuilder) -> ArpBuilder {
+        ArpBuilder {
+            link: Some(self),
+            ..arp
+        }
+    }
+
+    pub fn ipv4(self, ip: Ipv4Builder) -> Ipv4Builder {
+        Ipv4Builder {
+            link: Some(self),
+            ..ip
+        }
+    }
+
+    /// Prepends the header to `payload` in its headroom.
+    pub fn build(&self, ether_type: u16, mut payload: PacketBuf) -> PacketBuf {
+        let tags_len = self.vlan.len();
+        {
+            let header = payload.prepend(mem::size_of::<MacHeader>() + tags_len);
+            let (mac_header, _) = MacHeader::mapped_mut(header).unwrap();
+            mac_header.set_dst_mac(self.dst);
+            mac_header.set_src_mac(self.src);
+            // The ether type follows the tags, whose first TPID takes its place.
+            self.vlan.write(&mut header[12..12 + tags_len]);
+            header[12 + tags_len..]
+                .copy_from_slice(&self.ether_type.unwrap_or(ether_type).to_be_bytes());
+        }
+
+        let min_len = ETH_ZLEN as usize + tags_len;
+        if self.padding && payload.len() < min_len {
+            payload.resize(min_len, 0);
+        }
+
+        payload
+    }
+}
+
+/// An ARP packet for IPv4 over Ethernet.
+#[derive(Debug, Clone)]
+pub struct ArpBuilder {
+    link: Option<EthernetBuilder>,
+    op_code: u16,
+    sender: (MacAddress, IpAddress),
+    target: (MacAddress, IpAddress),
+    hard_addr_space: u16,
+    proto_addr_space: u16,
+    addr_lens: (u8, u8),
+}
+
+impl ArpBuilder {
+    pub fn new(op_code: u16) -> Self {
+        let unspecified = (MacAddress::new([0; 6]), IpAddress::new_be_bytes([0; 4]));
+        ArpBuilder {
+            link: None,
+            op_code,
+            sender: unspecified,
+            target: unspecified,
+            hard_addr_space: ARPHRD_ETHER,
+            proto_addr_space: ETHERTYPE_IP,
+            addr_lens: (
+                mem::size_of::<MacAddress>() as u8,
+                mem::size_of::<IpAddress>() as u8,
+            ),
+        }
+    }
+
+    pub fn sender(mut self, mac_addr: MacAddress, ip_addr: IpAddress) -> Self {
+        self.sender = (mac_addr, ip_addr);
+        self
+    }
+
+    pub fn target(mut self, mac_addr: MacAddress, ip_addr: IpAddress) -> Self {
+        self.target = (mac_addr, ip_addr);
+        self
+    }
+
+    /// Overrides the hardware address space, Ethernet by default.
+    pub fn hard_addr_space(mut self, hard_addr_space: u16) -> Self {
+        self.hard_addr_space = hard_addr_space;
+        self
+    }
+
+    /// Overrides the protocol address space, IPv4 by default.
+    pub fn proto_addr_space(mut self, proto_addr_space: u16) -> Self {
+        self.proto_addr_space = proto_addr_space;
+        self
+    }
+
+    /// Overrides the address lengths in the header. The addresses are written as they are.
+    pub fn addr_lens(mut self, hard_addr_len: u8, proto_addr_len: u8) -> Self {
+        self.addr_lens = (hard_addr_len, proto_addr_len);
+        self
+    }
+
+    pub fn build(&self) -> PacketBuf {
+        let mut packet = PacketBuf::new();
+        {
+            let data =
+                packet.append(mem::size_of::<ArpHeader>() + mem::size_of::<EtherIpPayload>());
+            let (header, rest) = ArpHeader::mapped_mut(data).unwrap();
+            header.set_hard_addr_space(self.hard_addr_space);
+            header.set_proto_addr_space(self.proto_addr_space);
+            header.set_hard_addr_len(self.addr_lens.0);
+            header.set_proto_addr_len(self.addr_lens.1);
+            header.set_op_code(self.op_code);
+
+            let (payload, _) = EtherIpPayload::mapped_mut(rest).unwrap();
+            payload.set_sender_mac_addr(self.sender.0);
+            payload.set_sender_ip_addr(self.sender.1);
+            payload.set_target_mac_addr(self.target.0);
+            payload.set_target_ip_addr(self.target.1);
+        }
+
+        match &self.link {
+            Some(link) => link.build(ETHERTYPE_ARP, packet),
+            None => packet,
+        }
+    }
+}
+
+/// The IPv4 header.
+#[derive(Debug, Clone)]
+pub struct Ipv4Builder {
+    link: Option<EthernetBuilder>,
+    src: IpAddress,
+    dst: IpAddress,
+    version: u8,
+    type_of_service: u8,
+    identification: u16,
+    flags_fragment_offset: u16,
+    ttl: u8,
+    options: Vec<u8>,
+    protocol: Option<u8>,
+    total_length: Option<u16>,
+    checksum: Option<u16>,
+}
+
+impl Ipv4Builder {
+    /// A header with the don't fragment flag and the default TTL.
+    pub fn new(src: IpAddress, dst: IpAddress) -> Self {
+        Ipv4Builder {
+            link: None,
+            src,
+            dst,
+            version: IP_VERSION_4,
+            type_of_service: 0,
+            identification: 0,
+            flags_fragment_offset: IP_FLAG_DF,
+            ttl: DEFAULT_TTL,
+            options: Vec::new(),
+            protocol: None,
+            total_length: None,
+            checksum: None,
+        }
+    }
+
+    pub fn type_of_service(mut self, type_of_service: u8) -> Self {
+        self.type_of_service = type_of_service;
+        self
+    }
+
+    pub fn identification(mut self, identification: u16) -> Self {
+        self.identification = identification;
+        self
+    }
+
+    pub fn flags_fragment_offset(mut self, flags_fragment_offset: u16) -> Self {
+        self.flags_fragment_offset = flags_fragment_offset;
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn options(mut self, options: &[IpOption]) -> Self {
+        self.options = write_options(options);
+        self
+    }
+
+    /// Overrides the options with bytes written as they are, which should be a multiple of
+    /// 4 bytes long.
+    pub fn raw_options(mut self, options: &[u8]) -> Self {
+        self.options = options.to_vec();
+        self
+    }
+
+    /// Overrides the version, 4 by default.
+    pub fn version(mut self, version: u8) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Overrides the protocol of the payload.
+    pub fn protocol(mut self, protocol: u8) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Overrides the total length, which is otherwise that of the packet.
+    pub fn total_length(mut self, total_length: u16) -> Self {
+        self.total_length = Some(total_length);
+        self
+    }
+
+    /// Overrides the header checksum, which is otherwise computed.
+    pub fn checksum(mut self, checksum: u16) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    pub fn icmp(self, icmp: IcmpBuilder) -> IcmpBuilder {
+        IcmpBuilder {
+            ip: Some(self),
+            ..icmp
+        }
+    }
+
+    /// Prepends the header, and those of the outer layers, to `payload` in its headroom.
+    pub fn build(&self, protocol: u8, payload: PacketBuf) -> PacketBuf {
+        let packet = self.prepend_header(protocol, payload);
+        match &self.link {
+            Some(link) => link.build(ETHERTYPE_IP, packet),
+            None => packet,
+        }
+    }
+
+    fn prepend_header(&self, protocol: u8, mut payload: PacketBuf) -> PacketBuf {
+        let header_length = mem::size_of::<IpHeaderWithoutOptions>() + self.options.len();
+        let total_length = (header_length + payload.len()) as u16;
+        let header = payload.prepend(header_length);
+        header[mem::size_of::<IpHeaderWithoutOptions>()..].copy_from_slice(&self.options);
+        {
+            let (ip_header, _) = IpHeaderWithoutOptions::mapped_mut(header).unwrap();
+            ip_header.set_version_ihl(self.version, (header_length / 4) as u8);
+            ip_header.set_type_of_service(self.type_of_service);
+            ip_header.set_total_length(self.total_length.unwrap_or(total_length));
+            ip_header.set_identification(self.identification);
+            ip_header.set_flags_fragment_offset(self.flags_fragment_offset);
+            ip_header.set_ttl(self.ttl);
+            ip_header.set_protocol(self.protocol.unwrap_or(protocol));
+            ip_header.set_checksum(0);
+            ip_header.set_src_addr(self.src);
+            ip_header.set_dst_addr(self.dst);
+        }
+
+        let checksum = self.checksum.unwrap_or_else(|| utils::checksum(header));
+        let (ip_header, _) = IpHeaderWithoutOptions::mapped_mut(header).unwrap();
+        ip_header.set_checksum(checksum);
+        payload
+    }
+}
+
+/// An ICMP message.
+#[derive(Debug, Clone)]
+pub struct IcmpBuilder {
+    ip: Option<Ipv4Builder>,
+    icmp_type: u8,
+    code: u8,
+    rest_of_header: [u8; 4],
+    checksum: Option<u16>,
+}
+
+impl IcmpBuilder {
+    pub fn new(icmp_type: u8, code: u8) -> Self {
+        IcmpBuilder {
+            ip: None,
+            icmp_type,
+            code,
+            rest_of_header: [0; 4],
+            checksum: None,
+        }
+    }
+
+    pub fn echo_request(identifier: u16, sequence_id: u16) -> Self {
+        IcmpBuilder::new(ECHO_TYPE, ECHO_CODE).echo(identifier, sequence_id)
+    }
+
+    pub fn echo_reply(identifier: u16, sequence_id: u16) -> Self {
+        IcmpBuilder::new(ECHO_REPLY_TYPE, ECHO_CODE).echo(identifier, sequence_id)
+    }
+
+    fn echo(self, identifier: u16, sequence_id: u16) -> Self {
+        let echo = EchoPacketWithoutData::new(identifier, sequence_id);
+        let mut rest_of_header = [0; 4];
+        rest_of_header.copy_from_slice(echo.as_bytes());
+        self.rest_of_header(rest_of_header)
+    }
+
+    /// The four bytes following the checksum, whose meaning depends on the type.
+    pub fn rest_of_header(mut self, rest_of_header: [u8; 4]) -> Self {
+        self.rest_of_header = rest_of_header;
+        self
+    }
+
+    /// Overrides the checksum, which is otherwise computed.
+    pub fn checksum(mut self, checksum: u16) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// Prepends the header, and those of the outer layers, to `data` in its headroom.
+    pub fn build(&self, mut data: PacketBuf) -> PacketBuf {
+        {
+            let header = data.prepend(mem::size_of::<IcmpHeader>() + self.rest_of_header.len());
+            let (icmp_header, rest) = IcmpHeader::mapped_mut(header).unwrap();
+            icmp_header.set_icmp_type(self.icmp_type);
+            icmp_header.set_code(self.code);
+            icmp_header.set_checksum(0);
+            rest.copy_from_slice(&self.rest_of_header);
+        }
+        let checksum = self.checksum.unwrap_or_else(|| utils::checksum(&data));
+        IcmpHeader::mapped_mut(&mut data)
+            .unwrap()
+            .0
+            .set_checksum(checksum);
+
+        match &self.ip {
+            Some(ip) => ip.build(ICMP_PROTOCOL_NUMBER, data),
+            None => data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::arp::header::ARPOP_REQUEST;
+    use crate::builder::*;
+    use crate::ether::header::{parse_vlan_tags, VlanTag, ETHERTYPE_QINQ};
+    use crate::ip::options::{parse_options, Route};
+    use crate::ip::{IpDriver, IpParse, IpReply};
+    use crate::Destination;
+
+    const SRC_MAC: MacAddress = MacAddress {
+        address: [0x02, 0, 0, 0, 0, 1],
+    };
+    const DST_MAC: MacAddress = MacAddress {
+        address: [0x02, 0, 0, 0, 0, 2],
+    };
+
+    fn addr(n: u8) -> IpAddress {
+        IpAddress::new_be_bytes([10, 0, 0, n])
+    }
+
+    #[test]
+    fn test_echo_request() {
+        let frame = EthernetBuilder::new(SRC_MAC, DST_MAC)
+            .ipv4(Ipv4Builder::new(addr(1), addr(2)).ttl(7))
+            .icmp(IcmpBuilder::echo_request(0x1234, 5))
+            .build(PacketBuf::from_payload(b"ping"));
+
+        let (mac_header, packet) = MacHeader::mapped(&frame).unwrap();
+        assert_eq!(mac_header.dst_mac(), DST_MAC);
+        assert_eq!(mac_header.src_mac(), SRC_MAC);
+        assert_eq!(mac_header.ether_type(), ETHERTYPE_IP);
+        // Padded to the minimum frame length.
+        assert_eq!(frame.len(), ETH_ZLEN as usize);
+
+        let (ip_header, _) = IpHeaderWithoutOptions::mapped(packet).unwrap();
+        assert!(ip_header.is_valid(packet));
+        assert_eq!(ip_header.total_length(), 20 + 8 + 4);
+        assert_eq!(ip_header.ttl(), 7);
+        assert_eq!(ip_header.protocol(), ICMP_PROTOCOL_NUMBER);
+        let icmp = &packet[20..32];
+        assert_eq!(utils::checksum(icmp), 0);
+        assert_eq!(&icmp[4..], &[0x12, 0x34, 0, 5, b'p', b'i', b'n', b'g']);
+
+        // The driver answers what the builder makes.
+        let mut driver = IpDriver::new(addr(2));
+        match driver.parse(&packet[..32], Destination::ToMyself) {
+            Ok(IpReply::Reply { dst, .. }) => assert_eq!(dst, addr(1)),
+            _ => panic!("no reply to the echo request"),
+        }
+    }
+
+    #[test]
+    fn test_vlan_arp() {
+        let vlan = VlanTags::double(VlanTag::service(10), VlanTag::new(20));
+        let frame = EthernetBuilder::new(SRC_MAC, DST_MAC)
+            .vlan(vlan)
+            .arp(
+                ArpBuilder::new(ARPOP_REQUEST)
+                    .sender(SRC_MAC, addr(1))
+                    .target(DST_MAC, addr(2)),
+            )
+            .build();
+
+        let (mac_header, rest) = MacHeader::mapped(&frame).unwrap();
+        assert_eq!(mac_header.ether_type(), ETHERTYPE_QINQ);
+        let (tags, ether_type, arp) = parse_vlan_tags(mac_header.ether_type(), rest).unwrap();
+        assert_eq!(tags, vlan);
+        assert_eq!(ether_type, ETHERTYPE_ARP);
+        assert_eq!(frame.len(), ETH_ZLEN as usize + vlan.len());
+
+        let (header, payload) = ArpHeader::mapped(arp).unwrap();
+        assert_eq!(header.op_code(), ARPOP_REQUEST);
+        assert_eq!((header.hard_addr_len(), header.proto_addr_len()), (6, 4));
+        let (payload, _) = EtherIpPayload::mapped(payload).unwrap();
+        assert_eq!(payload.sender_ip_addr(), addr(1));
+        assert_eq!(payload.target_mac_addr(), DST_MAC);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let packet = Ipv4Builder::new(addr(1), addr(2))
+            .total_length(1000)
+            .checksum(0xBEEF)
+            .icmp(IcmpBuilder::echo_request(1, 1).checksum(0))
+            .build(PacketBuf::new());
+        let (header, icmp) = IpHeaderWithoutOptions::mapped(&packet).unwrap();
+        assert_eq!(header.total_length(), 1000);
+        assert_eq!(header.checksum(), 0xBEEF);
+        assert!(!header.is_valid(&packet));
+        assert_eq!(IcmpHeader::mapped(icmp).unwrap().0.checksum(), 0);
+
+        let runt = EthernetBuilder::new(SRC_MAC, DST_MAC)
+            .ether_type(0x88B5)
+            .padding(false)
+            .ipv4(Ipv4Builder::new(addr(1), addr(2)))
+            .build(0xFD, PacketBuf::new());
+        assert_eq!(runt.len(), 14 + 20);
+        assert_eq!(&runt[12..14], &[0x88, 0xB5]);
+    }
+
+    #[test]
+    fn test_options() {
+        let options = [IpOption::RecordRoute(Route::with_capacity(3))];
+        let packet = Ipv4Builder::new(addr(1), addr(2))
+            .options(&options)
+            .build(0xFD, PacketBuf::from_payload(b"data"));
+        let (header, _) = IpHeaderWithoutOptions::mapped(&packet).unwrap();
+        assert_eq!(header.ihl(), 9);
+        assert_eq!(header.total_length(), 40);
+        assert!(header.is_valid(&packet));
+        assert_eq!(parse_options(&packet[20..36]).unwrap(), options);
+        assert_eq!(&packet[36..], b"data");
+    }
+}
@@ -0,0 +1,257 @@
+use super::MacAddress;
+use crate::packet::PacketBuf;
+use crate::socket::filter::BpfInstruction;
+use crate::socket::interface::Interface;
+use crate::socket::ring::{PacketRing, RingConfig};
+use crate::socket::tap::Tap;
+use crate::socket::{FrameInfo, Socket};
+use libc::ETH_DATA_LEN;
+use std::io;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// A link-layer port that `EthernetDriver` sends and receives whole frames through.
+///
+/// The driver clones the device to hand it to the receiving side,
+/// so clones must refer to the same underlying port.
+pub trait LinkDevice: Clone + Send + Sync + 'static {
+    /// Transmits one complete Ethernet frame, returning the number of bytes sent.
+    fn send(&self, frame: &[u8]) -> io::Result<usize>;
+
+    /// Receives one Ethernet frame with what the device knows about it,
+    /// or arranges for the task to be woken when one arrives.
+    fn poll_recv(&self, cx: &mut Context) -> Poll<io::Result<(PacketBuf, FrameInfo)>>;
+
+    /// The largest payload, excluding the Ethernet header, that the link can carry.
+    fn mtu(&self) -> usize;
+
+    fn hardware_address(&self) -> MacAddress;
+
+    /// Installs a classic BPF program deciding which frames reach `poll_recv`.
+    /// Devices without kernel-side filtering refuse it.
+    fn attach_filter(&self, _program: &[BpfInstruction]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the device does not support filters",
+        ))
+    }
+
+    /// Has the link receive the frames sent to the multicast `group`.
+    /// Devices which cannot subscribe refuse it.
+    fn join_multicast(&self, _group: MacAddress) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the device does not support multicast membership",
+        ))
+    }
+
+    fn leave_multicast(&self, _group: MacAddress) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the device does not support multicast membership",
+        ))
+    }
+}
+
+/// `LinkDevice` backed by an `AF_PACKET` raw socket on an existing interface.
+/// Receiving relies on `reactor::block_on`.
+#[derive(Debug, Clone)]
+pub struct RawSocketDevice {
+    socket: Arc<Socket>,
+    mac_addr: MacAddress,
+    mtu: usize,
+}
+
+impl RawSocketDevice {
+    pub fn new(socket: Socket, mac_addr: MacAddress) -> Self {
+        RawSocketDevice {
+            socket: Arc::new(socket),
+            mac_addr,
+            mtu: ETH_DATA_LEN as _,
+        }
+    }
+
+    /// Uses the hardware address and MTU of `interface`, which `socket` should be bound to.
+    pub fn with_interface(socket: Socket, interface: &Interface) -> Self {
+        RawSocketDevice {
+            socket: Arc::new(socket),
+            mac_addr: MacAddress::new(interface.hardware_address),
+            mtu: interface.mtu,
+        }
+    }
+
+    /// Opens a raw socket bound to `iface_name`, taking over its hardware address and MTU.
+    /// The socket reports offloaded checksums and super-frames, so the interface may keep
+    /// its offload settings.
+    pub fn open(iface_name: &str) -> io::Result<Self> {
+        RawSocketDevice::open_with(iface_name, |_| Ok(()))
+    }
+
+    /// Like `open`, letting `configure` set the socket up further, e.g. enable the
+    /// promiscuous mode, before the device shares it.
+    pub fn open_with<F>(iface_name: &str, configure: F) -> io::Result<Self>
+    where
+        F: FnOnce(&mut Socket) -> io::Result<()>,
+    {
+        let interface = Interface::query(iface_name)?;
+        let mut socket = Socket::open_raw_socket()?;
+        socket.enable_offload_info()?;
+        socket.limit_interface(iface_name)?;
+        configure(&mut socket)?;
+
+        Ok(RawSocketDevice::with_interface(socket, &interface))
+    }
+
+    pub fn socket(&self) -> &Socket {
+        &self.socket
+    }
+}
+
+impl LinkDevice for RawSocketDevice {
+    fn send(&self, frame: &[u8]) -> io::Result<usize> {
+        self.socket.send(frame)
+    }
+
+    fn poll_recv(&self, cx: &mut Context) -> Poll<io::Result<(PacketBuf, FrameInfo)>> {
+        self.socket.poll_recv(cx)
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn hardware_address(&self) -> MacAddress {
+        self.mac_addr
+    }
+
+    fn attach_filter(&self, program: &[BpfInstruction]) -> io::Result<()> {
+        self.socket.attach_filter(program)
+    }
+
+    fn join_multicast(&self, group: MacAddress) -> io::Result<()> {
+        self.socket.join_multicast(group.address)
+    }
+
+    fn leave_multicast(&self, group: MacAddress) -> io::Result<()> {
+        self.socket.leave_multicast(group.address)
+    }
+}
+
+/// `LinkDevice` backed by a dedicated TAP interface. Receiving relies on `reactor::block_on`.
+#[derive(Debug, Clone)]
+pub struct TapDevice {
+    tap: Arc<Tap>,
+    mac_addr: MacAddress,
+    mtu: usize,
+}
+
+impl TapDevice {
+    pub fn new(tap: Tap, mac_addr: MacAddress) -> Self {
+        TapDevice {
+            tap: Arc::new(tap),
+            mac_addr,
+            mtu: ETH_DATA_LEN as _,
+        }
+    }
+
+    pub fn tap(&self) -> &Tap {
+        &self.tap
+    }
+}
+
+impl LinkDevice for TapDevice {
+    fn send(&self, frame: &[u8]) -> io::Result<usize> {
+        self.tap.send(frame)
+    }
+
+    fn poll_recv(&self, cx: &mut Context) -> Poll<io::Result<(PacketBuf, FrameInfo)>> {
+        self.tap
+            .poll_recv(cx)
+            .map_ok(|frame| (frame, FrameInfo::default()))
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn hardware_address(&self) -> MacAddress {
+        self.mac_addr
+    }
+
+    /// The kernel hands the TAP every frame routed to it, so there is nothing to join.
+    fn join_multicast(&self, _group: MacAddress) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn leave_multicast(&self, _group: MacAddress) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `LinkDevice` exchanging frames through the `PACKET_MMAP` rings of a raw socket.
+/// Receiving relies on `reactor::block_on`.
+#[derive(Clone)]
+pub struct RingDevice {
+    ring: Arc<PacketRing>,
+    mac_addr: MacAddress,
+    mtu: usize,
+}
+
+impl RingDevice {
+    pub fn with_interface(ring: PacketRing, interface: &Interface) -> Self {
+        RingDevice {
+            ring: Arc::new(ring),
+            mac_addr: MacAddress::new(interface.hardware_address),
+            mtu: interface.mtu,
+        }
+    }
+
+    /// Opens a raw socket bound to `iface_name` with rings of the given geometry,
+    /// taking over the interface's hardware address and MTU.
+    pub fn open(
+        iface_name: &str,
+        rx_config: RingConfig,
+        tx_config: RingConfig,
+    ) -> io::Result<Self> {
+        let interface = Interface::query(iface_name)?;
+        let mut socket = Socket::open_raw_socket()?;
+        socket.limit_interface(iface_name)?;
+        let ring = PacketRing::new(socket, rx_config, tx_config)?;
+
+        Ok(RingDevice::with_interface(ring, &interface))
+    }
+
+    pub fn ring(&self) -> &PacketRing {
+        &self.ring
+    }
+}
+
+impl LinkDevice for RingDevice {
+    fn send(&self, frame: &[u8]) -> io::Result<usize> {
+        self.ring.send(frame)
+    }
+
+    fn poll_recv(&self, cx: &mut Context) -> Poll<io::Result<(PacketBuf, FrameInfo)>> {
+        self.ring.poll_recv(cx)
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn hardware_address(&self) -> MacAddress {
+        self.mac_addr
+    }
+
+    fn attach_filter(&self, program: &[BpfInstruction]) -> io::Result<()> {
+        self.ring.socket().attach_filter(program)
+    }
+
+    fn join_multicast(&self, group: MacAddress) -> io::Result<()> {
+        self.ring.socket().join_multicast(group.address)
+    }
+
+    fn leave_multicast(&self, group: MacAddress) -> io::Result<()> {
+        self.ring.socket().leave_multicast(group.address)
+    }
+}
@@ -1,122 +1,324 @@
+use super::device::{LinkDevice, RawSocketDevice};
+use super::error::EtherError;
+use super::header::VlanTags;
+use super::offload;
 use super::{header, MacAddress, BROADCAST_MAC_ADDR};
 use crate::arp::{ArpReply, ArpResolve, ResolveResult};
+use crate::builder::EthernetBuilder;
 
 use crate::ether::header::MacHeader;
-use crate::ip::{IpAddress, IpParse, IpReply};
-use crate::socket::Socket;
+use crate::ip::{InterfaceAddress, IpAddress, IpParse, IpReply};
+use crate::packet::PacketBuf;
+use crate::reactor::Interval;
+use crate::socket::filter::{self, BpfInstruction};
+use crate::socket::{FrameInfo, PacketType};
 use crate::Destination;
 
-use futures::channel::mpsc::channel;
 use futures::future;
 use futures::prelude::*;
-use libc::ETH_ZLEN;
+use futures::stream;
 use map_struct::Mappable;
 use std::future::Future;
+use std::io;
 use std::pin::Pin;
+use std::task::Poll;
+use std::time::{Duration, Instant};
 
-const N_CHANNEL_BUFFER: usize = 256;
+mod registry;
+pub use registry::{EtherTypeHandler, EtherTypeRegistry, FrameSender};
 
-pub struct EthernetDriver<T, S>
+/// Frames whose handling is still waiting, e.g. for ARP resolution of the reply destination.
+const N_PENDING_FRAMES: usize = 64;
+
+/// How often the timers of ARP and of the IP layer run, e.g. to give up unanswered requests
+/// and to expire incomplete datagrams.
+const TIMER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What the receive stream has to handle.
+enum Event {
+    Frame(PacketBuf, FrameInfo),
+    Tick,
+    RegistryChanged,
+}
+
+pub struct EthernetDriver<T, S, D>
 where
     T: ArpResolve<LinkAddress = MacAddress, InternetAddress = IpAddress> + Sync + Send,
     S: IpParse + Sync + Send,
+    D: LinkDevice,
 {
     promisc: bool,
+    /// Ethernet multicast groups joined on the link.
+    multicast_groups: Vec<MacAddress>,
     arp_resolver: T,
     ip_parser: S,
-    device: EtherDevice,
+    registry: EtherTypeRegistry,
+    /// Whether the default filter is attached, and so must follow the frames handled.
+    filter_attached: bool,
+    device: EtherDevice<D>,
 }
 
 #[derive(Clone)]
-struct EtherDevice {
+struct EtherDevice<D: LinkDevice> {
     mac_addr: MacAddress,
-    socket: Socket,
+    /// Tags of the VLAN the driver is bound to, put on every frame sent.
+    vlan: VlanTags,
+    link: D,
 }
 
-impl EtherDevice {
-    fn send(&self, data: &[u8]) {
-        // TODO check MTU
-        unsafe {
-            let res = self.socket.send(data);
-            if res as usize != data.len() {
-                use crate::utils;
-                utils::show_error_text();
-            }
+impl<D: LinkDevice> EtherDevice<D> {
+    fn send(&self, data: &[u8]) -> Result<(), EtherError> {
+        let (h, d) = MacHeader::mapped(data).ok_or(EtherError::InvalidFrame)?;
+        // The tags do not count against the MTU.
+        let (_, _, payload) =
+            header::parse_vlan_tags(h.ether_type(), d).ok_or(EtherError::InvalidFrame)?;
+        let size = payload.len();
+        let mtu = self.link.mtu();
+        if size > mtu {
+            return Err(EtherError::FrameTooLarge { size, mtu });
+        }
+
+        let len = self.link.send(data).map_err(EtherError::IoError)?;
+        if len != data.len() {
+            return Err(EtherError::IoError(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "frame is partially sent",
+            )));
         }
+        Ok(())
     }
 
-    fn constract_ethernet_frame(&self, dst: MacAddress, ether_type: u16, data: &[u8]) -> Vec<u8> {
-        let mac_header = MacHeader {
-            dst_mac: dst,
-            src_mac: self.mac_addr,
-            ether_type: u16::to_be(ether_type),
-        };
+    fn send_or_report(&self, data: &[u8]) {
+        if let Err(err) = self.send(data) {
+            println!("- {}", err);
+        }
+    }
 
-        let mut result = mac_header.as_bytes().to_vec();
-        result.extend_from_slice(data);
+    /// Prepends the Ethernet header, with the VLAN tags if any, to `packet` in its headroom.
+    fn constract_ethernet_frame(
+        &self,
+        dst: MacAddress,
+        ether_type: u16,
+        packet: PacketBuf,
+    ) -> PacketBuf {
+        EthernetBuilder::new(self.mac_addr, dst)
+            .vlan(self.vlan)
+            .build(ether_type, packet)
+    }
+}
 
-        if result.len() < ETH_ZLEN as _ {
-            result.resize(ETH_ZLEN as _, 0);
-        }
+impl<D: LinkDevice> FrameSender for EtherDevice<D> {
+    fn send_frame(
+        &self,
+        dst: MacAddress,
+        ether_type: u16,
+        payload: &[u8],
+    ) -> Result<(), EtherError> {
+        self.send(&self.constract_ethernet_frame(dst, ether_type, PacketBuf::from_payload(payload)))
+    }
 
-        result
+    fn mac_address(&self) -> MacAddress {
+        self.mac_addr
     }
 }
 
-impl<T, S> EthernetDriver<T, S>
+impl<T, S, D> EthernetDriver<T, S, D>
 where
     T: ArpResolve<LinkAddress = MacAddress, InternetAddress = IpAddress> + Sync + Send,
     S: IpParse + Sync + Send,
+    D: LinkDevice,
 {
-    pub fn new(mac_addr: MacAddress, ip_addr: IpAddress, promisc: bool, socket: Socket) -> Self {
+    pub fn new(ip_addr: IpAddress, promisc: bool, link: D) -> Self {
+        let mac_addr = link.hardware_address();
         EthernetDriver {
             promisc,
-            arp_resolver: T::new(mac_addr.clone(), ip_addr.clone()),
-            ip_parser: S::new(ip_addr.clone()),
-            device: EtherDevice { mac_addr, socket },
+            multicast_groups: Vec::new(),
+            arp_resolver: T::new(mac_addr, ip_addr),
+            ip_parser: S::new(ip_addr),
+            registry: EtherTypeRegistry::new(),
+            filter_attached: false,
+            device: EtherDevice {
+                mac_addr,
+                vlan: VlanTags::default(),
+                link,
+            },
         }
     }
 
-    pub fn send(&self, data: &[u8]) {
-        self.device.send(data);
+    /// Binds the driver to a VLAN: only the frames carrying `vlan` are handled and the frames
+    /// sent are tagged with it. Untagged frames are handled by an unbound driver only.
+    pub fn set_vlan(&mut self, vlan: VlanTags) {
+        self.device.vlan = vlan;
     }
 
-    pub fn recv<'a>(mut self) -> impl Stream<Item = ()> {
-        let (mut sender, receiver) = channel::<Vec<u8>>(N_CHANNEL_BUFFER);
-        let socket = self.device.socket.clone();
+    pub fn vlan(&self) -> VlanTags {
+        self.device.vlan
+    }
 
-        std::thread::spawn(move || loop {
-            let data = unsafe { socket.recv() };
+    pub fn link(&self) -> &D {
+        &self.device.link
+    }
 
-            sender.try_send(data).expect("The buffer is full");
-        });
+    pub fn send(&self, data: &[u8]) -> Result<(), EtherError> {
+        self.device.send(data)
+    }
 
-        receiver.then(move |data| {
-            let d = MacHeader::mapped(&data[..]);
-            if let Some((h, d)) = d {
-                self.analyze(h, d)
-            } else {
+    /// Sends `payload` as a frame of `ether_type`, from this driver and on its VLAN.
+    pub fn send_frame(
+        &self,
+        dst: MacAddress,
+        ether_type: u16,
+        payload: &[u8],
+    ) -> Result<(), EtherError> {
+        self.device.send_frame(dst, ether_type, payload)
+    }
+
+    /// Receives the frames sent to the Ethernet multicast `group`, classified as
+    /// `Destination::Multicast`. The default filter, if attached, is attached again to admit
+    /// them; should that fail, the group stays joined and the error is returned.
+    pub fn join_multicast(&mut self, group: MacAddress) -> Result<(), EtherError> {
+        if !group.is_multicast() || group == BROADCAST_MAC_ADDR {
+            return Err(EtherError::NotMulticastGroup);
+        }
+        if !self.multicast_groups.contains(&group) {
+            self.device
+                .link
+                .join_multicast(group)
+                .map_err(EtherError::IoError)?;
+            self.multicast_groups.push(group);
+            self.update_filter().map_err(EtherError::IoError)?;
+        }
+        Ok(())
+    }
+
+    pub fn leave_multicast(&mut self, group: MacAddress) -> Result<(), EtherError> {
+        if let Some(i) = self.multicast_groups.iter().position(|g| *g == group) {
+            self.device
+                .link
+                .leave_multicast(group)
+                .map_err(EtherError::IoError)?;
+            self.multicast_groups.remove(i);
+            self.update_filter().map_err(EtherError::IoError)?;
+        }
+        Ok(())
+    }
+
+    /// Joins the IPv4 multicast `group`, and the Ethernet group it is mapped to.
+    pub fn join_ipv4_multicast(&mut self, group: IpAddress) -> Result<(), EtherError> {
+        let mac_group =
+            MacAddress::from_ipv4_multicast(group).ok_or(EtherError::NotMulticastGroup)?;
+        self.join_multicast(mac_group)?;
+        self.ip_parser
+            .join_group(group)
+            .map_err(EtherError::IpError)
+    }
+
+    pub fn leave_ipv4_multicast(&mut self, group: IpAddress) -> Result<(), EtherError> {
+        let mac_group =
+            MacAddress::from_ipv4_multicast(group).ok_or(EtherError::NotMulticastGroup)?;
+        self.ip_parser.leave_group(group);
+        self.leave_multicast(mac_group)
+    }
+
+    pub fn multicast_groups(&self) -> &[MacAddress] {
+        &self.multicast_groups
+    }
+
+    /// The handlers of the ether types besides IPv4 and ARP. The returned handle stays
+    /// usable once `recv` has taken the driver, which then updates the attached filter
+    /// for the ether types registered.
+    pub fn registry(&self) -> EtherTypeRegistry {
+        self.registry.clone()
+    }
+
+    /// Handles the incoming frames. The stream ends when the link fails,
+    /// and dropping it stops receiving.
+    pub fn recv(mut self) -> impl Stream<Item = ()> {
+        let link = self.device.link.clone();
+        let registry = self.registry.clone();
+        let mut timer = Interval::new(TIMER_INTERVAL)
+            .map_err(|err| println!("- Cannot start the timer: {}", err))
+            .ok();
+
+        // The frames answering a pending analysis (e.g. ARP replies) arrive on this very stream,
+        // so the analyses must not be awaited one by one.
+        stream::poll_fn(move |cx| {
+            // The filter is updated before the frames it would have dropped are received.
+            if registry.poll_changed(cx).is_ready() {
+                return Poll::Ready(Some(Event::RegistryChanged));
+            }
+            match link.poll_recv(cx) {
+                Poll::Ready(Ok((data, info))) => {
+                    return Poll::Ready(Some(Event::Frame(data, info)))
+                }
+                Poll::Ready(Err(err)) => {
+                    println!("- {}", err);
+                    return Poll::Ready(None);
+                }
+                Poll::Pending => {}
+            }
+            match timer.as_ref().map(|timer| timer.poll_tick(cx)) {
+                Some(Poll::Ready(Ok(_))) => Poll::Ready(Some(Event::Tick)),
+                Some(Poll::Ready(Err(err))) => {
+                    println!("- The timer stopped: {}", err);
+                    timer = None;
+                    Poll::Pending
+                }
+                _ => Poll::Pending,
+            }
+        })
+        .map(|event| match event {
+            Event::Frame(data, info) => {
+                let frames = offload::resolve(data, info).unwrap_or_else(|err| {
+                    println!("- {}", err);
+                    offload::Resolved::none()
+                });
+                stream::iter(frames)
+                    .map(|(data, info)| Event::Frame(data, info))
+                    .left_stream()
+            }
+            event => stream::once(future::ready(event)).right_stream(),
+        })
+        .flatten()
+        .map(move |event| match event {
+            Event::Frame(mut data, mut info) => {
+                header::restore_vlan_tag(&mut data, &mut info);
+                let d = MacHeader::mapped(&data[..]);
+                if let Some((h, d)) = d {
+                    self.analyze(h, d, info)
+                } else {
+                    future::ready(()).boxed()
+                }
+            }
+            Event::Tick => self.run_timers(),
+            Event::RegistryChanged => {
+                if let Err(err) = self.update_filter() {
+                    println!("- Cannot update the packet filter: {}", err);
+                }
                 future::ready(()).boxed()
             }
         })
+        .buffer_unordered(N_PENDING_FRAMES)
     }
 
     pub fn resolve(
         &mut self,
         ip_addr: IpAddress,
     ) -> Pin<Box<dyn Future<Output = Option<MacAddress>> + Send>> {
-        match self.arp_resolver.resolve(ip_addr) {
+        let src = self.ip_parser.source_for(ip_addr);
+        match self.arp_resolver.resolve(ip_addr, src) {
             ResolveResult::Found(value) => future::ready(Some(value)).boxed(),
             ResolveResult::NotFound {
                 packet_to_send,
                 result,
             } => {
                 println!("- Asking {:?} by broadcasting.", ip_addr);
-                self.device.send(&self.device.constract_ethernet_frame(
-                    BROADCAST_MAC_ADDR,
-                    header::ETHERTYPE_ARP,
-                    &packet_to_send,
-                ));
+                self.device
+                    .send_or_report(&self.device.constract_ethernet_frame(
+                        BROADCAST_MAC_ADDR,
+                        header::ETHERTYPE_ARP,
+                        packet_to_send,
+                    ));
                 result
             }
         }
@@ -133,76 +335,276 @@ where
             }
             Ok(ArpReply::Nop) => {}
             Ok(ArpReply::Reply { dst, data }) => {
-                self.device.send(&self.device.constract_ethernet_frame(
-                    dst,
-                    header::ETHERTYPE_ARP,
-                    &data,
-                ));
+                self.device
+                    .send_or_report(&self.device.constract_ethernet_frame(
+                        dst,
+                        header::ETHERTYPE_ARP,
+                        data,
+                    ));
             }
         }
         future::ready(()).boxed()
     }
 
+    /// A filter admitting only the frames this driver would handle, including those of the
+    /// ether types registered so far.
+    pub fn default_filter(&self) -> Vec<BpfInstruction> {
+        let mut destinations = vec![self.device.mac_addr.address, BROADCAST_MAC_ADDR.address];
+        destinations.extend(self.multicast_groups.iter().map(|group| group.address));
+        let mut ether_types = vec![header::ETHERTYPE_IP, header::ETHERTYPE_ARP];
+        if !self.device.vlan.is_untagged() {
+            // The kernel may not remove the tags, or only the outer one, before filtering.
+            ether_types.extend_from_slice(&[header::ETHERTYPE_VLAN, header::ETHERTYPE_QINQ]);
+        }
+        ether_types.extend(self.registry.ether_types());
+        filter::ether_filter(
+            // Too many groups or ether types for the filter are left to `classify` and
+            // the registry.
+            if self.promisc || destinations.len() > filter::MAX_DESTINATIONS {
+                None
+            } else {
+                Some(&destinations)
+            },
+            if ether_types.len() > filter::MAX_ETHER_TYPES {
+                None
+            } else {
+                Some(&ether_types)
+            },
+        )
+        .unwrap_or_else(filter::accept_all)
+    }
+
+    /// Lets the link drop the frames this driver would ignore before they are received.
+    /// The filter is attached again whenever the frames handled change.
+    pub fn attach_default_filter(&mut self) -> io::Result<()> {
+        self.device.link.attach_filter(&self.default_filter())?;
+        self.filter_attached = true;
+        Ok(())
+    }
+
+    /// Attaches the default filter again, if attached, after the frames handled changed.
+    fn update_filter(&self) -> io::Result<()> {
+        if !self.filter_attached {
+            return Ok(());
+        }
+        self.device.link.attach_filter(&self.default_filter())
+    }
+
+    pub fn ip_parser_mut(&mut self) -> &mut S {
+        &mut self.ip_parser
+    }
+
+    /// Owns `addr` too, announcing it on the link so that the caches of the neighbors
+    /// pointing it to another host are updated. The address is owned even if the
+    /// announcement cannot be sent, which is only reported.
+    pub fn add_ipv4_address(&mut self, addr: InterfaceAddress) -> Result<(), EtherError> {
+        self.ip_parser
+            .add_address(addr)
+            .map_err(EtherError::IpError)?;
+        self.arp_resolver.add_address(addr.addr);
+        self.announce(addr.addr);
+        Ok(())
+    }
+
+    /// Gives up `addr`, returning it with its prefix. ARP has no way to withdraw an address,
+    /// so the address now used on its subnet is announced in its place, which keeps the
+    /// neighbors talking to this host.
+    pub fn remove_ipv4_address(&mut self, addr: IpAddress) -> Option<InterfaceAddress> {
+        self.arp_resolver.remove_address(addr);
+        let removed = self.ip_parser.remove_address(addr)?;
+        let remaining = self.ip_parser.source_for(addr);
+        if remaining != IpAddress::new_be_bytes([0; 4]) {
+            self.announce(remaining);
+        }
+        Some(removed)
+    }
+
+    fn announce(&self, addr: IpAddress) {
+        println!("- Announcing {:?} by broadcasting.", addr);
+        self.device
+            .send_or_report(&self.device.constract_ethernet_frame(
+                BROADCAST_MAC_ADDR,
+                header::ETHERTYPE_ARP,
+                self.arp_resolver.announce(addr),
+            ));
+    }
+
+    pub fn send_ipv4(
+        &mut self,
+        protocol: u8,
+        dst: IpAddress,
+        payload: PacketBuf,
+    ) -> Pin<Box<dyn Future<Output = Option<()>> + Send>> {
+        let packet = self.ip_parser.construct_packet(protocol, dst, payload);
+        self.send_ip_packet(dst, packet)
+    }
+
+    fn send_ip_packet(
+        &mut self,
+        dst: IpAddress,
+        packet: PacketBuf,
+    ) -> Pin<Box<dyn Future<Output = Option<()>> + Send>> {
+        let fragments = match self.ip_parser.fragment(dst, packet, self.device.link.mtu()) {
+            Ok(fragments) => fragments,
+            Err(err) => {
+                println!("- {}", err);
+                return future::ready(None).boxed();
+            }
+        };
+        let sender = self.device.clone();
+        // Broadcasts and multicast groups map to Ethernet ones without ARP.
+        let resolved = match MacAddress::from_ipv4_multicast(dst) {
+            Some(group) => future::ready(Some(group)).boxed(),
+            None if self.ip_parser.is_broadcast(dst) => {
+                future::ready(Some(BROADCAST_MAC_ADDR)).boxed()
+            }
+            None => {
+                let next_hop = self.ip_parser.next_hop(dst);
+                println!("- Resolving IP Address {:?}", next_hop);
+                self.resolve(next_hop)
+            }
+        };
+        resolved
+            .map(move |result| {
+                let mac_addr = result?;
+                fragments.into_iter().try_for_each(|fragment| {
+                    sender
+                        .send(&sender.constract_ethernet_frame(
+                            mac_addr,
+                            header::ETHERTYPE_IP,
+                            fragment,
+                        ))
+                        .map_err(|err| println!("- {}", err))
+                        .ok()
+                })
+            })
+            .boxed()
+    }
+
     fn analyze_ipv4(
         &mut self,
         data: &[u8],
         frame_dst: Destination,
     ) -> Pin<Box<dyn Future<Output = ()>>> {
-        match self.ip_parser.parse(data, frame_dst) {
+        let reply = match self.ip_parser.parse(data, frame_dst) {
             Err(err) => {
                 println!("- {}", err);
                 future::ready(()).boxed()
             }
             Ok(IpReply::Nop) => future::ready(()).boxed(),
-            Ok(IpReply::Reply { dst, data }) => {
-                let sender = self.device.clone();
-                println!("- Resolving IP Address {:?} for ICMP Reply", dst);
-                self.resolve(dst)
-                    .map(move |result| {
-                        if let Some(mac_addr) = result {
-                            println!("- ARP Resolving succeeded for ICMP Reply",);
-                            sender.send(&sender.constract_ethernet_frame(
-                                mac_addr,
-                                header::ETHERTYPE_ARP,
-                                &data,
-                            ));
-                        } else {
-                            println!("- ARP Resolving failed for ICMP Reply",);
-                        }
-                    })
-                    .boxed()
-            }
+            Ok(IpReply::Reply { dst, data }) => self
+                .send_ip_packet(dst, data)
+                .map(move |result| {
+                    if result.is_some() {
+                        println!("- ARP Resolving succeeded for the reply to {:?}", dst);
+                    } else {
+                        println!("- ARP Resolving failed for the reply to {:?}", dst);
+                    }
+                })
+                .boxed(),
+        };
+
+        future::join(reply, self.send_pending())
+            .map(|_| ())
+            .boxed_local()
+    }
+
+    /// Sends the packets the IP layer left pending.
+    fn send_pending(&mut self) -> Pin<Box<dyn Future<Output = ()>>> {
+        let pending: Vec<_> = self
+            .ip_parser
+            .take_pending()
+            .into_iter()
+            .filter_map(|pending| match pending {
+                IpReply::Reply { dst, data } => Some(self.send_ip_packet(dst, data)),
+                IpReply::Nop => None,
+            })
+            .collect();
+        if pending.is_empty() {
+            return future::ready(()).boxed();
         }
+        future::join_all(pending).map(|_| ()).boxed()
     }
 
-    fn unknown_type(&self, ether_type: u16) -> Pin<Box<dyn Future<Output = ()>>> {
-        println!("Unknown ethertype: {:02X}", ether_type);
+    fn run_timers(&mut self) -> Pin<Box<dyn Future<Output = ()>>> {
+        let now = Instant::now();
+        // The analyses waiting for an ARP reply which will not come must give up their slots.
+        self.arp_resolver.expire_requests(now);
+        self.ip_parser.poll_timers(now);
+        self.send_pending()
+    }
+
+    fn analyze_other(
+        &self,
+        ether_type: u16,
+        data: &[u8],
+        src: MacAddress,
+        frame_dst: Destination,
+    ) -> Pin<Box<dyn Future<Output = ()>>> {
+        self.registry
+            .dispatch(ether_type, data, src, frame_dst, &self.device);
         future::ready(()).boxed()
     }
 
+    fn classify(&self, mac_header: &header::MacHeader, info: FrameInfo) -> Destination {
+        match info.packet_type {
+            Some(PacketType::Outgoing) => Destination::Outgoing,
+            Some(PacketType::Broadcast) => Destination::Broadcast,
+            // The kernel only knows the hardware address of the interface,
+            // which may differ from the one of this driver, and the groups joined by any socket.
+            _ if mac_header.dst_mac() == self.device.mac_addr => Destination::ToMyself,
+            _ if mac_header.dst_mac() == BROADCAST_MAC_ADDR => Destination::Broadcast,
+            _ if self.multicast_groups.contains(&mac_header.dst_mac()) => Destination::Multicast,
+            _ => Destination::Promisc,
+        }
+    }
+
     fn analyze(
         &mut self,
         mac_header: &header::MacHeader,
         data: &[u8],
+        info: FrameInfo,
     ) -> Pin<Box<dyn Future<Output = ()>>> {
-        let frame_dst = if mac_header.dst_mac == self.device.mac_addr {
-            Destination::ToMyself
-        } else if mac_header.dst_mac == BROADCAST_MAC_ADDR {
-            Destination::Broadcast
-        } else {
-            Destination::Promisc
-        };
+        let frame_dst = self.classify(mac_header, info);
 
-        if !self.promisc && frame_dst == Destination::Promisc {
+        if frame_dst == Destination::Outgoing
+            || (!self.promisc && frame_dst == Destination::Promisc)
+        {
             return future::ready(()).boxed();
         }
 
-        let ether_type = u16::from_be(mac_header.ether_type);
+        let (vlan, ether_type, data) = match header::parse_vlan_tags(mac_header.ether_type(), data)
+        {
+            Some(parsed) => parsed,
+            None => {
+                println!("- {}", EtherError::InvalidFrame);
+                return future::ready(()).boxed();
+            }
+        };
+        if !vlan.same_vlan(&self.device.vlan) {
+            return future::ready(()).boxed();
+        }
 
         match ether_type {
             header::ETHERTYPE_IP => self.analyze_ipv4(data, frame_dst),
             header::ETHERTYPE_ARP => self.analyze_arp(data, frame_dst),
-            _ => self.unknown_type(ether_type),
+            _ => self.analyze_other(ether_type, data, mac_header.src_mac(), frame_dst),
         }
     }
 }
+
+impl<T, S> EthernetDriver<T, S, RawSocketDevice>
+where
+    T: ArpResolve<LinkAddress = MacAddress, InternetAddress = IpAddress> + Sync + Send,
+    S: IpParse + Sync + Send,
+{
+    /// Builds a driver on a raw socket bound to `iface_name`, using the interface's own
+    /// hardware address and MTU.
+    pub fn from_interface(iface_name: &str, ip_addr: IpAddress, promisc: bool) -> io::Result<Self> {
+        Ok(Self::new(
+            ip_addr,
+            promisc,
+            RawSocketDevice::open(iface_name)?,
+        ))
+    }
+}
@@ -0,0 +1,271 @@
+use crate::ether::error::EtherError;
+use crate::ether::MacAddress;
+use crate::Destination;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+/// Sends frames on behalf of a handler, from the address and on the VLAN of the driver.
+pub trait FrameSender {
+    fn send_frame(
+        &self,
+        dst: MacAddress,
+        ether_type: u16,
+        payload: &[u8],
+    ) -> Result<(), EtherError>;
+
+    fn mac_address(&self) -> MacAddress;
+}
+
+/// Handles the frames of one ether type. `payload` follows the ether type,
+/// and `src` is the sender of the frame.
+pub trait EtherTypeHandler: Send {
+    fn handle(
+        &mut self,
+        payload: &[u8],
+        src: MacAddress,
+        frame_dst: Destination,
+        sender: &dyn FrameSender,
+    );
+}
+
+impl<F> EtherTypeHandler for F
+where
+    F: FnMut(&[u8], MacAddress, Destination, &dyn FrameSender) + Send,
+{
+    fn handle(
+        &mut self,
+        payload: &[u8],
+        src: MacAddress,
+        frame_dst: Destination,
+        sender: &dyn FrameSender,
+    ) {
+        self(payload, src, frame_dst, sender)
+    }
+}
+
+type SharedHandler = Arc<Mutex<Box<dyn EtherTypeHandler>>>;
+
+/// Locks `mutex` even if a handler panicked while holding it, so that one panic does not
+/// make every later call panic too.
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Default)]
+struct Registry {
+    handlers: HashMap<u16, SharedHandler>,
+    unhandled: HashMap<u16, u64>,
+    /// Whether the set of ether types changed since the driver last looked.
+    changed: bool,
+    waker: Option<Waker>,
+}
+
+impl Registry {
+    fn set_changed(&mut self) {
+        self.changed = true;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Handlers of the ether types other than IPv4 and ARP, which the driver handles itself.
+///
+/// Clones share the handlers, so they can be changed while the driver is receiving.
+#[derive(Clone, Default)]
+pub struct EtherTypeRegistry {
+    inner: Arc<Mutex<Registry>>,
+}
+
+impl EtherTypeRegistry {
+    pub fn new() -> Self {
+        EtherTypeRegistry::default()
+    }
+
+    /// Installs `handler` for `ether_type`, returning whether one was replaced.
+    pub fn register<H: EtherTypeHandler + 'static>(&self, ether_type: u16, handler: H) -> bool {
+        let mut registry = lock(&self.inner);
+        let replaced = registry
+            .handlers
+            .insert(ether_type, Arc::new(Mutex::new(Box::new(handler))))
+            .is_some();
+        if !replaced {
+            registry.set_changed();
+        }
+        replaced
+    }
+
+    /// Removes the handler of `ether_type`, returning whether there was one.
+    pub fn unregister(&self, ether_type: u16) -> bool {
+        let mut registry = lock(&self.inner);
+        let removed = registry.handlers.remove(&ether_type).is_some();
+        if removed {
+            registry.set_changed();
+        }
+        removed
+    }
+
+    /// The ether types with a handler, in ascending order.
+    pub fn ether_types(&self) -> Vec<u16> {
+        let mut ether_types: Vec<_> = lock(&self.inner).handlers.keys().cloned().collect();
+        ether_types.sort_unstable();
+        ether_types
+    }
+
+    /// How many frames of `ether_type` were dropped for lack of a handler.
+    pub fn unhandled_count(&self, ether_type: u16) -> u64 {
+        lock(&self.inner)
+            .unhandled
+            .get(&ether_type)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// The numbers of dropped frames by ether type.
+    pub fn unhandled_counts(&self) -> HashMap<u16, u64> {
+        lock(&self.inner).unhandled.clone()
+    }
+
+    /// Completes once ether types were registered or unregistered since the last call,
+    /// so that the driver updates its filter.
+    pub(super) fn poll_changed(&self, cx: &mut Context) -> Poll<()> {
+        let mut registry = lock(&self.inner);
+        if registry.changed {
+            registry.changed = false;
+            Poll::Ready(())
+        } else {
+            registry.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Passes a frame to the handler of `ether_type`, or counts it as unhandled.
+    pub(super) fn dispatch(
+        &self,
+        ether_type: u16,
+        payload: &[u8],
+        src: MacAddress,
+        frame_dst: Destination,
+        sender: &dyn FrameSender,
+    ) {
+        let handler = {
+            let mut registry = lock(&self.inner);
+            match registry.handlers.get(&ether_type) {
+                Some(handler) => handler.clone(),
+                None => {
+                    *registry.unhandled.entry(ether_type).or_insert(0) += 1;
+                    return;
+                }
+            }
+        };
+        // The registry is unlocked, so that the handler may change it.
+        lock(&handler).handle(payload, src, frame_dst, sender);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ether::driver::registry::*;
+    use crate::ether::hub::fixtures::{host, run_until};
+    use crate::ether::hub::VirtualHub;
+    use crate::socket::filter;
+    use futures::channel::mpsc::unbounded;
+    use futures::prelude::*;
+
+    const ETHERTYPE_LAB: u16 = 0x88B5;
+    const ETHERTYPE_LLDP: u16 = 0x88CC;
+
+    #[test]
+    fn test_dispatch() {
+        let hub = VirtualHub::new();
+        let a = host(&hub, 1);
+        let b = host(&hub, 2);
+
+        // `a` echoes the lab frames back in upper case.
+        a.registry().register(
+            ETHERTYPE_LAB,
+            |payload: &[u8], src, frame_dst, sender: &dyn FrameSender| {
+                assert_eq!(frame_dst, Destination::ToMyself);
+                sender
+                    .send_frame(src, ETHERTYPE_LAB, &payload.to_ascii_uppercase())
+                    .unwrap();
+            },
+        );
+        let (replies, mut received) = unbounded();
+        let registry = b.registry();
+        registry.register(
+            ETHERTYPE_LAB,
+            move |payload: &[u8], src, _, _: &dyn FrameSender| {
+                replies
+                    .unbounded_send((src, payload[..5].to_vec()))
+                    .unwrap();
+            },
+        );
+        assert!(a
+            .default_filter()
+            .iter()
+            .any(|ins| ins.k == ETHERTYPE_LAB as u32));
+
+        let a_mac = MacAddress::new([0x02, 0, 0, 0, 0, 1]);
+        b.send_frame(a_mac, ETHERTYPE_LAB, b"hello").unwrap();
+        // Nobody handles LLDP, so the frame to `b` is only counted.
+        a.send_frame(
+            MacAddress::new([0x02, 0, 0, 0, 0, 2]),
+            ETHERTYPE_LLDP,
+            b"lldp",
+        )
+        .unwrap();
+
+        let reply = run_until(received.next(), vec![a, b]);
+        assert_eq!(reply, Some((a_mac, b"HELLO".to_vec())));
+        assert_eq!(registry.unhandled_count(ETHERTYPE_LLDP), 1);
+        assert_eq!(registry.unhandled_count(ETHERTYPE_LAB), 0);
+
+        assert!(registry.unregister(ETHERTYPE_LAB));
+        assert!(registry.ether_types().is_empty());
+    }
+
+    #[test]
+    fn test_register_after_attach() {
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let b = host(&hub, 2);
+        a.attach_default_filter().unwrap();
+
+        // The filter attached knows nothing of the lab frames registered afterwards.
+        let (received, mut receiver) = unbounded();
+        a.registry().register(
+            ETHERTYPE_LAB,
+            move |payload: &[u8], _, _, _: &dyn FrameSender| {
+                received.unbounded_send(payload[..5].to_vec()).unwrap();
+            },
+        );
+        b.send_frame(
+            MacAddress::new([0x02, 0, 0, 0, 0, 1]),
+            ETHERTYPE_LAB,
+            b"hello",
+        )
+        .unwrap();
+
+        let received = run_until(receiver.next(), vec![a, b]);
+        assert_eq!(received, Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_many_ether_types() {
+        let hub = VirtualHub::new();
+        let a = host(&hub, 1);
+        for ether_type in 0x9000..0x9100 {
+            a.registry()
+                .register(ether_type, |_: &[u8], _, _, _: &dyn FrameSender| {});
+        }
+
+        // The filter still checks the destination, but admits any ether type.
+        let program = a.default_filter();
+        assert!(program.iter().all(|ins| ins.k != 0x9000));
+        assert_eq!(program.last().unwrap().k, filter::ACCEPT);
+        assert!(program.iter().any(|ins| ins.k == filter::DROP));
+    }
+}
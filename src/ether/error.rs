@@ -0,0 +1,28 @@
+use crate::socket::GsoType;
+
+#[derive(Debug, Fail)]
+pub enum EtherError {
+    #[fail(display = "frame too large: {} bytes of payload for MTU {}", size, mtu)]
+    FrameTooLarge { size: usize, mtu: usize },
+
+    #[fail(display = "invalid Ethernet frame")]
+    InvalidFrame,
+
+    #[fail(
+        display = "truncated frame: {} of {} bytes captured",
+        captured, original
+    )]
+    TruncatedFrame { captured: usize, original: usize },
+
+    #[fail(display = "cannot segment a {:?} super-frame", _0)]
+    UnsupportedGso(GsoType),
+
+    #[fail(display = "not a multicast group")]
+    NotMulticastGroup,
+
+    #[fail(display = "{}", _0)]
+    IpError(#[fail(cause)] crate::ip::error::IpError),
+
+    #[fail(display = "{}", _0)]
+    IoError(#[fail(cause)] std::io::Error),
+}
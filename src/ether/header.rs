@@ -1,15 +1,288 @@
 use super::MacAddress;
+use crate::packet::PacketBuf;
+use crate::socket::FrameInfo;
+use crate::wire::U16Be;
 use map_struct::Mappable;
+use std::mem;
 
-#[repr(C, packed)]
+#[repr(C)]
 #[derive(Debug, PartialEq, Eq)]
 pub struct MacHeader {
-    pub dst_mac: MacAddress,
-    pub src_mac: MacAddress,
-    pub ether_type: u16,
+    dst_mac: MacAddress,
+    src_mac: MacAddress,
+    ether_type: U16Be,
 }
 
 unsafe impl Mappable for MacHeader {}
 
+impl MacHeader {
+    pub fn dst_mac(&self) -> MacAddress {
+        self.dst_mac
+    }
+
+    pub fn set_dst_mac(&mut self, dst_mac: MacAddress) {
+        self.dst_mac = dst_mac;
+    }
+
+    pub fn src_mac(&self) -> MacAddress {
+        self.src_mac
+    }
+
+    pub fn set_src_mac(&mut self, src_mac: MacAddress) {
+        self.src_mac = src_mac;
+    }
+
+    /// The ether type, or the TPID of the outer tag of a tagged frame.
+    pub fn ether_type(&self) -> u16 {
+        self.ether_type.get()
+    }
+
+    pub fn set_ether_type(&mut self, ether_type: u16) {
+        self.ether_type.set(ether_type);
+    }
+}
+
 pub const ETHERTYPE_IP: u16 = 0x0800;
 pub const ETHERTYPE_ARP: u16 = 0x0806;
+/// ./linux/if_ether.h:#define ETH_P_8021Q 0x8100
+pub const ETHERTYPE_VLAN: u16 = 0x8100;
+/// ./linux/if_ether.h:#define ETH_P_8021AD 0x88A8
+pub const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+pub const VLAN_TAG_LEN: usize = 4;
+const VLAN_VID_MASK: u16 = 0x0FFF;
+const VLAN_DEI_MASK: u16 = 0x1000;
+const VLAN_PCP_SHIFT: u16 = 13;
+
+/// An 802.1Q or 802.1ad tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VlanTag {
+    /// `ETHERTYPE_VLAN` for a customer tag, `ETHERTYPE_QINQ` for a service tag.
+    pub tpid: u16,
+    /// Priority code point.
+    pub pcp: u8,
+    /// Drop eligible indicator.
+    pub dei: bool,
+    pub vid: u16,
+}
+
+impl VlanTag {
+    /// An 802.1Q tag with the default priority.
+    pub fn new(vid: u16) -> Self {
+        VlanTag::from_tci(ETHERTYPE_VLAN, vid & VLAN_VID_MASK)
+    }
+
+    /// An 802.1ad service tag with the default priority.
+    pub fn service(vid: u16) -> Self {
+        VlanTag::from_tci(ETHERTYPE_QINQ, vid & VLAN_VID_MASK)
+    }
+
+    pub fn from_tci(tpid: u16, tci: u16) -> Self {
+        VlanTag {
+            tpid,
+            pcp: (tci >> VLAN_PCP_SHIFT) as u8,
+            dei: tci & VLAN_DEI_MASK != 0,
+            vid: tci & VLAN_VID_MASK,
+        }
+    }
+
+    /// Tag control information, as on the wire after the TPID.
+    pub fn tci(&self) -> u16 {
+        ((self.pcp as u16 & 0x7) << VLAN_PCP_SHIFT)
+            | if self.dei { VLAN_DEI_MASK } else { 0 }
+            | (self.vid & VLAN_VID_MASK)
+    }
+}
+
+/// The tags of a frame: none, one, or an outer service tag and an inner customer tag.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct VlanTags {
+    pub outer: Option<VlanTag>,
+    pub inner: Option<VlanTag>,
+}
+
+impl VlanTags {
+    pub fn single(tag: VlanTag) -> Self {
+        VlanTags {
+            outer: Some(tag),
+            inner: None,
+        }
+    }
+
+    pub fn double(outer: VlanTag, inner: VlanTag) -> Self {
+        VlanTags {
+            outer: Some(outer),
+            inner: Some(inner),
+        }
+    }
+
+    pub fn is_untagged(&self) -> bool {
+        self.outer.is_none()
+    }
+
+    /// Bytes the tags take between the source address and the ether type.
+    pub fn len(&self) -> usize {
+        (self.outer.is_some() as usize + self.inner.is_some() as usize) * VLAN_TAG_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether both carry the same VLAN IDs, whatever their priorities.
+    pub fn same_vlan(&self, other: &VlanTags) -> bool {
+        let vid = |tag: Option<VlanTag>| tag.map(|tag| tag.vid);
+        vid(self.outer) == vid(other.outer) && vid(self.inner) == vid(other.inner)
+    }
+
+    /// Writes the tags in wire format to the first `len()` bytes of `out`.
+    pub fn write(&self, out: &mut [u8]) {
+        let tags = self.outer.iter().chain(self.inner.iter());
+        for (tag, out) in tags.zip(out.chunks_exact_mut(VLAN_TAG_LEN)) {
+            out[..2].copy_from_slice(&tag.tpid.to_be_bytes());
+            out[2..].copy_from_slice(&tag.tci().to_be_bytes());
+        }
+    }
+}
+
+/// A tag as it follows its TPID, which is read as the ether type of the header before it.
+#[repr(C)]
+struct VlanTagHeader {
+    tci: U16Be,
+    ether_type: U16Be,
+}
+
+unsafe impl Mappable for VlanTagHeader {}
+
+fn is_tpid(ether_type: u16) -> bool {
+    ether_type == ETHERTYPE_VLAN || ether_type == ETHERTYPE_QINQ
+}
+
+/// Splits the tags off `data`, the bytes following a `MacHeader` carrying `ether_type`.
+/// Returns the tags, the ether type of the payload and the payload, or `None` if a tag is
+/// truncated or more than two are stacked.
+pub fn parse_vlan_tags(ether_type: u16, data: &[u8]) -> Option<(VlanTags, u16, &[u8])> {
+    let mut tags = VlanTags::default();
+    let mut ether_type = ether_type;
+    let mut data = data;
+
+    while is_tpid(ether_type) {
+        let (header, rest) = VlanTagHeader::mapped(data)?;
+        let tag = VlanTag::from_tci(ether_type, header.tci.get());
+        if tags.outer.is_none() {
+            tags.outer = Some(tag);
+        } else if tags.inner.is_none() {
+            tags.inner = Some(tag);
+        } else {
+            return None;
+        }
+        ether_type = header.ether_type.get();
+        data = rest;
+    }
+
+    Some((tags, ether_type, data))
+}
+
+/// The tags a received frame carried on the wire, including the one the kernel removed.
+/// Returns `None` for a malformed frame.
+pub fn frame_vlan_tags(frame: &[u8], info: &FrameInfo) -> Option<VlanTags> {
+    let (h, d) = MacHeader::mapped(frame)?;
+    let (tags, _, _) = parse_vlan_tags(h.ether_type(), d)?;
+    match info.vlan_tci {
+        None => Some(tags),
+        Some(_) if tags.inner.is_some() => None,
+        Some(tci) => Some(VlanTags {
+            outer: Some(VlanTag::from_tci(
+                info.vlan_tpid.unwrap_or(ETHERTYPE_VLAN),
+                tci,
+            )),
+            inner: tags.outer,
+        }),
+    }
+}
+
+/// Puts the outermost tag the kernel removed from a received frame back in place,
+/// so that the frame reads as it was on the wire.
+pub fn restore_vlan_tag(frame: &mut PacketBuf, info: &mut FrameInfo) {
+    let tci = match info.vlan_tci.take() {
+        Some(tci) => tci,
+        None => return,
+    };
+    let tpid = info.vlan_tpid.take().unwrap_or(ETHERTYPE_VLAN);
+    if frame.len() < mem::size_of::<MacHeader>() {
+        return;
+    }
+
+    // The addresses move to the headroom, leaving room for the tag after them.
+    let offset = 2 * mem::size_of::<MacAddress>();
+    frame.prepend(VLAN_TAG_LEN);
+    frame.copy_within(VLAN_TAG_LEN..VLAN_TAG_LEN + offset, 0);
+    frame[offset..offset + 2].copy_from_slice(&tpid.to_be_bytes());
+    frame[offset + 2..offset + VLAN_TAG_LEN].copy_from_slice(&tci.to_be_bytes());
+    if let Some(original_length) = info.original_length.as_mut() {
+        *original_length += VLAN_TAG_LEN;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ether::header::*;
+    use crate::packet::PacketBuf;
+    use crate::socket::FrameInfo;
+
+    #[test]
+    fn test_vlan_tag() {
+        let tag = VlanTag::from_tci(ETHERTYPE_VLAN, 0xB064);
+        assert_eq!(tag.pcp, 5);
+        assert!(tag.dei);
+        assert_eq!(tag.vid, 100);
+        assert_eq!(tag.tci(), 0xB064);
+        assert_eq!(VlanTag::new(0x1005).vid, 5);
+    }
+
+    #[test]
+    fn test_parse_vlan_tags() {
+        let tags = VlanTags::double(VlanTag::service(10), VlanTag::new(20));
+        let mut data = vec![0; tags.len()];
+        tags.write(&mut data);
+        data.extend_from_slice(&ETHERTYPE_IP.to_be_bytes());
+        data.extend_from_slice(b"payload");
+
+        // The first TPID is read as the ether type of the MAC header.
+        let (parsed, ether_type, payload) = parse_vlan_tags(ETHERTYPE_QINQ, &data[2..]).unwrap();
+        assert_eq!(parsed, tags);
+        assert_eq!(parsed.len(), 8);
+        assert_eq!(ether_type, ETHERTYPE_IP);
+        assert_eq!(payload, b"payload");
+
+        let (parsed, ether_type, _) = parse_vlan_tags(ETHERTYPE_ARP, b"arp").unwrap();
+        assert!(parsed.is_untagged());
+        assert_eq!(ether_type, ETHERTYPE_ARP);
+
+        assert!(parse_vlan_tags(ETHERTYPE_VLAN, &[0, 1]).is_none());
+        let triple = [0, 1, 0x81, 0, 0, 2, 0x81, 0, 0, 3, 0x08, 0];
+        assert!(parse_vlan_tags(ETHERTYPE_VLAN, &triple).is_none());
+    }
+
+    #[test]
+    fn test_restore_vlan_tag() {
+        let mut frame = PacketBuf::from_payload(&[0xAA; 12]);
+        frame.extend_from_slice(&ETHERTYPE_IP.to_be_bytes());
+        let mut info = FrameInfo {
+            original_length: Some(14),
+            vlan_tci: Some(42),
+            vlan_tpid: Some(ETHERTYPE_QINQ),
+            ..FrameInfo::default()
+        };
+
+        restore_vlan_tag(&mut frame, &mut info);
+        assert_eq!(&frame[..12], &[0xAA; 12]);
+        assert_eq!(&frame[12..], &[0x88, 0xA8, 0, 42, 0x08, 0x00]);
+        assert_eq!(info.original_length, Some(18));
+        assert_eq!(info.vlan_tci, None);
+
+        // Nothing is left to restore.
+        restore_vlan_tag(&mut frame, &mut info);
+        assert_eq!(frame.len(), 18);
+    }
+}
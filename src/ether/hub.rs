@@ -0,0 +1,624 @@
+use super::device::LinkDevice;
+use super::MacAddress;
+use crate::packet::PacketBuf;
+use crate::socket::filter::{self, BpfInstruction};
+use crate::socket::FrameInfo;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use libc::ETH_DATA_LEN;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// In-process Ethernet segment which repeats every frame to all the other ports,
+/// like a hub. Classifying the frames is left to the receiving `EthernetDriver`, after the
+/// filter attached to the port, if any, as the kernel would do for a socket.
+#[derive(Clone, Default)]
+pub struct VirtualHub {
+    ports: Arc<Mutex<Vec<UnboundedSender<PacketBuf>>>>,
+}
+
+#[derive(Clone)]
+pub struct VirtualPort {
+    id: usize,
+    mac_addr: MacAddress,
+    hub: VirtualHub,
+    receiver: Arc<Mutex<UnboundedReceiver<PacketBuf>>>,
+    filter: Arc<Mutex<Option<Vec<BpfInstruction>>>>,
+}
+
+impl VirtualHub {
+    pub fn new() -> Self {
+        VirtualHub::default()
+    }
+
+    pub fn connect(&self, mac_addr: MacAddress) -> VirtualPort {
+        let (sender, receiver) = unbounded();
+        let mut ports = self.ports.lock().unwrap();
+        ports.push(sender);
+
+        VirtualPort {
+            id: ports.len() - 1,
+            mac_addr,
+            hub: self.clone(),
+            receiver: Arc::new(Mutex::new(receiver)),
+            filter: Arc::default(),
+        }
+    }
+}
+
+impl LinkDevice for VirtualPort {
+    fn send(&self, frame: &[u8]) -> io::Result<usize> {
+        let ports = self.hub.ports.lock().unwrap();
+        for (id, port) in ports.iter().enumerate() {
+            if id != self.id {
+                // A port whose receiver is gone has just been unplugged.
+                let _ = port.unbounded_send(PacketBuf::from_payload(frame));
+            }
+        }
+        Ok(frame.len())
+    }
+
+    fn poll_recv(&self, cx: &mut Context) -> Poll<io::Result<(PacketBuf, FrameInfo)>> {
+        let mut receiver = self.receiver.lock().unwrap();
+        loop {
+            let frame = match receiver.poll_next_unpin(cx) {
+                Poll::Ready(Some(frame)) => frame,
+                Poll::Ready(None) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "hub is gone",
+                    )))
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            let filter = self.filter.lock().unwrap();
+            if filter
+                .as_ref()
+                .is_none_or(|program| filter::run(program, &frame) != filter::DROP)
+            {
+                return Poll::Ready(Ok((frame, FrameInfo::default())));
+            }
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        ETH_DATA_LEN as _
+    }
+
+    fn attach_filter(&self, program: &[BpfInstruction]) -> io::Result<()> {
+        *self.filter.lock().unwrap() = Some(program.to_vec());
+        Ok(())
+    }
+
+    fn hardware_address(&self) -> MacAddress {
+        self.mac_addr
+    }
+
+    /// A hub repeats every frame, so there is nothing to join.
+    fn join_multicast(&self, _group: MacAddress) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn leave_multicast(&self, _group: MacAddress) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hosts on a hub, shared by the tests of the layers above.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::{VirtualHub, VirtualPort};
+    use crate::arp::EtherIpResolver;
+    use crate::ether::device::LinkDevice;
+    use crate::ether::driver::EthernetDriver;
+    use crate::ether::header::{MacHeader, ETHERTYPE_IP};
+    use crate::ether::MacAddress;
+    use crate::ip::header::IpHeaderWithoutOptions;
+    use crate::ip::{IpAddress, IpDriver};
+    use crate::reactor::block_on;
+    use futures::prelude::*;
+    use map_struct::Mappable;
+    use std::task::Poll;
+
+    pub type Driver = EthernetDriver<EtherIpResolver, IpDriver, VirtualPort>;
+
+    /// The host `10.0.0.n`, with the MAC address `02:00:00:00:00:n` and no netmask.
+    pub fn host(hub: &VirtualHub, n: u8) -> Driver {
+        Driver::new(
+            IpAddress::new_be_bytes([10, 0, 0, n]),
+            false,
+            hub.connect(MacAddress::new([0x02, 0, 0, 0, 0, n])),
+        )
+    }
+
+    /// Runs `f` to completion while the hosts, on any kind of link, receive.
+    pub fn run_until<F: Future + Unpin, D: LinkDevice>(
+        f: F,
+        hosts: Vec<EthernetDriver<EtherIpResolver, IpDriver, D>>,
+    ) -> F::Output {
+        let streams = future::join_all(
+            hosts
+                .into_iter()
+                .map(|h| h.recv().for_each(|_| future::ready(())).boxed_local()),
+        );
+        match block_on(future::select(f, streams)) {
+            future::Either::Left((output, _)) => output,
+            future::Either::Right(_) => unreachable!(),
+        }
+    }
+
+    /// Completes with the first value `f` makes of a frame of `ether_type` seen by `port`,
+    /// from its header and payload.
+    pub fn sniff_ether<'a, T, F>(
+        port: &'a VirtualPort,
+        ether_type: u16,
+        mut f: F,
+    ) -> impl Future<Output = T> + Unpin + 'a
+    where
+        F: FnMut(&MacHeader, &[u8]) -> Option<T> + Unpin + 'a,
+    {
+        future::poll_fn(move |cx| loop {
+            let frame = match port.poll_recv(cx) {
+                Poll::Ready(frame) => frame.unwrap().0,
+                Poll::Pending => return Poll::Pending,
+            };
+            let (mac_header, payload) = MacHeader::mapped(&frame).unwrap();
+            if mac_header.ether_type() != ether_type {
+                continue;
+            }
+            if let Some(value) = f(mac_header, payload) {
+                return Poll::Ready(value);
+            }
+        })
+    }
+
+    /// Completes with the first value `f` makes of an IPv4 packet seen by `port`, from its
+    /// headers and the data after the fixed IP header.
+    pub fn sniff<'a, T, F>(port: &'a VirtualPort, mut f: F) -> impl Future<Output = T> + Unpin + 'a
+    where
+        F: FnMut(&MacHeader, &IpHeaderWithoutOptions, &[u8]) -> Option<T> + Unpin + 'a,
+    {
+        sniff_ether(port, ETHERTYPE_IP, move |mac_header, ip_packet| {
+            let (ip_header, payload) = IpHeaderWithoutOptions::mapped(ip_packet).unwrap();
+            f(mac_header, ip_header, payload)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builder::{EthernetBuilder, IcmpBuilder, Ipv4Builder};
+    use crate::ether::device::LinkDevice;
+    use crate::ether::driver::FrameSender;
+    use crate::ether::error::EtherError;
+    use crate::ether::header::{MacHeader, ETHERTYPE_ARP, ETHERTYPE_IP};
+    use crate::ether::hub::fixtures::*;
+    use crate::ether::hub::*;
+    use crate::ether::BROADCAST_MAC_ADDR;
+    use crate::ip::fragment::Reassembler;
+    use crate::ip::header::IpHeaderWithoutOptions;
+    use crate::ip::icmp::{
+        header::IcmpHeader, ECHO_REPLY_TYPE, ECHO_TYPE, ICMP_PROTOCOL_NUMBER, TIME_EXCEEDED_TYPE,
+    };
+    use crate::ip::protocol::DatagramSender;
+    use crate::ip::route::Route;
+    use crate::ip::{InterfaceAddress, IpAddress, PathConfig};
+    use crate::reactor::block_on;
+    use crate::utils;
+    use crate::Destination;
+    use map_struct::Mappable;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_arp_resolution() {
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let b = host(&hub, 2);
+
+        let resolved = a.resolve(IpAddress::new_be_bytes([10, 0, 0, 2]));
+        assert_eq!(
+            run_until(resolved, vec![a, b]),
+            Some(MacAddress::new([0x02, 0, 0, 0, 0, 2]))
+        );
+    }
+
+    #[test]
+    fn test_echo_without_netmask() {
+        let hub = VirtualHub::new();
+        let a = host(&hub, 1);
+        // Neither host knows the other is on the link, as they have no netmask.
+        let mut b = Driver::new(
+            IpAddress::new_be_bytes([172, 16, 0, 2]),
+            false,
+            hub.connect(MacAddress::new([0x02, 0, 0, 0, 0, 2])),
+        );
+
+        let sniffer = hub.connect(MacAddress::new([0x02, 0, 0, 0, 0, 0xFF]));
+        let reply_receiver = sniff(&sniffer, |mac_header, ip_header, icmp| {
+            if icmp[0] == ECHO_REPLY_TYPE {
+                Some((mac_header.dst_mac(), ip_header.dst_addr()))
+            } else {
+                None
+            }
+        });
+
+        let echo = IcmpBuilder::echo_request(1, 1).build(PacketBuf::from_payload(b"ping"));
+        let sent = b.send_ipv4(
+            ICMP_PROTOCOL_NUMBER,
+            IpAddress::new_be_bytes([10, 0, 0, 1]),
+            echo,
+        );
+        let (sent, reply) = run_until(future::join(sent, reply_receiver), vec![a, b]);
+        assert_eq!(sent, Some(()));
+        assert_eq!(
+            reply,
+            (
+                MacAddress::new([0x02, 0, 0, 0, 0, 2]),
+                IpAddress::new_be_bytes([172, 16, 0, 2])
+            )
+        );
+    }
+
+    #[test]
+    fn test_mtu() {
+        let hub = VirtualHub::new();
+        let a = host(&hub, 1);
+        let header_size = std::mem::size_of::<MacHeader>();
+
+        assert!(a.send(&vec![0; header_size + 1500]).is_ok());
+        match a.send(&vec![0; header_size + 1501]) {
+            Err(EtherError::FrameTooLarge { size, mtu }) => assert_eq!((size, mtu), (1501, 1500)),
+            _ => panic!("an oversized frame is sent"),
+        }
+    }
+
+    #[test]
+    fn test_icmp_echo() {
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let b = host(&hub, 2);
+        let sniffer = hub.connect(MacAddress::new([0x02, 0, 0, 0, 0, 0xFF]));
+
+        let mut echo = vec![ECHO_TYPE, 0, 0, 0, 0x12, 0x34, 0x00, 0x01];
+        echo.extend((0..32).collect::<Vec<u8>>());
+        let checksum = utils::checksum(&echo).to_be_bytes();
+        echo[2..4].copy_from_slice(&checksum);
+
+        let reply_receiver = sniff(&sniffer, |mac_header, ip_header, icmp| {
+            let (icmp_header, _) = IcmpHeader::mapped(icmp).unwrap();
+            if ip_header.src_addr() == IpAddress::new_be_bytes([10, 0, 0, 2])
+                && icmp_header.icmp_type() == ECHO_REPLY_TYPE
+            {
+                Some([mac_header.as_bytes(), ip_header.as_bytes(), icmp].concat())
+            } else {
+                None
+            }
+        });
+
+        let sent = a.send_ipv4(
+            ICMP_PROTOCOL_NUMBER,
+            IpAddress::new_be_bytes([10, 0, 0, 2]),
+            PacketBuf::from_payload(&echo),
+        );
+        let (sent, reply) = run_until(future::join(sent, reply_receiver), vec![a, b]);
+        assert_eq!(sent, Some(()));
+
+        let (mac_header, ip_packet) = MacHeader::mapped(&reply).unwrap();
+        assert_eq!(mac_header.dst_mac(), MacAddress::new([0x02, 0, 0, 0, 0, 1]));
+        let (ip_header, icmp) = IpHeaderWithoutOptions::mapped(ip_packet).unwrap();
+        assert!(ip_header.is_valid(ip_packet));
+        assert_eq!(ip_header.dst_addr(), IpAddress::new_be_bytes([10, 0, 0, 1]));
+        let icmp = &icmp[..echo.len()];
+        assert_eq!(utils::checksum(icmp), 0);
+        // The identifier and the sequence number come back unchanged.
+        assert_eq!(&icmp[4..8], &[0x12, 0x34, 0x00, 0x01]);
+        assert_eq!(&icmp[8..], &echo[8..]);
+    }
+
+    #[test]
+    fn test_fragmented_echo() {
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let b = host(&hub, 2);
+        let sniffer = hub.connect(MacAddress::new([0x02, 0, 0, 0, 0, 0xFF]));
+        let peer = IpAddress::new_be_bytes([10, 0, 0, 2]);
+
+        let echo =
+            IcmpBuilder::echo_request(0x1234, 1).build(PacketBuf::from_payload(&[0xC3; 4000]));
+
+        // The reply is larger than the MTU as well, so it comes back in fragments.
+        let mut reassembler = Reassembler::new();
+        let reply_receiver = sniff(&sniffer, |_, ip_header, payload| {
+            assert!(ip_header.total_length() <= 1500);
+            if ip_header.src_addr() != peer {
+                return None;
+            }
+            reassembler
+                .insert(ip_header, ip_header.as_bytes(), payload, Instant::now())
+                .unwrap()
+                .map(|datagram| datagram.payload)
+        });
+
+        let sent = a.send_ipv4(ICMP_PROTOCOL_NUMBER, peer, echo.clone());
+        let (sent, reply) = run_until(future::join(sent, reply_receiver), vec![a, b]);
+        assert_eq!(sent, Some(()));
+        assert_eq!(reply.len(), echo.len());
+        assert_eq!(reply[0], ECHO_REPLY_TYPE);
+        assert_eq!(utils::checksum(&reply), 0);
+        assert_eq!(&reply[4..], &echo[4..]);
+    }
+
+    #[test]
+    fn test_reassembly_timeout() {
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let b = host(&hub, 2);
+        let sniffer = hub.connect(MacAddress::new([0x02, 0, 0, 0, 0, 0xFF]));
+        let a_addr = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let b_addr = IpAddress::new_be_bytes([10, 0, 0, 2]);
+        a.ip_parser_mut()
+            .reassembler_mut()
+            .set_timeout(Duration::from_millis(100));
+
+        // Only the first fragment is ever sent, and nothing else arrives afterwards.
+        let first = Ipv4Builder::new(b_addr, a_addr)
+            .flags_fragment_offset(0x2000)
+            .build(
+                ICMP_PROTOCOL_NUMBER,
+                IcmpBuilder::echo_request(0x1234, 1).build(PacketBuf::from_payload(&[0xC3; 64])),
+            );
+        b.send_frame(MacAddress::new([0x02, 0, 0, 0, 0, 1]), ETHERTYPE_IP, &first)
+            .unwrap();
+
+        let error_receiver = sniff(&sniffer, |_, ip_header, payload| {
+            if ip_header.src_addr() == a_addr && ip_header.dst_addr() == b_addr {
+                Some(payload.to_vec())
+            } else {
+                None
+            }
+        });
+
+        let error = run_until(error_receiver, vec![a, b]);
+        assert_eq!(error[0], TIME_EXCEEDED_TYPE);
+        assert_eq!(&error[8..28], &first[..20]);
+    }
+
+    #[test]
+    fn test_dont_fragment() {
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let b = host(&hub, 2);
+        let peer = IpAddress::new_be_bytes([10, 0, 0, 2]);
+        a.ip_parser_mut().set_path(
+            peer,
+            PathConfig {
+                mtu: None,
+                dont_fragment: true,
+            },
+        );
+
+        let echo = IcmpBuilder::echo_request(1, 1).build(PacketBuf::from_payload(&[0; 1500]));
+        let sent = a.send_ipv4(ICMP_PROTOCOL_NUMBER, peer, echo);
+        assert_eq!(run_until(sent, vec![a, b]), None);
+    }
+
+    #[test]
+    fn test_gateway() {
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let gateway = host(&hub, 254);
+        let sniffer = hub.connect(MacAddress::new([0x02, 0, 0, 0, 0, 0xFF]));
+        let pinger = IpAddress::new_be_bytes([192, 168, 1, 1]);
+
+        // Without a gateway, the pinger is taken to be on the link.
+        let echo = IcmpBuilder::echo_request(1, 1).build(PacketBuf::from_payload(b"ping"));
+        drop(a.send_ipv4(ICMP_PROTOCOL_NUMBER, pinger, echo.clone()));
+        let asked = block_on(sniff_ether(&sniffer, ETHERTYPE_ARP, |_, arp| {
+            Some(arp[24..28].to_vec())
+        }));
+        assert_eq!(asked, pinger.to_be_bytes());
+
+        a.ip_parser_mut()
+            .routing_table_mut()
+            .add(Route::default_via(IpAddress::new_be_bytes([10, 0, 0, 254])))
+            .unwrap();
+        let request = EthernetBuilder::new(
+            MacAddress::new([0x02, 0, 0, 0, 0, 0xFE]),
+            MacAddress::new([0x02, 0, 0, 0, 0, 1]),
+        )
+        .ipv4(Ipv4Builder::new(
+            pinger,
+            IpAddress::new_be_bytes([10, 0, 0, 1]),
+        ))
+        .build(ICMP_PROTOCOL_NUMBER, echo);
+        sniffer.send(&request).unwrap();
+
+        // The reply to the pinger off the subnet goes to the gateway.
+        let reply_receiver = sniff(&sniffer, |mac_header, ip_header, _| {
+            if ip_header.dst_addr() == pinger {
+                Some(mac_header.dst_mac())
+            } else {
+                None
+            }
+        });
+        assert_eq!(
+            run_until(reply_receiver, vec![a, gateway]),
+            MacAddress::new([0x02, 0, 0, 0, 0, 254])
+        );
+    }
+
+    #[test]
+    fn test_multiple_addresses() {
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let mut b = host(&hub, 2);
+        let sniffer = hub.connect(MacAddress::new([0x02, 0, 0, 0, 0, 0xFF]));
+        let secondary = IpAddress::new_be_bytes([10, 0, 0, 101]);
+
+        a.add_ipv4_address(InterfaceAddress::new(secondary, 24))
+            .unwrap();
+        let announcement = block_on(sniff_ether(&sniffer, ETHERTYPE_ARP, |mac_header, arp| {
+            assert_eq!(mac_header.dst_mac(), BROADCAST_MAC_ADDR);
+            Some(arp.to_vec())
+        }));
+        // The sender and the target protocol addresses are both the new one.
+        assert_eq!(&announcement[8..14], &[0x02, 0, 0, 0, 0, 1]);
+        assert_eq!(&announcement[14..18], &secondary.to_be_bytes());
+        assert_eq!(&announcement[24..28], &secondary.to_be_bytes());
+
+        // The reply to an echo request to the new address comes from it.
+        let reply_receiver = sniff(&sniffer, |mac_header, ip_header, icmp| {
+            if icmp[0] == ECHO_REPLY_TYPE {
+                Some((mac_header.src_mac(), ip_header.src_addr()))
+            } else {
+                None
+            }
+        });
+        let echo = IcmpBuilder::echo_request(1, 1).build(PacketBuf::from_payload(b"ping"));
+        let sent = b.send_ipv4(ICMP_PROTOCOL_NUMBER, secondary, echo);
+        let (sent, reply) = run_until(future::join(sent, reply_receiver), vec![a, b]);
+        assert_eq!(sent, Some(()));
+        assert_eq!(reply, (MacAddress::new([0x02, 0, 0, 0, 0, 1]), secondary));
+    }
+
+    #[test]
+    fn test_address_removal() {
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let sniffer = hub.connect(MacAddress::new([0x02, 0, 0, 0, 0, 0xFF]));
+        let primary = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let secondary = IpAddress::new_be_bytes([10, 0, 0, 101]);
+        let announced = || {
+            block_on(sniff_ether(&sniffer, ETHERTYPE_ARP, |_, arp| {
+                Some(arp[14..18].to_vec())
+            }))
+        };
+
+        a.add_ipv4_address(InterfaceAddress::new(secondary, 24))
+            .unwrap();
+        assert_eq!(announced(), secondary.to_be_bytes());
+        // The address left to talk to the neighbors from takes the place of the removed one.
+        assert_eq!(
+            a.remove_ipv4_address(secondary),
+            Some(InterfaceAddress::new(secondary, 24))
+        );
+        assert_eq!(announced(), primary.to_be_bytes());
+    }
+
+    #[test]
+    fn test_broadcast() {
+        /// Reserved for experimentation and testing (RFC 3692).
+        const PROTOCOL_LAB: u8 = 253;
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let mut b = host(&hub, 2);
+        for driver in [&mut a, &mut b].iter_mut() {
+            driver
+                .ip_parser_mut()
+                .set_netmask(IpAddress::new_be_bytes([255, 255, 255, 0]))
+                .unwrap();
+        }
+        let (received, receiver) = unbounded();
+        b.ip_parser_mut()
+            .registry()
+            .register(
+                PROTOCOL_LAB,
+                move |_: &[u8], _, dst, _: &mut dyn DatagramSender| {
+                    received.unbounded_send(dst).unwrap();
+                },
+            )
+            .unwrap();
+
+        // Nobody would answer ARP for either broadcast address.
+        let limited = IpAddress::new_be_bytes([255, 255, 255, 255]);
+        let directed = IpAddress::new_be_bytes([10, 0, 0, 255]);
+        let sent = future::join(
+            a.send_ipv4(PROTOCOL_LAB, limited, PacketBuf::from_payload(b"to all")),
+            a.send_ipv4(PROTOCOL_LAB, directed, PacketBuf::from_payload(b"to all")),
+        );
+        let (sent, received) = run_until(
+            future::join(sent, receiver.take(2).collect::<Vec<_>>()),
+            vec![a, b],
+        );
+        assert_eq!(sent, (Some(()), Some(())));
+        assert_eq!(received, vec![limited, directed]);
+    }
+
+    #[test]
+    fn test_join_after_attach() {
+        /// Reserved for experimentation and testing (RFC 3692).
+        const PROTOCOL_LAB: u8 = 253;
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let mut b = host(&hub, 2);
+        let group = IpAddress::new_be_bytes([239, 1, 2, 3]);
+
+        b.attach_default_filter().unwrap();
+        b.join_ipv4_multicast(group).unwrap();
+        let (received, mut receiver) = unbounded();
+        b.ip_parser_mut()
+            .registry()
+            .register(
+                PROTOCOL_LAB,
+                move |payload: &[u8], _, dst, _: &mut dyn DatagramSender| {
+                    received.unbounded_send((dst, payload.to_vec())).unwrap();
+                },
+            )
+            .unwrap();
+
+        let sent = a.send_ipv4(
+            PROTOCOL_LAB,
+            group,
+            PacketBuf::from_payload(b"to the group"),
+        );
+        let (sent, received) = run_until(future::join(sent, receiver.next()), vec![a, b]);
+        assert_eq!(sent, Some(()));
+        assert_eq!(received, Some((group, b"to the group".to_vec())));
+    }
+
+    #[test]
+    fn test_multicast() {
+        const ETHERTYPE_LAB: u16 = 0x88B5;
+        let hub = VirtualHub::new();
+        let a = host(&hub, 1);
+        let mut b = host(&hub, 2);
+        let c = host(&hub, 3);
+        let group = IpAddress::new_be_bytes([239, 1, 2, 3]);
+        b.join_ipv4_multicast(group).unwrap();
+        assert!(b
+            .join_ipv4_multicast(IpAddress::new_be_bytes([10, 0, 0, 1]))
+            .is_err());
+
+        let (received, receiver) = unbounded();
+        for (n, driver) in [(2u8, &b), (3, &c)].iter() {
+            let received = received.clone();
+            let n = *n;
+            driver.registry().register(
+                ETHERTYPE_LAB,
+                move |_: &[u8], _, frame_dst, _: &dyn FrameSender| {
+                    received.unbounded_send((n, frame_dst)).unwrap();
+                },
+            );
+        }
+
+        let group_mac = MacAddress::from_ipv4_multicast(group).unwrap();
+        a.send_frame(group_mac, ETHERTYPE_LAB, b"to the group")
+            .unwrap();
+        // Only `b` has joined the group, and `c` is not promiscuous,
+        // so the broadcast frame is the only one `c` handles.
+        a.send_frame(BROADCAST_MAC_ADDR, ETHERTYPE_LAB, b"to all")
+            .unwrap();
+
+        let mut received = run_until(receiver.take(3).collect::<Vec<_>>(), vec![a, b, c]);
+        received.sort_by_key(|&(n, frame_dst)| (n, frame_dst == Destination::Broadcast));
+        assert_eq!(
+            received,
+            vec![
+                (2, Destination::Multicast),
+                (2, Destination::Broadcast),
+                (3, Destination::Broadcast)
+            ]
+        );
+    }
+}
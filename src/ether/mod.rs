@@ -1,8 +1,14 @@
+use crate::ip::IpAddress;
 use map_struct::Mappable;
 use std::fmt;
 
+pub mod device;
 pub mod driver;
+pub mod error;
 pub mod header;
+pub mod hub;
+pub mod offload;
+pub mod vlan;
 
 #[repr(C, packed)]
 #[derive(PartialEq, Eq, Copy, Clone, Hash)]
@@ -14,6 +20,21 @@ impl MacAddress {
     pub fn new(address: [u8; 6]) -> Self {
         MacAddress { address }
     }
+
+    /// The address of the Ethernet group an IPv4 multicast `group` is sent to (RFC 1112):
+    /// 01:00:5E followed by the low 23 bits of the group. `None` for a unicast address.
+    pub fn from_ipv4_multicast(group: IpAddress) -> Option<Self> {
+        if !group.is_multicast() {
+            return None;
+        }
+        let [_, b, c, d] = group.to_be_bytes();
+        Some(MacAddress::new([0x01, 0x00, 0x5E, b & 0x7F, c, d]))
+    }
+
+    /// Whether the address is a group address. The broadcast address is one of them.
+    pub fn is_multicast(&self) -> bool {
+        self.address[0] & 0x01 != 0
+    }
 }
 
 unsafe impl Mappable for MacAddress {}
@@ -33,3 +54,26 @@ impl fmt::Debug for MacAddress {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::ether::*;
+
+    #[test]
+    fn test_ipv4_multicast() {
+        let group = IpAddress::new_be_bytes([239, 129, 2, 3]);
+        assert_eq!(
+            MacAddress::from_ipv4_multicast(group),
+            Some(MacAddress::new([0x01, 0x00, 0x5E, 0x01, 0x02, 0x03]))
+        );
+        assert!(MacAddress::from_ipv4_multicast(group)
+            .unwrap()
+            .is_multicast());
+        assert_eq!(
+            MacAddress::from_ipv4_multicast(IpAddress::new_be_bytes([10, 0, 0, 1])),
+            None
+        );
+        assert!(BROADCAST_MAC_ADDR.is_multicast());
+        assert!(!MacAddress::new([0x02, 0, 0, 0, 0, 1]).is_multicast());
+    }
+}
@@ -0,0 +1,481 @@
+//! Undoes what checksum offload and GSO/GRO leave in received frames, so that the parsers
+//! only ever see frames as they would be on the wire.
+
+use super::error::EtherError;
+use super::header::{MacHeader, ETHERTYPE_IP};
+use crate::ip::header::{IpHeaderWithoutOptions, IP_FLAG_DF, IP_FLAG_MF, IP_VERSION_4};
+use crate::packet::PacketBuf;
+use crate::socket::{ChecksumPosition, ChecksumStatus, FrameInfo, Gso, GsoType};
+use crate::utils;
+use map_struct::Mappable;
+
+use std::mem;
+use std::vec;
+
+const MAC_HEADER_LEN: usize = mem::size_of::<MacHeader>();
+const IP_PROTOCOL_ICMP: u8 = 1;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+const TCP_CHECKSUM_OFFSET: usize = 16;
+const UDP_CHECKSUM_OFFSET: usize = 6;
+const ICMP_CHECKSUM_OFFSET: usize = 2;
+const UDP_HEADER_LEN: usize = 8;
+const TCP_FLAG_FIN: u8 = 0x01;
+const TCP_FLAG_PSH: u8 = 0x08;
+
+/// Bounds of the IPv4 packet in a frame.
+#[derive(Debug, Clone, Copy)]
+struct Ipv4Layout {
+    header_len: usize,
+    /// End of the packet, before any Ethernet padding.
+    end: usize,
+    protocol: u8,
+}
+
+impl Ipv4Layout {
+    fn of(frame: &[u8]) -> Option<Ipv4Layout> {
+        let (mac_header, ip) = MacHeader::mapped(frame)?;
+        if mac_header.ether_type() != ETHERTYPE_IP {
+            return None;
+        }
+        let (header, _) = IpHeaderWithoutOptions::mapped(ip)?;
+        if header.version() != IP_VERSION_4 {
+            return None;
+        }
+        let header_len = header.header_len();
+        let total_len = header.total_length() as usize;
+        if header_len < mem::size_of::<IpHeaderWithoutOptions>()
+            || total_len < header_len
+            || total_len > ip.len()
+        {
+            return None;
+        }
+        Some(Ipv4Layout {
+            header_len,
+            end: MAC_HEADER_LEN + total_len,
+            protocol: header.protocol(),
+        })
+    }
+
+    fn ip_header<'a>(&self, frame: &'a [u8]) -> &'a IpHeaderWithoutOptions {
+        IpHeaderWithoutOptions::mapped(&frame[MAC_HEADER_LEN..])
+            .unwrap()
+            .0
+    }
+
+    fn transport_start(&self) -> usize {
+        MAC_HEADER_LEN + self.header_len
+    }
+}
+
+/// The frames `resolve` makes of a received frame, without allocating for a lone frame.
+pub enum Resolved {
+    Frame(Option<(PacketBuf, FrameInfo)>),
+    Segments(vec::IntoIter<(PacketBuf, FrameInfo)>),
+}
+
+impl Resolved {
+    pub fn none() -> Self {
+        Resolved::Frame(None)
+    }
+}
+
+impl Iterator for Resolved {
+    type Item = (PacketBuf, FrameInfo);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Resolved::Frame(frame) => frame.take(),
+            Resolved::Segments(segments) => segments.next(),
+        }
+    }
+}
+
+/// Turns a received frame into the frames the parsers should see: the frame itself with
+/// its partial checksum completed, or the segments of a super-frame.
+pub fn resolve(mut frame: PacketBuf, mut info: FrameInfo) -> Result<Resolved, EtherError> {
+    if let Some(original) = info.original_length {
+        if original > frame.len() {
+            return Err(EtherError::TruncatedFrame {
+                captured: frame.len(),
+                original,
+            });
+        }
+    }
+
+    if let ChecksumStatus::Partial(position) = info.checksum {
+        if complete_checksum(&mut frame, position)? {
+            info.checksum = ChecksumStatus::Valid;
+        }
+    }
+
+    match info.gso {
+        None => Ok(Resolved::Frame(Some((frame, info)))),
+        Some(gso) => {
+            let segments: Vec<_> = segment(&frame, gso)?
+                .into_iter()
+                .map(|segment| {
+                    let info = FrameInfo {
+                        original_length: Some(segment.len()),
+                        checksum: ChecksumStatus::Valid,
+                        gso: None,
+                        ..info
+                    };
+                    (segment, info)
+                })
+                .collect();
+            Ok(Resolved::Segments(segments.into_iter()))
+        }
+    }
+}
+
+/// Fills in a transport checksum holding only the sum of the pseudo header. Without a
+/// reported position it is located in an IPv4 frame; `false` is returned for other frames.
+fn complete_checksum(
+    frame: &mut [u8],
+    position: Option<ChecksumPosition>,
+) -> Result<bool, EtherError> {
+    let layout = Ipv4Layout::of(frame);
+    let position = match position {
+        Some(position) => position,
+        None => {
+            let layout = match layout {
+                Some(layout) => layout,
+                None => return Ok(false),
+            };
+            let offset = match layout.protocol {
+                IP_PROTOCOL_TCP => TCP_CHECKSUM_OFFSET,
+                IP_PROTOCOL_UDP => UDP_CHECKSUM_OFFSET,
+                IP_PROTOCOL_ICMP => ICMP_CHECKSUM_OFFSET,
+                _ => return Ok(false),
+            };
+            ChecksumPosition {
+                start: layout.transport_start(),
+                offset,
+            }
+        }
+    };
+
+    let end = layout.map_or(frame.len(), |layout| layout.end);
+    let field = position.start + position.offset;
+    if field + 2 > end {
+        return Err(EtherError::InvalidFrame);
+    }
+    let checksum = utils::checksum(&frame[position.start..end]);
+    frame[field..field + 2].copy_from_slice(&checksum.to_be_bytes());
+    Ok(true)
+}
+
+fn set_ip_header(frame: &mut [u8], layout: &Ipv4Layout, total_len: usize, id: u16, frag: u16) {
+    let ip = &mut frame[MAC_HEADER_LEN..MAC_HEADER_LEN + layout.header_len];
+    {
+        let (header, _) = IpHeaderWithoutOptions::mapped_mut(ip).unwrap();
+        header.set_total_length(total_len as u16);
+        header.set_identification(id);
+        header.set_flags_fragment_offset(frag);
+        header.set_checksum(0);
+    }
+    let checksum = utils::checksum(ip);
+    IpHeaderWithoutOptions::mapped_mut(ip)
+        .unwrap()
+        .0
+        .set_checksum(checksum);
+}
+
+/// Checksum of a TCP or UDP segment in `frame`, including the IPv4 pseudo header.
+/// The checksum field of the segment must be zero.
+fn transport_checksum(frame: &[u8], layout: &Ipv4Layout) -> u16 {
+    let header = layout.ip_header(frame);
+    let segment = &frame[layout.transport_start()..];
+    let mut data = Vec::with_capacity(12 + segment.len());
+    data.extend_from_slice(&header.src_addr().to_be_bytes());
+    data.extend_from_slice(&header.dst_addr().to_be_bytes());
+    data.extend_from_slice(&[0, layout.protocol]);
+    data.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+    data.extend_from_slice(segment);
+    utils::checksum(&data)
+}
+
+/// Splits an IPv4 super-frame into the frames a NIC would have sent or received.
+fn segment(frame: &[u8], gso: Gso) -> Result<Vec<PacketBuf>, EtherError> {
+    let layout = Ipv4Layout::of(frame).ok_or(EtherError::InvalidFrame)?;
+    if gso.size == 0 {
+        return Err(EtherError::InvalidFrame);
+    }
+    let transport = layout.transport_start();
+    let header = layout.ip_header(frame);
+    let id = header.identification();
+    let df = header.flags_fragment_offset() & IP_FLAG_DF;
+
+    match (gso.gso_type, layout.protocol) {
+        (GsoType::TcpV4, IP_PROTOCOL_TCP) => {
+            if layout.end < transport + 20 {
+                return Err(EtherError::InvalidFrame);
+            }
+            let tcp_header_len = (frame[transport + 12] >> 4) as usize * 4;
+            let headers = transport + tcp_header_len;
+            if tcp_header_len < 20 || headers > layout.end {
+                return Err(EtherError::InvalidFrame);
+            }
+            let seq = u32::from_be_bytes([
+                frame[transport + 4],
+                frame[transport + 5],
+                frame[transport + 6],
+                frame[transport + 7],
+            ]);
+            let flags = frame[transport + 13];
+
+            let chunks: Vec<_> = frame[headers..layout.end].chunks(gso.size).collect();
+            let n = chunks.len();
+            Ok(chunks
+                .into_iter()
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let mut segment = PacketBuf::from_payload(&frame[..headers]);
+                    segment.extend_from_slice(chunk);
+                    set_ip_header(
+                        &mut segment,
+                        &layout,
+                        layout.header_len + tcp_header_len + chunk.len(),
+                        id.wrapping_add(i as _),
+                        df,
+                    );
+
+                    let tcp = &mut segment[transport..];
+                    let seq = seq.wrapping_add((i * gso.size) as u32);
+                    tcp[4..8].copy_from_slice(&seq.to_be_bytes());
+                    // Only the last segment ends the push or the stream.
+                    if i + 1 != n {
+                        tcp[13] = flags & !(TCP_FLAG_FIN | TCP_FLAG_PSH);
+                    }
+                    tcp[TCP_CHECKSUM_OFFSET..TCP_CHECKSUM_OFFSET + 2].copy_from_slice(&[0, 0]);
+                    let checksum = transport_checksum(&segment, &layout);
+                    segment[transport + TCP_CHECKSUM_OFFSET..transport + TCP_CHECKSUM_OFFSET + 2]
+                        .copy_from_slice(&checksum.to_be_bytes());
+                    segment
+                })
+                .collect())
+        }
+        (GsoType::UdpL4, IP_PROTOCOL_UDP) => {
+            let headers = transport + UDP_HEADER_LEN;
+            if headers > layout.end {
+                return Err(EtherError::InvalidFrame);
+            }
+            let with_checksum = frame[transport + UDP_CHECKSUM_OFFSET..headers] != [0, 0];
+
+            Ok(frame[headers..layout.end]
+                .chunks(gso.size)
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let mut segment = PacketBuf::from_payload(&frame[..headers]);
+                    segment.extend_from_slice(chunk);
+                    set_ip_header(
+                        &mut segment,
+                        &layout,
+                        layout.header_len + UDP_HEADER_LEN + chunk.len(),
+                        id.wrapping_add(i as _),
+                        df,
+                    );
+
+                    let udp_len = (UDP_HEADER_LEN + chunk.len()) as u16;
+                    segment[transport + 4..transport + 6].copy_from_slice(&udp_len.to_be_bytes());
+                    segment[transport + UDP_CHECKSUM_OFFSET..headers].copy_from_slice(&[0, 0]);
+                    if with_checksum {
+                        // A zero checksum means none in UDP, so it is sent as all ones.
+                        let checksum = match transport_checksum(&segment, &layout) {
+                            0 => 0xFFFF,
+                            checksum => checksum,
+                        };
+                        segment[transport + UDP_CHECKSUM_OFFSET..headers]
+                            .copy_from_slice(&checksum.to_be_bytes());
+                    }
+                    segment
+                })
+                .collect())
+        }
+        (GsoType::Udp, IP_PROTOCOL_UDP) => {
+            // Fragment offsets count 8 bytes.
+            let size = gso.size / 8 * 8;
+            if size == 0 {
+                return Err(EtherError::InvalidFrame);
+            }
+            let chunks: Vec<_> = frame[transport..layout.end].chunks(size).collect();
+            let n = chunks.len();
+            Ok(chunks
+                .into_iter()
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let mut fragment = PacketBuf::from_payload(&frame[..transport]);
+                    fragment.extend_from_slice(chunk);
+                    let more = if i + 1 != n { IP_FLAG_MF } else { 0 };
+                    set_ip_header(
+                        &mut fragment,
+                        &layout,
+                        layout.header_len + chunk.len(),
+                        id,
+                        more | (i * size / 8) as u16,
+                    );
+                    fragment
+                })
+                .collect())
+        }
+        (gso_type, _) => Err(EtherError::UnsupportedGso(gso_type)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ether::offload::*;
+    use crate::utils;
+
+    fn ipv4_frame(protocol: u8, transport: &[u8]) -> PacketBuf {
+        let mut frame = vec![0u8; MAC_HEADER_LEN];
+        frame[12..14].copy_from_slice(&ETHERTYPE_IP.to_be_bytes());
+        let total_len = (20 + transport.len()) as u16;
+        let mut ip = vec![0x45, 0, 0, 0, 0x12, 0x34, 0x40, 0, 64, protocol, 0, 0];
+        ip[2..4].copy_from_slice(&total_len.to_be_bytes());
+        ip.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        let checksum = utils::checksum(&ip);
+        ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+        frame.extend_from_slice(&ip);
+        frame.extend_from_slice(transport);
+        PacketBuf::from_payload(&frame)
+    }
+
+    fn assert_checksums(frame: &[u8]) {
+        let layout = Ipv4Layout::of(frame).unwrap();
+        assert_eq!(
+            utils::checksum(&frame[MAC_HEADER_LEN..layout.transport_start()]),
+            0
+        );
+        let fragmented = u16::from_be_bytes([frame[20], frame[21]]) & !IP_FLAG_DF != 0;
+        if !fragmented && (layout.protocol == IP_PROTOCOL_TCP || layout.protocol == IP_PROTOCOL_UDP)
+        {
+            assert_eq!(transport_checksum(frame, &layout), 0);
+        }
+    }
+
+    #[test]
+    fn test_complete_checksum() {
+        let mut icmp = vec![8, 0, 0, 0, 0, 1, 0, 1];
+        icmp.extend_from_slice(b"ping");
+        let frame = ipv4_frame(IP_PROTOCOL_ICMP, &icmp);
+        // Padding must not be summed.
+        let mut padded = frame.clone();
+        padded.resize(60, 0xAA);
+
+        let info = FrameInfo {
+            checksum: ChecksumStatus::Partial(None),
+            ..FrameInfo::default()
+        };
+        let frames = resolve(padded, info).unwrap().collect::<Vec<_>>();
+        assert_eq!(frames.len(), 1);
+        let (frame, info) = &frames[0];
+        assert_eq!(info.checksum, ChecksumStatus::Valid);
+        assert_eq!(utils::checksum(&frame[34..34 + icmp.len()]), 0);
+    }
+
+    fn tcp_super_frame(payload_len: usize) -> PacketBuf {
+        let mut tcp = vec![0u8; 20];
+        tcp[0..2].copy_from_slice(&1234u16.to_be_bytes());
+        tcp[2..4].copy_from_slice(&80u16.to_be_bytes());
+        tcp[4..8].copy_from_slice(&0xFFFF_FF00u32.to_be_bytes());
+        tcp[12] = 5 << 4;
+        tcp[13] = 0x10 | TCP_FLAG_PSH | TCP_FLAG_FIN;
+        tcp.extend((0..payload_len).map(|i| i as u8));
+        ipv4_frame(IP_PROTOCOL_TCP, &tcp)
+    }
+
+    #[test]
+    fn test_segment_tcp() {
+        let frame = tcp_super_frame(3000);
+        let info = FrameInfo {
+            original_length: Some(frame.len()),
+            gso: Some(Gso {
+                gso_type: GsoType::TcpV4,
+                size: 1460,
+                ecn: false,
+            }),
+            ..FrameInfo::default()
+        };
+
+        let segments = resolve(frame, info).unwrap().collect::<Vec<_>>();
+        assert_eq!(segments.len(), 3);
+        let mut payload = Vec::new();
+        for (i, (segment, info)) in segments.iter().enumerate() {
+            assert_checksums(segment);
+            assert_eq!(u16::from_be_bytes([segment[20], segment[21]]), IP_FLAG_DF);
+            assert_eq!(info.gso, None);
+            assert_eq!(info.original_length, Some(segment.len()));
+
+            let seq = u32::from_be_bytes([segment[38], segment[39], segment[40], segment[41]]);
+            assert_eq!(seq, 0xFFFF_FF00u32.wrapping_add(i as u32 * 1460));
+            let last = i == 2;
+            assert_eq!(segment[47] & (TCP_FLAG_FIN | TCP_FLAG_PSH) != 0, last);
+            payload.extend_from_slice(&segment[54..]);
+        }
+        assert_eq!(segments[0].0.len(), 54 + 1460);
+        assert_eq!(segments[2].0.len(), 54 + 80);
+        assert_eq!(payload, (0..3000).map(|i| i as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_segment_udp() {
+        let mut udp = vec![0u8; 8];
+        udp[4..6].copy_from_slice(&2008u16.to_be_bytes());
+        udp[6] = 1;
+        udp.extend((0..2000).map(|i| i as u8));
+        let frame = ipv4_frame(IP_PROTOCOL_UDP, &udp);
+
+        let gso = |gso_type, size| FrameInfo {
+            gso: Some(Gso {
+                gso_type,
+                size,
+                ecn: false,
+            }),
+            ..FrameInfo::default()
+        };
+
+        let datagrams = resolve(frame.clone(), gso(GsoType::UdpL4, 1000))
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(datagrams.len(), 2);
+        for (datagram, _) in &datagrams {
+            assert_checksums(datagram);
+            assert_eq!(u16::from_be_bytes([datagram[38], datagram[39]]), 1008);
+        }
+
+        let fragments = resolve(frame, gso(GsoType::Udp, 1004))
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(fragments.len(), 3);
+        let offsets: Vec<_> = fragments
+            .iter()
+            .map(|(fragment, _)| {
+                assert_checksums(fragment);
+                u16::from_be_bytes([fragment[20], fragment[21]])
+            })
+            .collect();
+        assert_eq!(offsets, vec![IP_FLAG_MF, IP_FLAG_MF | 125, 250]);
+    }
+
+    #[test]
+    fn test_reject() {
+        let frame = tcp_super_frame(100);
+        let truncated = FrameInfo {
+            original_length: Some(frame.len() + 1),
+            ..FrameInfo::default()
+        };
+        assert!(resolve(frame.clone(), truncated).is_err());
+
+        let tcp_v6 = FrameInfo {
+            gso: Some(Gso {
+                gso_type: GsoType::TcpV6,
+                size: 1440,
+                ecn: false,
+            }),
+            ..FrameInfo::default()
+        };
+        assert!(resolve(frame, tcp_v6).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use super::device::LinkDevice;
+use super::header::{self, VlanTags};
+use super::MacAddress;
+use crate::packet::PacketBuf;
+use crate::socket::FrameInfo;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::prelude::*;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+type VlanId = (Option<u16>, Option<u16>);
+type Frame = (PacketBuf, FrameInfo);
+
+fn vlan_id(vlan: &VlanTags) -> VlanId {
+    (vlan.outer.map(|tag| tag.vid), vlan.inner.map(|tag| tag.vid))
+}
+
+/// Shares one link among several hosts on different VLANs, like a trunk port of a switch.
+/// Each frame received on the link is handed to the port of its VLAN; frames of VLANs
+/// without a port are dropped.
+///
+/// Whichever port is polled receives from the link on behalf of all of them.
+#[derive(Clone)]
+pub struct VlanTrunk<D: LinkDevice> {
+    link: D,
+    ports: Arc<Mutex<HashMap<VlanId, UnboundedSender<Frame>>>>,
+}
+
+/// The `LinkDevice` of a host on one VLAN of a `VlanTrunk`. The tags are left in the frames,
+/// so the driver using the port must be bound to the same VLAN with `set_vlan`.
+#[derive(Clone)]
+pub struct VlanPort<D: LinkDevice> {
+    vlan: VlanTags,
+    mac_addr: MacAddress,
+    trunk: VlanTrunk<D>,
+    receiver: Arc<Mutex<UnboundedReceiver<Frame>>>,
+}
+
+impl<D: LinkDevice> VlanTrunk<D> {
+    pub fn new(link: D) -> Self {
+        VlanTrunk {
+            link,
+            ports: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Adds the port of `vlan`, replacing the previous one. `VlanTags::default()` stands
+    /// for the untagged frames.
+    pub fn connect(&self, vlan: VlanTags, mac_addr: MacAddress) -> VlanPort<D> {
+        let (sender, receiver) = unbounded();
+        self.ports.lock().unwrap().insert(vlan_id(&vlan), sender);
+
+        VlanPort {
+            vlan,
+            mac_addr,
+            trunk: self.clone(),
+            receiver: Arc::new(Mutex::new(receiver)),
+        }
+    }
+
+    pub fn link(&self) -> &D {
+        &self.link
+    }
+}
+
+impl<D: LinkDevice> VlanPort<D> {
+    pub fn vlan(&self) -> VlanTags {
+        self.vlan
+    }
+}
+
+impl<D: LinkDevice> LinkDevice for VlanPort<D> {
+    fn send(&self, frame: &[u8]) -> io::Result<usize> {
+        self.trunk.link.send(frame)
+    }
+
+    fn poll_recv(&self, cx: &mut Context) -> Poll<io::Result<(PacketBuf, FrameInfo)>> {
+        let own = vlan_id(&self.vlan);
+        loop {
+            if let Poll::Ready(Some(frame)) = self.receiver.lock().unwrap().poll_next_unpin(cx) {
+                return Poll::Ready(Ok(frame));
+            }
+
+            let (frame, info) = match self.trunk.link.poll_recv(cx) {
+                Poll::Ready(Ok(received)) => received,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            };
+            let id = match header::frame_vlan_tags(&frame, &info) {
+                Some(vlan) => vlan_id(&vlan),
+                None => continue,
+            };
+            if id == own {
+                return Poll::Ready(Ok((frame, info)));
+            }
+            if let Some(port) = self.trunk.ports.lock().unwrap().get(&id) {
+                // A port whose receiver is gone has just been unplugged.
+                let _ = port.unbounded_send((frame, info));
+            }
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        self.trunk.link.mtu()
+    }
+
+    fn hardware_address(&self) -> MacAddress {
+        self.mac_addr
+    }
+
+    fn join_multicast(&self, group: MacAddress) -> io::Result<()> {
+        self.trunk.link.join_multicast(group)
+    }
+
+    fn leave_multicast(&self, group: MacAddress) -> io::Result<()> {
+        self.trunk.link.leave_multicast(group)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::arp::EtherIpResolver;
+    use crate::ether::driver::EthernetDriver;
+    use crate::ether::header::{VlanTag, VlanTags};
+    use crate::ether::hub::fixtures::run_until;
+    use crate::ether::hub::{VirtualHub, VirtualPort};
+    use crate::ether::vlan::*;
+    use crate::ip::{IpAddress, IpDriver};
+
+    type Driver = EthernetDriver<EtherIpResolver, IpDriver, VlanPort<VirtualPort>>;
+
+    /// The host `10.0.0.1` on `vlan` of `trunk`.
+    fn vlan_host(trunk: &VlanTrunk<VirtualPort>, vlan: VlanTags, mac_addr: MacAddress) -> Driver {
+        let mut driver = Driver::new(
+            IpAddress::new_be_bytes([10, 0, 0, 1]),
+            false,
+            trunk.connect(vlan, mac_addr),
+        );
+        driver.set_vlan(vlan);
+        driver
+    }
+
+    #[test]
+    fn test_trunk() {
+        let hub = VirtualHub::new();
+        let trunk = VlanTrunk::new(hub.connect(MacAddress::new([0x02, 0, 0, 0, 0, 0xFF])));
+        let vlan10 = VlanTags::single(VlanTag::new(10));
+        let qinq = VlanTags::double(VlanTag::service(100), VlanTag::new(20));
+
+        // Hosts sharing the address 10.0.0.1, told apart by their VLANs only.
+        let mac10 = MacAddress::new([0x02, 0, 0, 0, 0, 10]);
+        let mac20 = MacAddress::new([0x02, 0, 0, 0, 0, 20]);
+        let untagged = MacAddress::new([0x02, 0, 0, 0, 0, 1]);
+        let mut hosts = vec![
+            vlan_host(&trunk, vlan10, mac10),
+            vlan_host(&trunk, qinq, mac20),
+            vlan_host(&trunk, VlanTags::default(), untagged),
+        ];
+
+        // The clients share a trunk of their own, on the other side of the hub.
+        let client_trunk = VlanTrunk::new(hub.connect(MacAddress::new([0x02, 0, 0, 0, 1, 0xFF])));
+        let mut client = |n, vlan| {
+            let mut driver = vlan_host(&client_trunk, vlan, MacAddress::new([0x02, 0, 0, 0, 1, n]));
+            let resolved = driver.resolve(IpAddress::new_be_bytes([10, 0, 0, 1]));
+            hosts.push(driver);
+            resolved
+        };
+        let resolved = future::join3(
+            client(10, vlan10),
+            client(20, qinq),
+            client(1, VlanTags::default()),
+        );
+        assert_eq!(
+            run_until(resolved, hosts),
+            (Some(mac10), Some(mac20), Some(untagged))
+        );
+    }
+}
@@ -12,6 +12,66 @@ pub enum IpError {
     #[fail(display = "invalid checksum")]
     InvalidChecksum,
 
+    #[fail(display = "unsupported IP version: {}", _0)]
+    UnsupportedVersion(u8),
+
+    #[fail(display = "invalid header length: {} words", _0)]
+    InvalidHeaderLength(u8),
+
+    #[fail(
+        display = "total length {} exceeds the {} bytes received",
+        total_length, received
+    )]
+    InvalidTotalLength { total_length: u16, received: usize },
+
+    #[fail(display = "invalid source address: {:?}", _0)]
+    InvalidSourceAddress(super::IpAddress),
+
+    #[fail(display = "packet to another host: {:?}", _0)]
+    UnacceptableDestination(super::IpAddress),
+
+    #[fail(display = "unicast packet to {:?} in a link-layer broadcast", _0)]
+    UnicastInLinkBroadcast(super::IpAddress),
+
+    #[fail(display = "invalid fragment")]
+    InvalidFragment,
+
+    #[fail(display = "overlapping fragments")]
+    OverlappingFragments,
+
+    #[fail(display = "no memory left to reassemble fragments")]
+    ReassemblyMemoryExceeded,
+
+    #[fail(display = "invalid option: {}", _0)]
+    InvalidOption(u8),
+
+    #[fail(display = "source routed packet rejected")]
+    SourceRouteRejected,
+
+    #[fail(
+        display = "datagram of {} bytes too large for MTU {} and not to be fragmented",
+        size, mtu
+    )]
+    FragmentationNeeded { size: usize, mtu: usize },
+
+    #[fail(display = "invalid route: {:?}", _0)]
+    InvalidRoute(super::route::Route),
+
+    #[fail(display = "invalid netmask: {:?}", _0)]
+    InvalidNetmask(super::IpAddress),
+
+    #[fail(display = "invalid interface address: {:?}", _0)]
+    InvalidAddress(super::InterfaceAddress),
+
+    #[fail(display = "no address on the interface")]
+    NoAddress,
+
+    #[fail(display = "protocol {} is handled by the driver", _0)]
+    ReservedProtocol(u8),
+
+    #[fail(display = "not a multicast group: {:?}", _0)]
+    NotMulticastGroup(super::IpAddress),
+
     #[fail(display = "{}", _0)]
     IcmpError(#[fail(cause)] super::icmp::error::IcmpError),
 }
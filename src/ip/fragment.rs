@@ -0,0 +1,649 @@
+use super::error::IpError;
+use super::header::{IpHeaderWithoutOptions, IP_FLAG_DF, IP_FLAG_MF, IP_VERSION_4};
+use super::options;
+use super::IpAddress;
+use crate::packet::PacketBuf;
+use crate::utils;
+use map_struct::Mappable;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// How long the fragments of a datagram are kept, the upper bound RFC 1122, 3.3.2 suggests
+/// being 60 to 120 seconds and Linux using 30.
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many bytes of fragments may be held at once, over all datagrams.
+pub const DEFAULT_REASSEMBLY_MEMORY: usize = 1024 * 1024;
+/// How many datagrams may be reassembled at once.
+pub const DEFAULT_MAX_REASSEMBLIES: usize = 1024;
+/// The bytes counted for each datagram besides its fragments, for its header and its
+/// bookkeeping, so that even datagrams without data take their share of the memory.
+pub const REASSEMBLY_OVERHEAD: usize = 128;
+
+const MAX_DATAGRAM_LEN: usize = 65535;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1FFF;
+/// The bytes of data quoted with the header by ICMP errors (RFC 792).
+const QUOTED_DATA_LEN: usize = 8;
+
+/// The fragments of one datagram share these (RFC 791).
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct FragmentKey {
+    pub src: IpAddress,
+    pub dst: IpAddress,
+    pub protocol: u8,
+    pub identification: u16,
+}
+
+impl FragmentKey {
+    pub fn of(header: &IpHeaderWithoutOptions) -> Self {
+        FragmentKey {
+            src: header.src_addr(),
+            dst: header.dst_addr(),
+            protocol: header.protocol(),
+            identification: header.identification(),
+        }
+    }
+}
+
+/// Whether the packet is only a part of a datagram.
+pub fn is_fragment(header: &IpHeaderWithoutOptions) -> bool {
+    header.flags_fragment_offset() & (IP_FLAG_MF | FRAGMENT_OFFSET_MASK) != 0
+}
+
+/// Splits `packet`, a whole datagram or a fragment, into fragments of at most `mtu` bytes
+/// (RFC 791, 3.2). Fails if it is too large but must not be fragmented.
+pub fn fragment(packet: PacketBuf, mtu: usize) -> Result<Vec<PacketBuf>, IpError> {
+    if packet.len() <= mtu {
+        return Ok(vec![packet]);
+    }
+    let (header, _) = IpHeaderWithoutOptions::mapped(&packet).ok_or(IpError::InvalidIpPacket)?;
+    let flags_fragment_offset = header.flags_fragment_offset();
+    if flags_fragment_offset & IP_FLAG_DF != 0 {
+        return Err(IpError::FragmentationNeeded {
+            size: packet.len(),
+            mtu,
+        });
+    }
+    let header_len = header.header_len();
+    let base_len = mem::size_of::<IpHeaderWithoutOptions>();
+    let payload = packet
+        .get(header_len..)
+        .filter(|_| header_len >= base_len)
+        .ok_or(IpError::InvalidIpPacket)?;
+    // Fragments after the first one only carry the options with the copied flag.
+    let later_header = [
+        &packet[..base_len],
+        &options::copied_options(&packet[base_len..header_len])?,
+    ]
+    .concat();
+
+    let offset = (flags_fragment_offset & FRAGMENT_OFFSET_MASK) as usize * 8;
+    let mut fragments = Vec::new();
+    let mut start = 0;
+    while start < payload.len() {
+        let header_bytes = if start == 0 {
+            &packet[..header_len]
+        } else {
+            &later_header[..]
+        };
+        // All but the last fragment carry a multiple of 8 bytes.
+        let chunk_len = mtu.saturating_sub(header_bytes.len()) & !7;
+        if chunk_len == 0 {
+            return Err(IpError::FragmentationNeeded {
+                size: packet.len(),
+                mtu,
+            });
+        }
+        let end = payload.len().min(start + chunk_len);
+        // The last fragment of a fragment keeps its more fragments flag.
+        let more = end < payload.len() || flags_fragment_offset & IP_FLAG_MF != 0;
+        let fragment_offset = (offset + start) / 8;
+        if fragment_offset > FRAGMENT_OFFSET_MASK as usize {
+            return Err(IpError::InvalidIpPacket);
+        }
+        let flags = if more { IP_FLAG_MF } else { 0 };
+
+        let mut fragment = PacketBuf::from_payload(&payload[start..end]);
+        let fragment_header = fragment.prepend(header_bytes.len());
+        fragment_header.copy_from_slice(header_bytes);
+        let (ip_header, _) = IpHeaderWithoutOptions::mapped_mut(fragment_header).unwrap();
+        ip_header.set_version_ihl(IP_VERSION_4, (header_bytes.len() / 4) as u8);
+        ip_header.set_total_length((header_bytes.len() + end - start) as u16);
+        ip_header.set_flags_fragment_offset(flags | fragment_offset as u16);
+        ip_header.set_checksum(0);
+        let checksum = utils::checksum(fragment_header);
+        let (ip_header, _) = IpHeaderWithoutOptions::mapped_mut(fragment_header).unwrap();
+        ip_header.set_checksum(checksum);
+        fragments.push(fragment);
+        start = end;
+    }
+    Ok(fragments)
+}
+
+/// A datagram whose fragments did not all arrive in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expired {
+    pub key: FragmentKey,
+    /// The header and the first 8 bytes of data of the first fragment, if it arrived.
+    /// Only then is the sender told of the loss (RFC 1122, 3.2.2.4).
+    pub first: Option<Vec<u8>>,
+}
+
+/// A datagram put back together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    /// The header of the first fragment, with all the options.
+    pub header: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+struct Reassembly {
+    started: Instant,
+    /// The header of the fragment at offset 0.
+    header: Option<Vec<u8>>,
+    /// The data received so far by offset, none overlapping another.
+    fragments: BTreeMap<usize, Vec<u8>>,
+    /// The length of the data, known once the last fragment arrived.
+    total_len: Option<usize>,
+    size: usize,
+    /// Set once fragments overlapped, which drops the datagram along with the fragments
+    /// still to come (RFC 5722).
+    discarded: bool,
+}
+
+impl Reassembly {
+    fn new(now: Instant) -> Self {
+        Reassembly {
+            started: now,
+            header: None,
+            fragments: BTreeMap::new(),
+            total_len: None,
+            size: REASSEMBLY_OVERHEAD,
+            discarded: false,
+        }
+    }
+
+    /// Whether data at `offset..end` fits, the last fragment ending at `end` if `last`.
+    fn fits(&self, offset: usize, end: usize, last: bool) -> bool {
+        let before = self.fragments.range(..offset).next_back();
+        if before.is_some_and(|(&start, data)| start + data.len() > offset) {
+            return false;
+        }
+        let after = self.fragments.range(offset..).next();
+        if after.is_some_and(|(&start, _)| start < end) {
+            return false;
+        }
+        match self.total_len {
+            Some(total_len) => end <= total_len && (!last || end == total_len),
+            None => !last || self.fragments.range(end..).next().is_none(),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        let mut expected = 0;
+        for (&offset, data) in &self.fragments {
+            if offset != expected {
+                return false;
+            }
+            expected += data.len();
+        }
+        Some(expected) == self.total_len
+    }
+}
+
+/// Puts fragmented datagrams back together (RFC 791, RFC 815).
+pub struct Reassembler {
+    reassemblies: HashMap<FragmentKey, Reassembly>,
+    timeout: Duration,
+    memory_limit: usize,
+    max_reassemblies: usize,
+    memory: usize,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Reassembler::new()
+    }
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Reassembler {
+            reassemblies: HashMap::new(),
+            timeout: DEFAULT_REASSEMBLY_TIMEOUT,
+            memory_limit: DEFAULT_REASSEMBLY_MEMORY,
+            max_reassemblies: DEFAULT_MAX_REASSEMBLIES,
+            memory: 0,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn memory_limit(&self) -> usize {
+        self.memory_limit
+    }
+
+    pub fn set_memory_limit(&mut self, memory_limit: usize) {
+        self.memory_limit = memory_limit;
+    }
+
+    pub fn max_reassemblies(&self) -> usize {
+        self.max_reassemblies
+    }
+
+    pub fn set_max_reassemblies(&mut self, max_reassemblies: usize) {
+        self.max_reassemblies = max_reassemblies;
+    }
+
+    /// The bytes of fragments held now, with `REASSEMBLY_OVERHEAD` for each datagram.
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Drops the datagrams older than the timeout.
+    pub fn expire(&mut self, now: Instant) -> Vec<Expired> {
+        let timeout = self.timeout;
+        let keys: Vec<_> = self
+            .reassemblies
+            .iter()
+            .filter(|(_, reassembly)| now.duration_since(reassembly.started) >= timeout)
+            .map(|(&key, _)| key)
+            .collect();
+
+        keys.into_iter()
+            .map(|key| {
+                let Reassembly {
+                    header, fragments, ..
+                } = self.remove(&key);
+                let first = header.map(|header| {
+                    let data = &fragments[&0];
+                    [&header, &data[..data.len().min(QUOTED_DATA_LEN)]].concat()
+                });
+                Expired { key, first }
+            })
+            .collect()
+    }
+
+    /// Adds a fragment, with `header` the start of `header_bytes` and `payload` the data
+    /// following them. Returns the datagram once it is complete.
+    /// Call `expire` beforehand, or a stale datagram may take the fragment.
+    pub fn insert(
+        &mut self,
+        header: &IpHeaderWithoutOptions,
+        header_bytes: &[u8],
+        payload: &[u8],
+        now: Instant,
+    ) -> Result<Option<Datagram>, IpError> {
+        let offset = (header.flags_fragment_offset() & FRAGMENT_OFFSET_MASK) as usize * 8;
+        let last = header.flags_fragment_offset() & IP_FLAG_MF == 0;
+        let end = offset + payload.len();
+        // Fragments carry data, all but the last a multiple of 8 bytes, and the datagram
+        // must fit in the total length field.
+        if payload.is_empty()
+            || (!last && !payload.len().is_multiple_of(8))
+            || header_bytes.len() + end > MAX_DATAGRAM_LEN
+        {
+            return Err(IpError::InvalidFragment);
+        }
+
+        let key = FragmentKey::of(header);
+        if let Some(reassembly) = self.reassemblies.get_mut(&key) {
+            if reassembly.discarded {
+                return Err(IpError::OverlappingFragments);
+            }
+            // A retransmitted fragment is no overlap.
+            if reassembly.fragments.get(&offset).map(Vec::as_slice) == Some(payload)
+                && (!last || reassembly.total_len == Some(end))
+            {
+                return Ok(None);
+            }
+            if !reassembly.fits(offset, end, last) {
+                // The entry stays, still counted, to drop the rest of the datagram.
+                self.memory -= reassembly.size - REASSEMBLY_OVERHEAD;
+                reassembly.size = REASSEMBLY_OVERHEAD;
+                reassembly.fragments.clear();
+                reassembly.header = None;
+                reassembly.discarded = true;
+                return Err(IpError::OverlappingFragments);
+            }
+        }
+
+        let new = !self.reassemblies.contains_key(&key);
+        let size = payload.len() + if new { REASSEMBLY_OVERHEAD } else { 0 };
+        if size > self.memory_limit {
+            return Err(IpError::ReassemblyMemoryExceeded);
+        }
+        while self.memory + size > self.memory_limit
+            || (new && self.reassemblies.len() >= self.max_reassemblies)
+        {
+            // The oldest datagram is the least likely to complete.
+            let oldest = self
+                .reassemblies
+                .iter()
+                .filter(|(&other, _)| other != key)
+                .min_by_key(|(_, reassembly)| reassembly.started)
+                .map(|(&other, _)| other);
+            match oldest {
+                Some(oldest) => {
+                    self.remove(&oldest);
+                }
+                None => return Err(IpError::ReassemblyMemoryExceeded),
+            }
+        }
+
+        let reassembly = self
+            .reassemblies
+            .entry(key)
+            .or_insert_with(|| Reassembly::new(now));
+        reassembly.fragments.insert(offset, payload.to_vec());
+        reassembly.size += payload.len();
+        self.memory += size;
+        if offset == 0 {
+            reassembly.header = Some(header_bytes.to_vec());
+        }
+        if last {
+            reassembly.total_len = Some(end);
+        }
+
+        if !reassembly.is_complete() {
+            return Ok(None);
+        }
+        let reassembly = self.remove(&key);
+        Ok(Some(Datagram {
+            header: reassembly.header.unwrap(),
+            payload: reassembly.fragments.into_values().flatten().collect(),
+        }))
+    }
+
+    fn remove(&mut self, key: &FragmentKey) -> Reassembly {
+        let reassembly = self.reassemblies.remove(key).unwrap();
+        self.memory -= reassembly.size;
+        reassembly
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builder::Ipv4Builder;
+    use crate::ip::error::IpError;
+    use crate::ip::fragment::*;
+    use crate::ip::header::*;
+    use crate::ip::options::{parse_options, IpOption, Route};
+    use crate::packet::PacketBuf;
+    use map_struct::Mappable;
+
+    const SRC: IpAddress = IpAddress::new_be_bytes([10, 0, 0, 2]);
+    const DST: IpAddress = IpAddress::new_be_bytes([10, 0, 0, 1]);
+
+    fn build_fragment(identification: u16, offset: usize, more: bool, data: &[u8]) -> PacketBuf {
+        let flags = if more { IP_FLAG_MF } else { 0 };
+        Ipv4Builder::new(SRC, DST)
+            .identification(identification)
+            .flags_fragment_offset(flags | (offset / 8) as u16)
+            .build(0xFD, PacketBuf::from_payload(data))
+    }
+
+    fn insert(
+        reassembler: &mut Reassembler,
+        packet: &[u8],
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>, IpError> {
+        let (header, payload) = IpHeaderWithoutOptions::mapped(packet).unwrap();
+        let datagram = reassembler.insert(header, &packet[..20], payload, now)?;
+        Ok(datagram.map(|datagram| datagram.payload))
+    }
+
+    #[test]
+    fn test_reassembly() {
+        let data: Vec<u8> = (0..40).collect();
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+
+        // Out of order, with a retransmission.
+        let last = build_fragment(1, 32, false, &data[32..]);
+        let middle = build_fragment(1, 16, true, &data[16..32]);
+        let first = build_fragment(1, 0, true, &data[..16]);
+        assert_eq!(insert(&mut reassembler, &last, now).unwrap(), None);
+        assert_eq!(insert(&mut reassembler, &middle, now).unwrap(), None);
+        assert_eq!(insert(&mut reassembler, &middle, now).unwrap(), None);
+        assert_eq!(reassembler.memory(), 24 + REASSEMBLY_OVERHEAD);
+        assert_eq!(insert(&mut reassembler, &first, now).unwrap(), Some(data));
+        assert_eq!(reassembler.memory(), 0);
+
+        let unaligned = build_fragment(2, 0, true, &[0; 12]);
+        assert!(matches!(
+            insert(&mut reassembler, &unaligned, now),
+            Err(IpError::InvalidFragment)
+        ));
+        let too_long = build_fragment(2, 65528, false, &[0; 8]);
+        assert!(matches!(
+            insert(&mut reassembler, &too_long, now),
+            Err(IpError::InvalidFragment)
+        ));
+        let empty = build_fragment(2, 8, false, &[]);
+        assert!(matches!(
+            insert(&mut reassembler, &empty, now),
+            Err(IpError::InvalidFragment)
+        ));
+        assert_eq!(reassembler.memory(), 0);
+    }
+
+    #[test]
+    fn test_overlap() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+
+        insert(&mut reassembler, &build_fragment(1, 0, true, &[1; 16]), now).unwrap();
+        assert!(matches!(
+            insert(&mut reassembler, &build_fragment(1, 8, true, &[2; 16]), now),
+            Err(IpError::OverlappingFragments)
+        ));
+        assert_eq!(reassembler.memory(), REASSEMBLY_OVERHEAD);
+        // The rest of the datagram is dropped as well.
+        assert!(matches!(
+            insert(
+                &mut reassembler,
+                &build_fragment(1, 16, false, &[3; 8]),
+                now
+            ),
+            Err(IpError::OverlappingFragments)
+        ));
+
+        // A last fragment in the middle of the received data.
+        insert(
+            &mut reassembler,
+            &build_fragment(2, 16, true, &[1; 16]),
+            now,
+        )
+        .unwrap();
+        assert!(matches!(
+            insert(&mut reassembler, &build_fragment(2, 0, false, &[2; 8]), now),
+            Err(IpError::OverlappingFragments)
+        ));
+        // The same retransmitted offset with other data.
+        insert(&mut reassembler, &build_fragment(3, 0, true, &[1; 16]), now).unwrap();
+        assert!(matches!(
+            insert(&mut reassembler, &build_fragment(3, 0, true, &[2; 16]), now),
+            Err(IpError::OverlappingFragments)
+        ));
+
+        // Discarded datagrams only go away with the timeout, and without any error.
+        let expired = reassembler.expire(now + DEFAULT_REASSEMBLY_TIMEOUT);
+        assert_eq!(expired.len(), 3);
+        assert!(expired.iter().all(|expired| expired.first.is_none()));
+    }
+
+    #[test]
+    fn test_expire() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        reassembler.set_timeout(Duration::from_secs(10));
+
+        let first = build_fragment(1, 0, true, &[7; 16]);
+        insert(&mut reassembler, &first, now).unwrap();
+        insert(&mut reassembler, &build_fragment(2, 8, false, &[7; 8]), now).unwrap();
+        assert!(reassembler.expire(now + Duration::from_secs(9)).is_empty());
+
+        let mut expired = reassembler.expire(now + Duration::from_secs(10));
+        expired.sort_by_key(|expired| expired.key.identification);
+        assert_eq!(expired.len(), 2);
+        assert_eq!(expired[0].key.src, SRC);
+        assert_eq!(expired[0].first.as_deref(), Some(&first[..28]));
+        assert_eq!(expired[1].first, None);
+        assert_eq!(reassembler.memory(), 0);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        reassembler.set_memory_limit(2 * (16 + REASSEMBLY_OVERHEAD));
+
+        insert(&mut reassembler, &build_fragment(1, 0, true, &[1; 16]), now).unwrap();
+        let later = now + Duration::from_secs(1);
+        insert(
+            &mut reassembler,
+            &build_fragment(2, 0, true, &[2; 16]),
+            later,
+        )
+        .unwrap();
+        // The oldest datagram makes room.
+        insert(
+            &mut reassembler,
+            &build_fragment(3, 0, true, &[3; 16]),
+            later,
+        )
+        .unwrap();
+        assert_eq!(reassembler.memory(), 2 * (16 + REASSEMBLY_OVERHEAD));
+        let expired = reassembler.expire(now + DEFAULT_REASSEMBLY_TIMEOUT * 2);
+        assert!(expired
+            .iter()
+            .all(|expired| expired.key.identification != 1));
+
+        // A datagram larger than the limit is never held.
+        let large = build_fragment(4, 0, true, &[4; 2 * REASSEMBLY_OVERHEAD]);
+        assert!(matches!(
+            insert(&mut reassembler, &large, now),
+            Err(IpError::ReassemblyMemoryExceeded)
+        ));
+    }
+
+    #[test]
+    fn test_max_reassemblies() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        reassembler.set_max_reassemblies(2);
+
+        // Discarded datagrams hold no data, but are evicted all the same.
+        insert(&mut reassembler, &build_fragment(1, 0, true, &[1; 16]), now).unwrap();
+        assert!(insert(&mut reassembler, &build_fragment(1, 8, true, &[1; 16]), now).is_err());
+        let later = now + Duration::from_secs(1);
+        for identification in 2..10 {
+            let fragment = build_fragment(identification, 8, false, &[2; 8]);
+            insert(&mut reassembler, &fragment, later).unwrap();
+        }
+        assert_eq!(reassembler.memory(), 2 * (8 + REASSEMBLY_OVERHEAD));
+        let expired = reassembler.expire(later + DEFAULT_REASSEMBLY_TIMEOUT);
+        let identifications: Vec<_> = expired
+            .iter()
+            .map(|expired| expired.key.identification)
+            .collect();
+        assert_eq!(identifications.len(), 2);
+        assert!(!identifications.contains(&1));
+    }
+
+    #[test]
+    fn test_fragment() {
+        let data: Vec<u8> = (0..100).collect();
+        let datagram = Ipv4Builder::new(SRC, DST)
+            .identification(5)
+            .flags_fragment_offset(0)
+            .build(0xFD, PacketBuf::from_payload(&data));
+        assert_eq!(fragment(datagram.clone(), 120).unwrap().len(), 1);
+
+        let fragments = fragment(datagram.clone(), 60).unwrap();
+        let mut reassembler = Reassembler::new();
+        let now = Instant::now();
+        let lens: Vec<_> = fragments.iter().map(|fragment| fragment.len()).collect();
+        assert_eq!(lens, [60, 60, 40]);
+        for (i, fragment) in fragments.iter().enumerate().rev() {
+            let (header, _) = IpHeaderWithoutOptions::mapped(fragment).unwrap();
+            assert!(header.is_valid(fragment));
+            assert_eq!(header.total_length() as usize, fragment.len());
+            assert_eq!(header.identification(), 5);
+            let more = if i < 2 { IP_FLAG_MF } else { 0 };
+            assert_eq!(header.flags_fragment_offset(), more | (i * 5) as u16);
+            let reassembled = insert(&mut reassembler, fragment, now).unwrap();
+            assert_eq!(reassembled.is_some(), i == 0);
+        }
+
+        // A fragment splits into fragments of the same datagram.
+        let refragmented = fragment(fragments[1].clone(), 36).unwrap();
+        assert_eq!(refragmented.len(), 3);
+        for fragment in refragmented.iter().chain(&[fragments[0].clone()]) {
+            assert_eq!(insert(&mut reassembler, fragment, now).unwrap(), None);
+        }
+        assert_eq!(
+            insert(&mut reassembler, &fragments[2], now).unwrap(),
+            Some(data)
+        );
+
+        let dont_fragment =
+            Ipv4Builder::new(SRC, DST).build(0xFD, PacketBuf::from_payload(&[0; 100]));
+        assert!(matches!(
+            fragment(dont_fragment, 60),
+            Err(IpError::FragmentationNeeded { size: 120, mtu: 60 })
+        ));
+        assert!(fragment(datagram, 27).is_err());
+    }
+
+    #[test]
+    fn test_fragment_options() {
+        let options = [
+            IpOption::RecordRoute(Route::with_capacity(2)),
+            IpOption::RouterAlert(0),
+        ];
+        let datagram = Ipv4Builder::new(SRC, DST)
+            .flags_fragment_offset(0)
+            .options(&options)
+            .build(0xFD, PacketBuf::from_payload(&[0x77; 100]));
+        let fragments = fragment(datagram, 64).unwrap();
+        let lens: Vec<_> = fragments.iter().map(|fragment| fragment.len()).collect();
+        assert_eq!(lens, [60, 64, 60]);
+
+        // Only the router alert is copied beyond the first fragment.
+        let mut reassembler = Reassembler::new();
+        let now = Instant::now();
+        for (i, fragment) in fragments.iter().enumerate().rev() {
+            let (header, payload) = IpHeaderWithoutOptions::mapped(fragment).unwrap();
+            let header_len = header.header_len();
+            assert_eq!(header_len, if i == 0 { 36 } else { 24 });
+            assert!(header.is_valid(fragment));
+            let header_bytes = &fragment[..header_len];
+            let payload = &payload[header_len - 20..];
+            if i != 0 {
+                assert_eq!(
+                    parse_options(&header_bytes[20..]).unwrap(),
+                    [IpOption::RouterAlert(0)]
+                );
+            }
+            match reassembler
+                .insert(header, header_bytes, payload, now)
+                .unwrap()
+            {
+                Some(datagram) => {
+                    assert_eq!(i, 0);
+                    assert_eq!(parse_options(&datagram.header[20..]).unwrap(), options);
+                    assert_eq!(datagram.payload, [0x77; 100]);
+                }
+                None => assert_ne!(i, 0),
+            }
+        }
+    }
+}
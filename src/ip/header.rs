@@ -1,28 +1,36 @@
 use super::IpAddress;
 use crate::utils::checksum;
+use crate::wire::U16Be;
 use map_struct::Mappable;
 
 #[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
+#[repr(C)]
 pub struct IpHeaderWithoutOptions {
-    pub version_ihl: u8,
-    pub type_of_service: u8,
-    pub total_length: u16,
-    pub identification: u16,
-    pub flags_fragment_offset: u16,
-    pub ttl: u8,
-    pub protocol: u8,
-    pub checksum: u16,
-    pub src_addr: IpAddress,
-    pub dst_addr: IpAddress,
+    version_ihl: u8,
+    type_of_service: u8,
+    total_length: U16Be,
+    identification: U16Be,
+    flags_fragment_offset: U16Be,
+    ttl: u8,
+    protocol: u8,
+    checksum: U16Be,
+    src_addr: IpAddress,
+    dst_addr: IpAddress,
 }
 
 unsafe impl Mappable for IpHeaderWithoutOptions {}
 
+pub const IP_VERSION_4: u8 = 4;
+pub const IP_FLAG_DF: u16 = 0x4000;
+pub const IP_FLAG_MF: u16 = 0x2000;
+
 impl IpHeaderWithoutOptions {
+    /// Whether the checksum of the header at the start of `orig_data` is right.
+    /// `false` if `orig_data` is shorter than the header claims.
     pub fn is_valid(&self, orig_data: &[u8]) -> bool {
-        let ihl = self.version_ihl & 0x0F;
-        checksum(&orig_data[..(ihl << 2) as usize]) == 0
+        orig_data
+            .get(..self.header_len())
+            .is_some_and(|header| checksum(header) == 0)
     }
 
     pub fn version(&self) -> u8 {
@@ -32,4 +40,86 @@ impl IpHeaderWithoutOptions {
     pub fn ihl(&self) -> u8 {
         self.version_ihl & 0x0F
     }
+
+    /// The length of the header with its options, in bytes.
+    pub fn header_len(&self) -> usize {
+        self.ihl() as usize * 4
+    }
+
+    pub fn set_version_ihl(&mut self, version: u8, ihl: u8) {
+        self.version_ihl = (version << 4) | (ihl & 0x0F);
+    }
+
+    pub fn type_of_service(&self) -> u8 {
+        self.type_of_service
+    }
+
+    pub fn set_type_of_service(&mut self, type_of_service: u8) {
+        self.type_of_service = type_of_service;
+    }
+
+    pub fn total_length(&self) -> u16 {
+        self.total_length.get()
+    }
+
+    pub fn set_total_length(&mut self, total_length: u16) {
+        self.total_length.set(total_length);
+    }
+
+    pub fn identification(&self) -> u16 {
+        self.identification.get()
+    }
+
+    pub fn set_identification(&mut self, identification: u16) {
+        self.identification.set(identification);
+    }
+
+    /// The flags and the fragment offset, in units of 8 bytes.
+    pub fn flags_fragment_offset(&self) -> u16 {
+        self.flags_fragment_offset.get()
+    }
+
+    pub fn set_flags_fragment_offset(&mut self, flags_fragment_offset: u16) {
+        self.flags_fragment_offset.set(flags_fragment_offset);
+    }
+
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl;
+    }
+
+    pub fn protocol(&self) -> u8 {
+        self.protocol
+    }
+
+    pub fn set_protocol(&mut self, protocol: u8) {
+        self.protocol = protocol;
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.checksum.get()
+    }
+
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.checksum.set(checksum);
+    }
+
+    pub fn src_addr(&self) -> IpAddress {
+        self.src_addr
+    }
+
+    pub fn set_src_addr(&mut self, src_addr: IpAddress) {
+        self.src_addr = src_addr;
+    }
+
+    pub fn dst_addr(&self) -> IpAddress {
+        self.dst_addr
+    }
+
+    pub fn set_dst_addr(&mut self, dst_addr: IpAddress) {
+        self.dst_addr = dst_addr;
+    }
 }
@@ -1,15 +1,41 @@
 use super::IpAddress;
+use crate::wire::U16Be;
 use map_struct::Mappable;
 
-#[repr(C, packed)]
+#[repr(C)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct EchoPacketWithoutData {
-    pub identifier: u16,
-    pub sequence_id: u16,
+    identifier: U16Be,
+    sequence_id: U16Be,
 }
 
 unsafe impl Mappable for EchoPacketWithoutData {}
 
+impl EchoPacketWithoutData {
+    pub fn new(identifier: u16, sequence_id: u16) -> Self {
+        EchoPacketWithoutData {
+            identifier: U16Be::new(identifier),
+            sequence_id: U16Be::new(sequence_id),
+        }
+    }
+
+    pub fn identifier(&self) -> u16 {
+        self.identifier.get()
+    }
+
+    pub fn set_identifier(&mut self, identifier: u16) {
+        self.identifier.set(identifier);
+    }
+
+    pub fn sequence_id(&self) -> u16 {
+        self.sequence_id.get()
+    }
+
+    pub fn set_sequence_id(&mut self, sequence_id: u16) {
+        self.sequence_id.set(sequence_id);
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct EchoReply {
     pub src: IpAddress,
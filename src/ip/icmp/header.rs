@@ -1,11 +1,38 @@
+use crate::wire::U16Be;
 use map_struct::Mappable;
 
-#[repr(C, packed)]
+#[repr(C)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct IcmpHeader {
-    pub icmp_type: u8,
-    pub code: u8,
-    pub checksum: u16,
+    icmp_type: u8,
+    code: u8,
+    checksum: U16Be,
 }
 
 unsafe impl Mappable for IcmpHeader {}
+
+impl IcmpHeader {
+    pub fn icmp_type(&self) -> u8 {
+        self.icmp_type
+    }
+
+    pub fn set_icmp_type(&mut self, icmp_type: u8) {
+        self.icmp_type = icmp_type;
+    }
+
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    pub fn set_code(&mut self, code: u8) {
+        self.code = code;
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.checksum.get()
+    }
+
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.checksum.set(checksum);
+    }
+}
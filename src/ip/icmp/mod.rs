@@ -3,6 +3,8 @@ pub mod error;
 pub mod header;
 
 use super::IpAddress;
+use crate::builder::IcmpBuilder;
+use crate::packet::PacketBuf;
 use crate::utils;
 use crate::Destination;
 use echo::{EchoPacketWithoutData, EchoReply};
@@ -11,18 +13,35 @@ use futures::channel::mpsc::{channel, Receiver, Sender};
 use header::IcmpHeader;
 use map_struct::Mappable;
 use std::collections::{HashMap, HashSet};
-use std::mem::size_of;
 
 pub const ICMP_PROTOCOL_NUMBER: u8 = 1;
 
 pub const ECHO_REPLY_TYPE: u8 = 0;
 pub const ECHO_TYPE: u8 = 8;
 pub const ECHO_CODE: u8 = 0;
+pub const DESTINATION_UNREACHABLE_TYPE: u8 = 3;
+pub const SOURCE_QUENCH_TYPE: u8 = 4;
+pub const REDIRECT_TYPE: u8 = 5;
+pub const TIME_EXCEEDED_TYPE: u8 = 11;
+pub const PARAMETER_PROBLEM_TYPE: u8 = 12;
+pub const FRAGMENT_REASSEMBLY_TIME_EXCEEDED_CODE: u8 = 1;
+
+/// Whether messages of the type report errors, about which no error is sent (RFC 1122, 3.2.2).
+pub fn is_error_type(icmp_type: u8) -> bool {
+    matches!(
+        icmp_type,
+        DESTINATION_UNREACHABLE_TYPE
+            | SOURCE_QUENCH_TYPE
+            | REDIRECT_TYPE
+            | TIME_EXCEEDED_TYPE
+            | PARAMETER_PROBLEM_TYPE
+    )
+}
 
 const BUFFER_SIZE: usize = 32;
 
 pub enum IcmpReply {
-    Reply { dst: IpAddress, data: Vec<u8> },
+    Reply { dst: IpAddress, data: PacketBuf },
     Nop,
 }
 
@@ -32,32 +51,6 @@ pub struct IcmpDriver {
     used_identifier: HashSet<u16>,
 }
 
-fn construct_echo_packet(
-    icmp_type: u8,
-    echo_wo_data: EchoPacketWithoutData,
-    data: &[u8],
-) -> Vec<u8> {
-    let mut result =
-        vec![0; size_of::<IcmpHeader>() + size_of::<EchoPacketWithoutData>() + data.len()];
-    {
-        let (reply_header, rest) = IcmpHeader::mapped_mut(&mut result).unwrap();
-        reply_header.icmp_type = icmp_type.to_be();
-        reply_header.code = ECHO_CODE.to_be();
-
-        let (reply_packet_wo_data, rest) = EchoPacketWithoutData::mapped_mut(rest).unwrap();
-        reply_packet_wo_data.identifier = echo_wo_data.identifier.to_be();
-        reply_packet_wo_data.sequence_id = echo_wo_data.sequence_id.to_be();
-
-        rest.copy_from_slice(data);
-    }
-    let checksum = utils::checksum(&result);
-    {
-        let (reply_header, _) = IcmpHeader::mapped_mut(&mut result).unwrap();
-        reply_header.checksum = checksum.to_be();
-    }
-    result
-}
-
 impl IcmpDriver {
     pub fn new() -> Self {
         IcmpDriver {
@@ -66,7 +59,7 @@ impl IcmpDriver {
         }
     }
 
-    pub fn register_echo(&mut self, data: &[u8]) -> Option<(u16, Vec<u8>, Receiver<EchoReply>)> {
+    pub fn register_echo(&mut self, data: &[u8]) -> Option<(u16, PacketBuf, Receiver<EchoReply>)> {
         let identifier = (0..=(std::u16::MAX))
             .filter(|&v| !self.used_identifier.contains(&v))
             .next()?;
@@ -77,14 +70,7 @@ impl IcmpDriver {
 
         Some((
             identifier,
-            construct_echo_packet(
-                ECHO_TYPE,
-                EchoPacketWithoutData {
-                    identifier,
-                    sequence_id: 0,
-                },
-                data,
-            ),
+            IcmpBuilder::echo_request(identifier, 0).build(PacketBuf::from_payload(data)),
             receiver,
         ))
     }
@@ -102,11 +88,11 @@ impl IcmpDriver {
             return Err(IcmpError::InvalidChecksum);
         }
 
-        if u8::from_be(header.code) != ECHO_CODE {
+        if header.code() != ECHO_CODE {
             return Err(IcmpError::Unimplemented);
         }
 
-        match u8::from_be(header.icmp_type) {
+        match header.icmp_type() {
             ECHO_TYPE => {
                 let (id_seq, data) =
                     EchoPacketWithoutData::mapped(payload).ok_or(IcmpError::InvalidIcmpPacket)?;
@@ -118,14 +104,9 @@ impl IcmpDriver {
                     return Ok(IcmpReply::Nop);
                 }
 
-                let result = construct_echo_packet(
-                    ECHO_REPLY_TYPE,
-                    EchoPacketWithoutData {
-                        identifier: u16::from_be(id_seq.identifier),
-                        sequence_id: u16::from_be(id_seq.sequence_id) + 1,
-                    },
-                    data,
-                );
+                // The reply echoes the identifier and the sequence number as they are.
+                let result = IcmpBuilder::echo_reply(id_seq.identifier(), id_seq.sequence_id())
+                    .build(PacketBuf::from_payload(data));
 
                 Ok(IcmpReply::Reply {
                     dst: from,
@@ -138,7 +119,7 @@ impl IcmpDriver {
 
                 let receiver = self
                     .echo_requests
-                    .get_mut(&u16::from_be(id_seq.identifier))
+                    .get_mut(&id_seq.identifier())
                     .ok_or(IcmpError::InvalidIcmpPacket)?;
 
                 println!("- ICMP Echo Reply from {:?}", from);
@@ -146,7 +127,7 @@ impl IcmpDriver {
                 receiver
                     .try_send(EchoReply {
                         src: from,
-                        sequence_id: u16::from_be(id_seq.sequence_id) + 1,
+                        sequence_id: id_seq.sequence_id(),
                         data: data.into(),
                     })
                     .map_err(|_| IcmpError::NoEmptyEchoBuffer)?;
@@ -1,40 +1,108 @@
+use crate::builder::{IcmpBuilder, Ipv4Builder};
+use crate::packet::PacketBuf;
 use crate::Destination;
 use error::IpError;
-use header::IpHeaderWithoutOptions;
+use fragment::{Expired, Reassembler};
+use header::{IpHeaderWithoutOptions, IP_FLAG_DF, IP_VERSION_4};
 use icmp::error::IcmpError;
 use icmp::{IcmpDriver, IcmpReply};
 use map_struct::Mappable;
+use options::{IpOption, OptionPolicy};
+use protocol::{DatagramSender, ProtocolRegistry};
+use route::{Route, RoutingTable};
+use std::collections::HashMap;
 use std::fmt;
 use std::mem;
+use std::time::Instant;
 
 pub mod error;
+pub mod fragment;
 pub mod header;
 pub mod icmp;
+pub mod options;
+pub mod protocol;
+pub mod route;
 
+pub const DEFAULT_TTL: u8 = 64;
+/// The group every host is a member of, 224.0.0.1 (RFC 1112, 4).
+pub const ALL_HOSTS_GROUP: IpAddress = IpAddress::new_be_bytes([224, 0, 0, 1]);
+
+/// An IPv4 address, held in network byte order so that headers can carry it as is.
 #[repr(transparent)]
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
-pub struct IpAddress(u32);
+pub struct IpAddress([u8; 4]);
+
+/// How datagrams to a destination are sent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PathConfig {
+    /// The MTU of the path, if smaller than the one of the link.
+    pub mtu: Option<usize>,
+    /// Whether datagrams carry the don't fragment flag, failing to be sent when too large
+    /// rather than being fragmented.
+    pub dont_fragment: bool,
+}
+
+/// Datagrams are fragmented unless the flag is asked for.
+impl Default for PathConfig {
+    fn default() -> Self {
+        PathConfig {
+            mtu: None,
+            dont_fragment: false,
+        }
+    }
+}
 
 pub enum IpReply {
-    Reply { dst: IpAddress, data: Vec<u8> },
+    Reply { dst: IpAddress, data: PacketBuf },
     Nop,
 }
 
 impl IpAddress {
-    pub fn new_be_bytes(addr: [u8; 4]) -> Self {
-        IpAddress(u32::from_be_bytes(addr))
+    pub const fn new_be_bytes(addr: [u8; 4]) -> Self {
+        IpAddress(addr)
     }
 
-    pub fn from_be_int(addr: u32) -> IpAddress {
-        IpAddress(u32::from_be(addr))
+    /// The address in network byte order.
+    pub fn to_be_bytes(self) -> [u8; 4] {
+        self.0
     }
 
-    pub fn from_be(addr: IpAddress) -> IpAddress {
-        IpAddress(u32::from_be(addr.0))
+    /// Whether the address is a class D group address, 224.0.0.0/4.
+    pub fn is_multicast(self) -> bool {
+        self.0[0] >> 4 == 0xE
     }
 
-    pub fn to_be(addr: IpAddress) -> IpAddress {
-        IpAddress(u32::to_be(addr.0))
+    /// Whether the address is the limited broadcast address, 255.255.255.255.
+    pub fn is_broadcast(self) -> bool {
+        self.0 == [0xFF; 4]
+    }
+
+    /// Whether the address is 0.0.0.0, which a host uses before it knows its own.
+    pub fn is_unspecified(self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    /// Whether the address is in 127.0.0.0/8, which never appears on a network.
+    pub fn is_loopback(self) -> bool {
+        self.0[0] == 127
+    }
+
+    /// Whether the address is a class E address, 240.0.0.0/4, the limited broadcast
+    /// address included.
+    pub fn is_reserved(self) -> bool {
+        self.0[0] >> 4 == 0xF
+    }
+}
+
+impl From<u32> for IpAddress {
+    fn from(addr: u32) -> Self {
+        IpAddress(addr.to_be_bytes())
+    }
+}
+
+impl From<IpAddress> for u32 {
+    fn from(addr: IpAddress) -> Self {
+        u32::from_be_bytes(addr.0)
     }
 }
 
@@ -42,8 +110,7 @@ unsafe impl Mappable for IpAddress {}
 
 impl fmt::Debug for IpAddress {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let addrs = self.0.to_be_bytes();
-        for (i, addr) in addrs.iter().enumerate() {
+        for (i, addr) in self.0.iter().enumerate() {
             if i != 0 {
                 write!(f, ".")?;
             }
@@ -53,41 +120,469 @@ impl fmt::Debug for IpAddress {
     }
 }
 
+/// An address of the host, with the length of the prefix of its subnet.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    pub addr: IpAddress,
+    pub prefix_len: u8,
+}
+
+impl InterfaceAddress {
+    pub fn new(addr: IpAddress, prefix_len: u8) -> Self {
+        InterfaceAddress { addr, prefix_len }
+    }
+
+    pub fn netmask(&self) -> IpAddress {
+        IpAddress::from(route::prefix_mask(self.prefix_len))
+    }
+
+    pub fn subnet(&self) -> IpAddress {
+        IpAddress::from(u32::from(self.addr) & route::prefix_mask(self.prefix_len))
+    }
+
+    /// The directed broadcast address of the subnet. A /31 or /32 has none.
+    pub fn broadcast(&self) -> Option<IpAddress> {
+        if self.prefix_len >= 31 {
+            return None;
+        }
+        Some(IpAddress::from(
+            u32::from(self.addr) | !route::prefix_mask(self.prefix_len),
+        ))
+    }
+
+    pub fn contains(&self, addr: IpAddress) -> bool {
+        let mask = route::prefix_mask(self.prefix_len);
+        u32::from(addr) & mask == u32::from(self.addr) & mask
+    }
+
+    /// The route to the hosts of the subnet, on the link. A /32 has none.
+    pub fn connected_route(&self) -> Option<Route> {
+        if self.prefix_len >= 32 {
+            return None;
+        }
+        Some(Route::on_link(self.subnet(), self.prefix_len).metric(route::CONNECTED_ROUTE_METRIC))
+    }
+
+    /// Whether a host may own the address: a unicast one, which is neither the number nor
+    /// the broadcast address of its subnet.
+    fn is_valid(&self) -> bool {
+        let addr = self.addr;
+        self.prefix_len <= 32
+            && !(addr.is_unspecified()
+                || addr.is_multicast()
+                || addr.is_reserved()
+                || addr.is_loopback())
+            && (self.prefix_len >= 31 || (addr != self.subnet() && Some(addr) != self.broadcast()))
+    }
+}
+
+impl fmt::Debug for InterfaceAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}/{}", self.addr, self.prefix_len)
+    }
+}
+
 pub trait IpParse {
     fn new(my_addr: IpAddress) -> Self;
 
+    /// Owns `addr` too, or changes its prefix if already owned.
+    fn add_address(&mut self, addr: InterfaceAddress) -> Result<(), IpError>;
+
+    /// Stops owning `addr`, returning it with its prefix.
+    fn remove_address(&mut self, addr: IpAddress) -> Option<InterfaceAddress>;
+
+    /// Accepts datagrams to the multicast `group`.
+    fn join_group(&mut self, group: IpAddress) -> Result<(), IpError>;
+
+    fn leave_group(&mut self, group: IpAddress);
+
     fn parse(&mut self, data: &[u8], frame_dst: Destination) -> Result<IpReply, IpError>;
+
+    /// Prepends the IPv4 header to `payload`, in its headroom.
+    fn construct_packet(&mut self, protocol: u8, dst: IpAddress, payload: PacketBuf) -> PacketBuf;
+
+    /// The host on the link to hand datagrams to `dst` to. Without a route, `dst` is taken
+    /// to be on the link.
+    fn next_hop(&self, dst: IpAddress) -> IpAddress;
+
+    /// The address to send datagrams to `dst` from, or the unspecified one if none is owned.
+    fn source_for(&self, dst: IpAddress) -> IpAddress;
+
+    /// Whether `addr` is the limited broadcast address or the one of a subnet of the host.
+    fn is_broadcast(&self, addr: IpAddress) -> bool;
+
+    /// Splits a datagram to `dst` into fragments which fit both the link and the path.
+    fn fragment(
+        &self,
+        dst: IpAddress,
+        packet: PacketBuf,
+        link_mtu: usize,
+    ) -> Result<Vec<PacketBuf>, IpError>;
+
+    /// Takes the packets to send which answer no packet in particular, such as the errors
+    /// about datagrams whose reassembly timed out.
+    fn take_pending(&mut self) -> Vec<IpReply> {
+        Vec::new()
+    }
+
+    /// Runs the timers due by `now`, leaving the packets to send pending. The driver calls
+    /// it periodically, even when no packet arrives.
+    fn poll_timers(&mut self, _now: Instant) {}
 }
 
 pub struct IpDriver {
-    my_addr: IpAddress,
+    /// The addresses of the host, the primary one first.
+    addresses: Vec<InterfaceAddress>,
+    /// The multicast groups joined, besides the all-hosts group.
+    groups: Vec<IpAddress>,
+    identification: u16,
+    ttl: u8,
     icmp_driver: IcmpDriver,
+    reassembler: Reassembler,
+    pending: Vec<IpReply>,
+    paths: HashMap<IpAddress, PathConfig>,
+    option_policy: OptionPolicy,
+    routing_table: RoutingTable,
+    registry: ProtocolRegistry,
 }
 
-fn construct_packet(proto: u8, dst: IpAddress, payload: &[u8]) -> Vec<u8> {
-    let mut result = vec![0; mem::size_of::<IpHeaderWithoutOptions>() + payload.len()];
-    {
-        let (ip_header, payload) = IpHeaderWithoutOptions::mapped_mut(&mut result).unwrap();
-        unimplemented!();
-    }
+/// Queues the datagrams a protocol handler sends while handling one from `from` to `to`.
+struct HandlerSender<'a> {
+    driver: &'a mut IpDriver,
+    from: IpAddress,
+    to: IpAddress,
+}
 
-    result
+impl DatagramSender for HandlerSender<'_> {
+    fn send_datagram(&mut self, protocol: u8, dst: IpAddress, payload: &[u8]) {
+        let driver = &mut *self.driver;
+        let src = if dst == self.from {
+            driver.reply_source(self.from, self.to)
+        } else {
+            driver.source_for(dst)
+        };
+        let dont_fragment = driver.path(dst).dont_fragment;
+        let data = driver.build_packet(
+            protocol,
+            src,
+            dst,
+            PacketBuf::from_payload(payload),
+            dont_fragment,
+            &[],
+        );
+        driver.pending.push(IpReply::Reply { dst, data });
+    }
 }
 
 impl IpDriver {
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl;
+    }
+
+    pub fn addresses(&self) -> &[InterfaceAddress] {
+        &self.addresses
+    }
+
+    /// The mask of the subnet of the primary address.
+    pub fn netmask(&self) -> Option<IpAddress> {
+        self.addresses.first().map(InterfaceAddress::netmask)
+    }
+
+    /// Sets the mask of the subnet of the primary address, whose directed broadcast address
+    /// is then accepted and whose hosts are then reached on the link.
+    pub fn set_netmask(&mut self, netmask: IpAddress) -> Result<(), IpError> {
+        let prefix_len = route::prefix_len(netmask).ok_or(IpError::InvalidNetmask(netmask))?;
+        let primary = self.addresses.first().ok_or(IpError::NoAddress)?;
+        self.add_address(InterfaceAddress::new(primary.addr, prefix_len))
+    }
+
+    /// Removes the route to the subnet of `addr`, unless another address shares it.
+    fn remove_connected_route(&mut self, addr: &InterfaceAddress) {
+        let connected = match addr.connected_route() {
+            Some(connected) => connected,
+            None => return,
+        };
+        let shared = self
+            .addresses
+            .iter()
+            .any(|other| other.addr != addr.addr && other.connected_route() == Some(connected));
+        if !shared {
+            self.routing_table.remove(&connected);
+        }
+    }
+
+    pub fn groups(&self) -> &[IpAddress] {
+        &self.groups
+    }
+
+    fn is_member(&self, group: IpAddress) -> bool {
+        group == ALL_HOSTS_GROUP || self.groups.contains(&group)
+    }
+
+    fn owns(&self, addr: IpAddress) -> bool {
+        self.addresses.iter().any(|owned| owned.addr == addr)
+    }
+
+    /// The address to answer a datagram to `dst` from `src` from: the targeted one, if
+    /// it was not sent to a group.
+    fn reply_source(&self, src: IpAddress, dst: IpAddress) -> IpAddress {
+        if self.owns(dst) {
+            dst
+        } else {
+            self.source_for(src)
+        }
+    }
+
+    /// The routes. A destination matching none is taken to be on the link.
+    pub fn routing_table(&self) -> &RoutingTable {
+        &self.routing_table
+    }
+
+    pub fn routing_table_mut(&mut self) -> &mut RoutingTable {
+        &mut self.routing_table
+    }
+
+    /// How datagrams to `dst` are sent.
+    pub fn path(&self, dst: IpAddress) -> PathConfig {
+        self.paths.get(&dst).copied().unwrap_or_default()
+    }
+
+    pub fn set_path(&mut self, dst: IpAddress, path: PathConfig) {
+        self.paths.insert(dst, path);
+    }
+
+    /// Sends datagrams to `dst` with the default configuration again.
+    pub fn remove_path(&mut self, dst: IpAddress) {
+        self.paths.remove(&dst);
+    }
+
+    pub fn option_policy(&self) -> OptionPolicy {
+        self.option_policy
+    }
+
+    pub fn set_option_policy(&mut self, option_policy: OptionPolicy) {
+        self.option_policy = option_policy;
+    }
+
+    /// Prepends the IPv4 header to `payload`, with the don't fragment flag if asked.
+    fn build_packet(
+        &mut self,
+        protocol: u8,
+        src: IpAddress,
+        dst: IpAddress,
+        payload: PacketBuf,
+        dont_fragment: bool,
+        options: &[IpOption],
+    ) -> PacketBuf {
+        let packet = Ipv4Builder::new(src, dst)
+            .identification(self.identification)
+            .flags_fragment_offset(if dont_fragment { IP_FLAG_DF } else { 0 })
+            .ttl(self.ttl)
+            .options(options)
+            .build(protocol, payload);
+        self.identification = self.identification.wrapping_add(1);
+        packet
+    }
+
+    /// The handlers of the protocols besides ICMP. The returned handle stays usable once
+    /// the driver is receiving.
+    pub fn registry(&self) -> ProtocolRegistry {
+        self.registry.clone()
+    }
+
+    pub fn reassembler_mut(&mut self) -> &mut Reassembler {
+        &mut self.reassembler
+    }
+
+    /// Drops the datagrams whose fragments did not all arrive in time, telling their
+    /// senders. This happens on every received packet and on every timer poll.
+    pub fn expire_fragments(&mut self, now: Instant) {
+        for expired in self.reassembler.expire(now) {
+            if let Some(error) = self.time_exceeded(expired) {
+                self.pending.push(error);
+            }
+        }
+    }
+
+    /// The ICMP Time Exceeded error about a datagram left incomplete (RFC 792), unless
+    /// RFC 1122, 3.2.2 forbids sending it.
+    fn time_exceeded(&mut self, expired: Expired) -> Option<IpReply> {
+        let Expired { key, first } = expired;
+        let first = first?;
+        if key.dst.is_multicast() || self.is_broadcast(key.dst) {
+            return None;
+        }
+        let (header, data) = IpHeaderWithoutOptions::mapped(&first)?;
+        let quoted_type = data.get(header.header_len() - mem::size_of_val(header));
+        if key.protocol == icmp::ICMP_PROTOCOL_NUMBER
+            && quoted_type.is_none_or(|&icmp_type| icmp::is_error_type(icmp_type))
+        {
+            return None;
+        }
+
+        println!("- Reassembly of a datagram from {:?} timed out", key.src);
+        let error = IcmpBuilder::new(
+            icmp::TIME_EXCEEDED_TYPE,
+            icmp::FRAGMENT_REASSEMBLY_TIME_EXCEEDED_CODE,
+        )
+        .build(PacketBuf::from_payload(&first));
+        let src = self.reply_source(key.src, key.dst);
+        Some(IpReply::Reply {
+            dst: key.src,
+            data: self.build_packet(icmp::ICMP_PROTOCOL_NUMBER, src, key.src, error, false, &[]),
+        })
+    }
+
+    /// Checks the header of a received packet (RFC 1122, 3.2.1), returning the payload
+    /// without the Ethernet padding.
+    fn validate<'a>(&self, data: &'a [u8], frame_dst: Destination) -> Result<&'a [u8], IpError> {
+        let (header, _) = IpHeaderWithoutOptions::mapped(data).ok_or(IpError::InvalidIpPacket)?;
+
+        if header.version() != IP_VERSION_4 {
+            return Err(IpError::UnsupportedVersion(header.version()));
+        }
+
+        // IHL and the total length come from the wire, so they may claim anything.
+        let header_len = header.header_len();
+        let total_length = header.total_length() as usize;
+        if header_len < mem::size_of::<IpHeaderWithoutOptions>() || header_len > total_length {
+            return Err(IpError::InvalidHeaderLength(header.ihl()));
+        }
+        if total_length > data.len() {
+            return Err(IpError::InvalidTotalLength {
+                total_length: header.total_length(),
+                received: data.len(),
+            });
+        }
+
+        if !header.is_valid(data) {
+            return Err(IpError::InvalidChecksum);
+        }
+
+        let src = header.src_addr();
+        if src.is_multicast() || src.is_reserved() || src.is_loopback() || self.is_broadcast(src) {
+            return Err(IpError::InvalidSourceAddress(src));
+        }
+
+        let dst = header.dst_addr();
+        let group = dst.is_multicast() || self.is_broadcast(dst);
+        // A multicast datagram is only for the members of its group (RFC 1112, 7.2).
+        let accepted = if dst.is_multicast() {
+            self.is_member(dst)
+        } else {
+            group || self.owns(dst)
+        };
+        if !accepted {
+            return Err(IpError::UnacceptableDestination(dst));
+        }
+        // A link-layer broadcast must carry an IP broadcast or multicast (RFC 1122, 3.3.6).
+        if !group && (frame_dst == Destination::Broadcast || frame_dst == Destination::Multicast) {
+            return Err(IpError::UnicastInLinkBroadcast(dst));
+        }
+
+        Ok(&data[header_len..total_length])
+    }
+
+    /// Parses the options of a received header, applying the policy.
+    fn receive_options(&self, header_bytes: &[u8]) -> Result<Vec<IpOption>, IpError> {
+        let options =
+            options::parse_options(&header_bytes[mem::size_of::<IpHeaderWithoutOptions>()..])?;
+        for option in &options {
+            match option {
+                IpOption::LooseSourceRoute(route) | IpOption::StrictSourceRoute(route)
+                    if !self.option_policy.accept_source_route || !route.is_full() =>
+                {
+                    return Err(IpError::SourceRouteRejected);
+                }
+                _ => {}
+            }
+        }
+        Ok(options)
+    }
+
+    /// The options of an echo request to send back in the reply, with this host recorded.
+    fn echo_options(&self, src: IpAddress, options: &[IpOption]) -> Vec<IpOption> {
+        if !self.option_policy.echo_route_and_timestamp {
+            return Vec::new();
+        }
+        options
+            .iter()
+            .filter_map(|option| match option {
+                IpOption::RecordRoute(route) => {
+                    let mut route = route.clone();
+                    route.record(src);
+                    Some(IpOption::RecordRoute(route))
+                }
+                IpOption::Timestamp(timestamp) => {
+                    let mut timestamp = timestamp.clone();
+                    timestamp.record(src, options::timestamp_now());
+                    Some(IpOption::Timestamp(timestamp))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Hands the payload of a whole datagram to its protocol. The datagrams sent by
+    /// the handlers of the registry are left pending.
+    fn dispatch(
+        &mut self,
+        src: IpAddress,
+        dst: IpAddress,
+        protocol: u8,
+        frame_dst: Destination,
+        options: &[IpOption],
+        payload: &[u8],
+    ) -> Result<IpReply, IpError> {
+        match protocol {
+            icmp::ICMP_PROTOCOL_NUMBER => self
+                .parse_and_reply_icmp(src, dst, frame_dst, options, payload)
+                .map_err(IpError::IcmpError),
+            _ => {
+                let registry = self.registry.clone();
+                let mut sender = HandlerSender {
+                    driver: self,
+                    from: src,
+                    to: dst,
+                };
+                if registry.dispatch(protocol, payload, src, dst, &mut sender) {
+                    Ok(IpReply::Nop)
+                } else {
+                    Err(IpError::Unimplemented)
+                }
+            }
+        }
+    }
+
     fn parse_and_reply_icmp(
         &mut self,
         from: IpAddress,
+        to: IpAddress,
         frame_dst: Destination,
+        options: &[IpOption],
         data: &[u8],
     ) -> Result<IpReply, IcmpError> {
         let reply = self.icmp_driver.parse(from, frame_dst, data)?;
 
         match reply {
+            // Like those of Linux, replies may be fragmented, as large as the requests are.
             IcmpReply::Reply { dst, data } => {
-                return Ok(IpReply::Reply {
+                let src = self.reply_source(from, to);
+                let options = self.echo_options(src, options);
+                Ok(IpReply::Reply {
                     dst,
-                    data: construct_packet(icmp::ICMP_PROTOCOL_NUMBER, dst, &data[..]),
+                    data: self.build_packet(
+                        icmp::ICMP_PROTOCOL_NUMBER,
+                        src,
+                        dst,
+                        data,
+                        false,
+                        &options,
+                    ),
                 })
             }
             _ => Ok(IpReply::Nop),
@@ -98,37 +593,640 @@ impl IpDriver {
 impl IpParse for IpDriver {
     fn new(my_addr: IpAddress) -> Self {
         IpDriver {
-            my_addr,
+            addresses: vec![InterfaceAddress::new(my_addr, 32)],
+            groups: Vec::new(),
+            identification: 0,
+            ttl: DEFAULT_TTL,
             icmp_driver: IcmpDriver::new(),
+            reassembler: Reassembler::new(),
+            pending: Vec::new(),
+            paths: HashMap::new(),
+            option_policy: OptionPolicy::default(),
+            routing_table: RoutingTable::new(),
+            registry: ProtocolRegistry::new(),
+        }
+    }
+
+    fn add_address(&mut self, addr: InterfaceAddress) -> Result<(), IpError> {
+        if !addr.is_valid() {
+            return Err(IpError::InvalidAddress(addr));
+        }
+        if let Some(connected) = addr.connected_route() {
+            self.routing_table.add(connected)?;
+        }
+        match self
+            .addresses
+            .iter()
+            .position(|owned| owned.addr == addr.addr)
+        {
+            Some(i) => {
+                let old = mem::replace(&mut self.addresses[i], addr);
+                if old.connected_route() != addr.connected_route() {
+                    self.remove_connected_route(&old);
+                }
+            }
+            None => self.addresses.push(addr),
+        }
+        Ok(())
+    }
+
+    fn remove_address(&mut self, addr: IpAddress) -> Option<InterfaceAddress> {
+        let i = self.addresses.iter().position(|owned| owned.addr == addr)?;
+        let removed = self.addresses[i];
+        self.remove_connected_route(&removed);
+        Some(self.addresses.remove(i))
+    }
+
+    fn join_group(&mut self, group: IpAddress) -> Result<(), IpError> {
+        if !group.is_multicast() {
+            return Err(IpError::NotMulticastGroup(group));
+        }
+        if !self.is_member(group) {
+            self.groups.push(group);
         }
+        Ok(())
+    }
+
+    fn leave_group(&mut self, group: IpAddress) {
+        self.groups.retain(|&joined| joined != group);
+    }
+
+    fn construct_packet(&mut self, protocol: u8, dst: IpAddress, payload: PacketBuf) -> PacketBuf {
+        let dont_fragment = self.path(dst).dont_fragment;
+        let src = self.source_for(dst);
+        self.build_packet(protocol, src, dst, payload, dont_fragment, &[])
+    }
+
+    fn next_hop(&self, dst: IpAddress) -> IpAddress {
+        self.routing_table
+            .lookup(dst)
+            .map_or(dst, |route| route.next_hop(dst))
+    }
+
+    fn is_broadcast(&self, addr: IpAddress) -> bool {
+        addr.is_broadcast()
+            || self
+                .addresses
+                .iter()
+                .any(|owned| owned.broadcast() == Some(addr))
+    }
+
+    /// The one on the subnet of the next hop, or else the primary one.
+    fn source_for(&self, dst: IpAddress) -> IpAddress {
+        let next_hop = self.next_hop(dst);
+        self.addresses
+            .iter()
+            .filter(|owned| owned.contains(next_hop))
+            .max_by_key(|owned| owned.prefix_len)
+            .or_else(|| self.addresses.first())
+            .map_or(IpAddress::new_be_bytes([0; 4]), |owned| owned.addr)
+    }
+
+    fn fragment(
+        &self,
+        dst: IpAddress,
+        packet: PacketBuf,
+        link_mtu: usize,
+    ) -> Result<Vec<PacketBuf>, IpError> {
+        let mtu = self.path(dst).mtu.map_or(link_mtu, |mtu| mtu.min(link_mtu));
+        fragment::fragment(packet, mtu)
     }
 
     fn parse(&mut self, data: &[u8], frame_dst: Destination) -> Result<IpReply, IpError> {
         println!("Received IPv4 packet",);
-        let (header, _) = IpHeaderWithoutOptions::mapped(&data).ok_or(IpError::InvalidIpPacket)?;
+        let now = Instant::now();
+        self.expire_fragments(now);
+
+        let payload = self.validate(data, frame_dst)?;
+        let (header, _) = IpHeaderWithoutOptions::mapped(data).unwrap();
+        let (src, dst, protocol) = (header.src_addr(), header.dst_addr(), header.protocol());
+        let header_bytes = &data[..header.header_len()];
+        let options = self.receive_options(header_bytes)?;
 
-        if header.version() != 4 {
-            return Err(IpError::Unimplemented);
+        if !fragment::is_fragment(header) {
+            return self.dispatch(src, dst, protocol, frame_dst, &options, payload);
         }
+        match self
+            .reassembler
+            .insert(header, header_bytes, payload, now)?
+        {
+            // The first fragment alone carries all the options.
+            Some(datagram) => {
+                let options = self.receive_options(&datagram.header)?;
+                self.dispatch(src, dst, protocol, frame_dst, &options, &datagram.payload)
+            }
+            None => Ok(IpReply::Nop),
+        }
+    }
 
-        if !header.is_valid(&data) {
-            return Err(IpError::InvalidChecksum);
+    fn take_pending(&mut self) -> Vec<IpReply> {
+        mem::take(&mut self.pending)
+    }
+
+    fn poll_timers(&mut self, now: Instant) {
+        self.expire_fragments(now);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builder::{IcmpBuilder, Ipv4Builder};
+    use crate::ip::header::*;
+    use crate::ip::*;
+    use crate::packet::PacketBuf;
+
+    #[test]
+    fn test_construct_packet() {
+        let my_addr = IpAddress::new_be_bytes([192, 168, 56, 150]);
+        let dst = IpAddress::new_be_bytes([192, 168, 56, 1]);
+        let mut driver = IpDriver::new(my_addr);
+        driver.set_ttl(32);
+
+        let payload = [0xDE, 0xAD, 0xBE, 0xEF, 0x01];
+        let packet = driver.construct_packet(0xFD, dst, PacketBuf::from_payload(&payload));
+        let (header, rest) = IpHeaderWithoutOptions::mapped(&packet).unwrap();
+
+        assert!(header.is_valid(&packet));
+        assert_eq!(header.version(), 4);
+        assert_eq!(header.ihl(), 5);
+        assert_eq!(header.total_length() as usize, packet.len());
+        assert_eq!(header.flags_fragment_offset(), 0);
+        assert_eq!(header.ttl(), 32);
+        assert_eq!(header.protocol(), 0xFD);
+        assert_eq!(header.src_addr(), my_addr);
+        assert_eq!(header.dst_addr(), dst);
+        assert_eq!(rest, &payload);
+        assert_eq!(&packet[12..20], &[192, 168, 56, 150, 192, 168, 56, 1]);
+
+        let next = driver.construct_packet(0xFD, dst, PacketBuf::from_payload(&payload));
+        let (next_header, _) = IpHeaderWithoutOptions::mapped(&next).unwrap();
+        assert!(next_header.is_valid(&next));
+        assert_eq!(
+            next_header.identification(),
+            header.identification().wrapping_add(1)
+        );
+
+        let mut corrupted = packet.clone();
+        corrupted[8] ^= 0xFF;
+        let (corrupted_header, _) = IpHeaderWithoutOptions::mapped(&corrupted).unwrap();
+        assert!(!corrupted_header.is_valid(&corrupted));
+    }
+
+    #[test]
+    fn test_hostile_packets() {
+        let my_addr = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let peer = IpAddress::new_be_bytes([10, 0, 0, 2]);
+        let echo = IcmpBuilder::echo_request(1, 1).build(PacketBuf::from_payload(b"ping"));
+        let packet = Ipv4Builder::new(peer, my_addr).build(icmp::ICMP_PROTOCOL_NUMBER, echo);
+        let mut driver = IpDriver::new(my_addr);
+
+        // Every IHL, with the checksum fixed so that only the length is wrong.
+        for ihl in 0..16 {
+            let mut hostile = packet.clone();
+            hostile[0] = (IP_VERSION_4 << 4) | ihl;
+            hostile[10..12].copy_from_slice(&[0, 0]);
+            let header_len = (ihl as usize * 4).min(hostile.len());
+            let checksum = crate::utils::checksum(&hostile[..header_len]);
+            hostile[10..12].copy_from_slice(&checksum.to_be_bytes());
+            let result = driver.parse(&hostile, Destination::ToMyself);
+            assert_eq!(result.is_ok(), ihl == 5);
         }
 
-        // TODO flagmentation
+        for len in 0..packet.len() {
+            assert!(driver.parse(&packet[..len], Destination::ToMyself).is_err());
+        }
+    }
 
-        let header_length_in_byte = (header.ihl() * 4) as usize;
-        if header_length_in_byte >= data.len() {
-            return Err(IpError::InvalidIpPacket);
+    #[test]
+    fn test_receive_checks() {
+        let my_addr = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let peer = IpAddress::new_be_bytes([10, 0, 0, 2]);
+        let mut driver = IpDriver::new(my_addr);
+        let echo = |src, dst| {
+            let echo = IcmpBuilder::echo_request(1, 1).build(PacketBuf::from_payload(b"ping"));
+            Ipv4Builder::new(src, dst).build(icmp::ICMP_PROTOCOL_NUMBER, echo)
+        };
+
+        // The padding of a short frame is not part of the payload.
+        let mut padded = echo(peer, my_addr);
+        padded.resize(46, 0xAA);
+        match driver.parse(&padded, Destination::ToMyself) {
+            Ok(IpReply::Reply { data, .. }) => assert_eq!(data.len(), 32),
+            _ => panic!("no reply to a padded echo request"),
         }
 
-        let payload = &data[header_length_in_byte..];
+        let other = IpAddress::new_be_bytes([10, 0, 0, 3]);
+        assert!(matches!(
+            driver.parse(&echo(peer, other), Destination::Promisc),
+            Err(IpError::UnacceptableDestination(addr)) if addr == other
+        ));
+        let subnet_broadcast = IpAddress::new_be_bytes([10, 0, 0, 255]);
+        assert!(driver
+            .parse(&echo(peer, subnet_broadcast), Destination::Broadcast)
+            .is_err());
+        driver
+            .set_netmask(IpAddress::new_be_bytes([255, 255, 255, 0]))
+            .unwrap();
+        for &dst in &[
+            subnet_broadcast,
+            IpAddress::new_be_bytes([255, 255, 255, 255]),
+            IpAddress::new_be_bytes([224, 0, 0, 1]),
+        ] {
+            assert!(driver
+                .parse(&echo(peer, dst), Destination::Broadcast)
+                .is_ok());
+        }
+        assert!(matches!(
+            driver.parse(&echo(peer, my_addr), Destination::Broadcast),
+            Err(IpError::UnicastInLinkBroadcast(_))
+        ));
 
-        match header.protocol {
-            icmp::ICMP_PROTOCOL_NUMBER => self
-                .parse_and_reply_icmp(IpAddress::from_be(header.src_addr), frame_dst, payload)
-                .map_err(IpError::IcmpError),
-            _ => Err(IpError::Unimplemented),
+        // Only the groups joined are accepted.
+        let group = IpAddress::new_be_bytes([239, 1, 2, 3]);
+        assert!(matches!(
+            driver.parse(&echo(peer, group), Destination::Multicast),
+            Err(IpError::UnacceptableDestination(addr)) if addr == group
+        ));
+        driver.join_group(group).unwrap();
+        assert!(driver
+            .parse(&echo(peer, group), Destination::Multicast)
+            .is_ok());
+        driver.leave_group(group);
+        assert!(driver
+            .parse(&echo(peer, group), Destination::Multicast)
+            .is_err());
+        assert!(matches!(
+            driver.join_group(peer),
+            Err(IpError::NotMulticastGroup(addr)) if addr == peer
+        ));
+
+        for &src in &[
+            subnet_broadcast,
+            IpAddress::new_be_bytes([224, 0, 0, 1]),
+            IpAddress::new_be_bytes([127, 0, 0, 1]),
+            IpAddress::new_be_bytes([240, 0, 0, 1]),
+        ] {
+            assert!(matches!(
+                driver.parse(&echo(src, my_addr), Destination::ToMyself),
+                Err(IpError::InvalidSourceAddress(addr)) if addr == src
+            ));
+        }
+
+        let long = Ipv4Builder::new(peer, my_addr).total_length(100).build(
+            icmp::ICMP_PROTOCOL_NUMBER,
+            PacketBuf::from_payload(&[0; 12]),
+        );
+        assert!(matches!(
+            driver.parse(&long, Destination::ToMyself),
+            Err(IpError::InvalidTotalLength {
+                total_length: 100,
+                received: 32
+            })
+        ));
+        let version_6 = Ipv4Builder::new(peer, my_addr).version(6).build(
+            icmp::ICMP_PROTOCOL_NUMBER,
+            PacketBuf::from_payload(&[0; 12]),
+        );
+        assert!(matches!(
+            driver.parse(&version_6, Destination::ToMyself),
+            Err(IpError::UnsupportedVersion(6))
+        ));
+    }
+
+    #[test]
+    fn test_fragmented_echo() {
+        let my_addr = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let peer = IpAddress::new_be_bytes([10, 0, 0, 2]);
+        let mut driver = IpDriver::new(my_addr);
+        let echo = IcmpBuilder::echo_request(1, 1).build(PacketBuf::from_payload(&[0xA5; 24]));
+        let fragments = |identification| {
+            [(0, header::IP_FLAG_MF), (16, 2)].map(|(start, flags)| {
+                Ipv4Builder::new(peer, my_addr)
+                    .identification(identification)
+                    .flags_fragment_offset(flags)
+                    .build(
+                        icmp::ICMP_PROTOCOL_NUMBER,
+                        PacketBuf::from_payload(&echo[start..(start + 16).min(echo.len())]),
+                    )
+            })
+        };
+
+        // Reassembled in any order, then answered like an unfragmented request.
+        let [first, last] = fragments(7);
+        assert!(matches!(
+            driver.parse(&last, Destination::ToMyself),
+            Ok(IpReply::Nop)
+        ));
+        match driver.parse(&first, Destination::ToMyself) {
+            Ok(IpReply::Reply { dst, data }) => {
+                assert_eq!(dst, peer);
+                assert_eq!(&data[28..], &echo[8..]);
+            }
+            _ => panic!("no reply to a fragmented echo request"),
+        }
+
+        // Only the first fragment arrives.
+        let [first, _] = fragments(8);
+        driver.parse(&first, Destination::ToMyself).unwrap();
+        driver.expire_fragments(Instant::now() + fragment::DEFAULT_REASSEMBLY_TIMEOUT);
+        let mut pending = driver.take_pending();
+        assert_eq!(pending.len(), 1);
+        match pending.pop() {
+            Some(IpReply::Reply { dst, data }) => {
+                assert_eq!(dst, peer);
+                let (_, error) = IpHeaderWithoutOptions::mapped(&data).unwrap();
+                assert_eq!(
+                    &error[..2],
+                    &[
+                        icmp::TIME_EXCEEDED_TYPE,
+                        icmp::FRAGMENT_REASSEMBLY_TIME_EXCEEDED_CODE
+                    ]
+                );
+                assert_eq!(crate::utils::checksum(error), 0);
+                assert_eq!(&error[8..], &first[..28]);
+            }
+            _ => panic!("no time exceeded error"),
+        }
+
+        // Without the first fragment, the sender is not told.
+        let [_, last] = fragments(9);
+        driver.parse(&last, Destination::ToMyself).unwrap();
+        driver.expire_fragments(Instant::now() + fragment::DEFAULT_REASSEMBLY_TIMEOUT);
+        assert!(driver.take_pending().is_empty());
+    }
+
+    #[test]
+    fn test_path_mtu() {
+        let my_addr = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let peer = IpAddress::new_be_bytes([10, 0, 0, 2]);
+        let mut driver = IpDriver::new(my_addr);
+        let mut peer_driver = IpDriver::new(peer);
+        let echo = IcmpBuilder::echo_request(1, 1).build(PacketBuf::from_payload(&[0x5A; 1000]));
+
+        // Datagrams are fragmented by default.
+        let packet = driver.construct_packet(icmp::ICMP_PROTOCOL_NUMBER, peer, echo.clone());
+        assert_eq!(driver.fragment(peer, packet, 576).unwrap().len(), 2);
+
+        // With the don't fragment flag, they fail to be sent instead.
+        driver.set_path(
+            peer,
+            PathConfig {
+                mtu: None,
+                dont_fragment: true,
+            },
+        );
+        let packet = driver.construct_packet(icmp::ICMP_PROTOCOL_NUMBER, peer, echo.clone());
+        assert_eq!(
+            driver.fragment(peer, packet.clone(), 1500).unwrap().len(),
+            1
+        );
+        assert!(matches!(
+            driver.fragment(peer, packet, 576),
+            Err(IpError::FragmentationNeeded {
+                size: 1028,
+                mtu: 576
+            })
+        ));
+
+        let path = PathConfig {
+            mtu: Some(576),
+            dont_fragment: false,
+        };
+        driver.set_path(peer, path);
+        assert_eq!(driver.path(peer), path);
+        let packet = driver.construct_packet(icmp::ICMP_PROTOCOL_NUMBER, peer, echo);
+        let fragments = driver.fragment(peer, packet, 1500).unwrap();
+        assert_eq!(fragments.len(), 2);
+        assert!(fragments.iter().all(|fragment| fragment.len() <= 576));
+
+        // The peer puts the request together, and may fragment its reply.
+        assert!(matches!(
+            peer_driver.parse(&fragments[1], Destination::ToMyself),
+            Ok(IpReply::Nop)
+        ));
+        let reply = match peer_driver.parse(&fragments[0], Destination::ToMyself) {
+            Ok(IpReply::Reply { data, .. }) => data,
+            _ => panic!("no reply to a fragmented echo request"),
+        };
+        assert_eq!(reply.len(), 1028);
+        let reply_fragments = peer_driver.fragment(my_addr, reply, 576).unwrap();
+        assert_eq!(reply_fragments.len(), 2);
+
+        driver.remove_path(peer);
+        assert_eq!(driver.path(peer), PathConfig::default());
+    }
+
+    #[test]
+    fn test_echo_options() {
+        let my_addr = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let peer = IpAddress::new_be_bytes([10, 0, 0, 2]);
+        let mut driver = IpDriver::new(my_addr);
+        let echo = |options: &[IpOption]| {
+            let echo = IcmpBuilder::echo_request(1, 1).build(PacketBuf::from_payload(b"ping"));
+            Ipv4Builder::new(peer, my_addr)
+                .options(options)
+                .build(icmp::ICMP_PROTOCOL_NUMBER, echo)
+        };
+        let reply_options = |driver: &mut IpDriver, request: &[u8]| match driver
+            .parse(request, Destination::ToMyself)
+        {
+            Ok(IpReply::Reply { data, .. }) => {
+                let (header, _) = IpHeaderWithoutOptions::mapped(&data).unwrap();
+                assert!(header.is_valid(&data));
+                options::parse_options(&data[20..header.header_len()]).unwrap()
+            }
+            _ => panic!("no reply to an echo request"),
+        };
+
+        // As `ping -R` and `ping -T tsandaddr` send them.
+        let mut route = options::Route::with_capacity(9);
+        route.record(peer);
+        let request = echo(&[IpOption::NoOperation, IpOption::RecordRoute(route)]);
+        match &reply_options(&mut driver, &request)[..] {
+            [IpOption::RecordRoute(route)] => {
+                assert_eq!(route.next, 2);
+                assert_eq!(&route.addresses[..2], &[peer, my_addr]);
+                assert_eq!(route.addresses.len(), 9);
+            }
+            options => panic!("unexpected options {:?}", options),
+        }
+        let timestamp = options::Timestamp::with_capacity(options::TIMESTAMP_WITH_ADDRESS, 4);
+        let request = echo(&[IpOption::RouterAlert(0), IpOption::Timestamp(timestamp)]);
+        match &reply_options(&mut driver, &request)[..] {
+            [IpOption::Timestamp(timestamp)] => {
+                assert_eq!(timestamp.next, 1);
+                assert_eq!(timestamp.entries[0].0, Some(my_addr));
+            }
+            options => panic!("unexpected options {:?}", options),
+        }
+
+        let record_route = echo(&[IpOption::RecordRoute(options::Route::with_capacity(1))]);
+        driver.set_option_policy(options::OptionPolicy {
+            echo_route_and_timestamp: false,
+            ..Default::default()
+        });
+        assert!(reply_options(&mut driver, &record_route).is_empty());
+
+        // Source routes end here only once every hop is passed.
+        let mut route = options::Route::with_capacity(1);
+        route.record(IpAddress::new_be_bytes([10, 0, 0, 3]));
+        let source_routed = echo(&[IpOption::LooseSourceRoute(route)]);
+        assert!(matches!(
+            driver.parse(&source_routed, Destination::ToMyself),
+            Err(IpError::SourceRouteRejected)
+        ));
+        driver.set_option_policy(options::OptionPolicy {
+            accept_source_route: true,
+            ..Default::default()
+        });
+        assert!(driver.parse(&source_routed, Destination::ToMyself).is_ok());
+        let unfinished = echo(&[IpOption::StrictSourceRoute(options::Route::with_capacity(
+            1,
+        ))]);
+        assert!(matches!(
+            driver.parse(&unfinished, Destination::ToMyself),
+            Err(IpError::SourceRouteRejected)
+        ));
+
+        let echo_request = IcmpBuilder::echo_request(1, 1).build(PacketBuf::from_payload(b"ping"));
+        let malformed = Ipv4Builder::new(peer, my_addr)
+            .raw_options(&[options::OPTION_RECORD_ROUTE, 9, 4, 0])
+            .build(icmp::ICMP_PROTOCOL_NUMBER, echo_request);
+        assert!(matches!(
+            driver.parse(&malformed, Destination::ToMyself),
+            Err(IpError::InvalidOption(options::OPTION_RECORD_ROUTE))
+        ));
+    }
+
+    #[test]
+    fn test_next_hop() {
+        let my_addr = IpAddress::new_be_bytes([10, 0, 1, 1]);
+        let neighbor = IpAddress::new_be_bytes([10, 0, 1, 2]);
+        let remote = IpAddress::new_be_bytes([192, 168, 1, 1]);
+        let gateway = IpAddress::new_be_bytes([10, 0, 1, 254]);
+        let mut driver = IpDriver::new(my_addr);
+        assert_eq!(driver.next_hop(neighbor), neighbor);
+        assert_eq!(driver.next_hop(remote), remote);
+
+        driver
+            .set_netmask(IpAddress::new_be_bytes([255, 255, 0, 0]))
+            .unwrap();
+        driver
+            .routing_table_mut()
+            .add(route::Route::default_via(gateway))
+            .unwrap();
+        assert_eq!(driver.next_hop(neighbor), neighbor);
+        assert_eq!(driver.next_hop(remote), gateway);
+
+        // A new netmask replaces the route to the subnet.
+        driver
+            .set_netmask(IpAddress::new_be_bytes([255, 255, 255, 0]))
+            .unwrap();
+        assert_eq!(driver.routing_table().routes().len(), 2);
+        let other_subnet = IpAddress::new_be_bytes([10, 0, 2, 1]);
+        assert_eq!(driver.next_hop(other_subnet), gateway);
+        assert!(matches!(
+            driver.set_netmask(IpAddress::new_be_bytes([255, 0, 255, 0])),
+            Err(IpError::InvalidNetmask(_))
+        ));
+    }
+
+    #[test]
+    fn test_multiple_addresses() {
+        let my_addr = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let secondary = IpAddress::new_be_bytes([172, 16, 0, 1]);
+        let peer = IpAddress::new_be_bytes([172, 16, 0, 2]);
+        let mut driver = IpDriver::new(my_addr);
+        driver
+            .set_netmask(IpAddress::new_be_bytes([255, 255, 255, 0]))
+            .unwrap();
+        let echo = |src, dst| {
+            let echo = IcmpBuilder::echo_request(1, 1).build(PacketBuf::from_payload(b"ping"));
+            Ipv4Builder::new(src, dst).build(icmp::ICMP_PROTOCOL_NUMBER, echo)
+        };
+        let reply_source = |driver: &mut IpDriver, request: &[u8], frame_dst| match driver
+            .parse(request, frame_dst)
+        {
+            Ok(IpReply::Reply { data, .. }) => {
+                let (header, _) = IpHeaderWithoutOptions::mapped(&data).unwrap();
+                header.src_addr()
+            }
+            _ => panic!("no reply to an echo request"),
+        };
+
+        assert!(matches!(
+            driver.parse(&echo(peer, secondary), Destination::ToMyself),
+            Err(IpError::UnacceptableDestination(_))
+        ));
+        driver
+            .add_address(InterfaceAddress::new(secondary, 24))
+            .unwrap();
+        assert_eq!(driver.addresses().len(), 2);
+        assert_eq!(
+            driver.netmask(),
+            Some(IpAddress::new_be_bytes([255, 255, 255, 0]))
+        );
+        assert_eq!(driver.next_hop(peer), peer);
+
+        // Replies come from the address targeted, or else from the one on the subnet.
+        assert_eq!(
+            reply_source(&mut driver, &echo(peer, secondary), Destination::ToMyself),
+            secondary
+        );
+        assert_eq!(
+            reply_source(&mut driver, &echo(peer, my_addr), Destination::ToMyself),
+            my_addr
+        );
+        let broadcast = IpAddress::new_be_bytes([172, 16, 0, 255]);
+        assert_eq!(
+            reply_source(&mut driver, &echo(peer, broadcast), Destination::Broadcast),
+            secondary
+        );
+        let packet = driver.construct_packet(0xFD, peer, PacketBuf::from_payload(b"data"));
+        assert_eq!(&packet[12..16], &secondary.to_be_bytes());
+        let remote = IpAddress::new_be_bytes([192, 168, 1, 1]);
+        let packet = driver.construct_packet(0xFD, remote, PacketBuf::from_payload(b"data"));
+        assert_eq!(&packet[12..16], &my_addr.to_be_bytes());
+
+        // Addresses sharing a subnet share its route.
+        let other = IpAddress::new_be_bytes([172, 16, 0, 3]);
+        driver
+            .add_address(InterfaceAddress::new(other, 24))
+            .unwrap();
+        assert_eq!(driver.routing_table().routes().len(), 2);
+        assert_eq!(
+            driver.remove_address(secondary),
+            Some(InterfaceAddress::new(secondary, 24))
+        );
+        assert_eq!(driver.next_hop(peer), peer);
+        assert!(driver.remove_address(secondary).is_none());
+        assert!(matches!(
+            driver.parse(&echo(peer, secondary), Destination::ToMyself),
+            Err(IpError::UnacceptableDestination(_))
+        ));
+        driver.remove_address(other);
+        assert!(driver.routing_table().lookup(peer).is_none());
+
+        for &(addr, prefix_len) in &[
+            ([172, 16, 0, 0], 24),
+            ([172, 16, 0, 255], 24),
+            ([224, 0, 0, 1], 32),
+            ([0, 0, 0, 0], 32),
+            ([172, 16, 0, 1], 33),
+        ] {
+            assert!(matches!(
+                driver.add_address(InterfaceAddress::new(
+                    IpAddress::new_be_bytes(addr),
+                    prefix_len
+                )),
+                Err(IpError::InvalidAddress(_))
+            ));
         }
+        assert!(driver
+            .add_address(InterfaceAddress::new(
+                IpAddress::new_be_bytes([172, 16, 0, 0]),
+                31
+            ))
+            .is_ok());
     }
 }
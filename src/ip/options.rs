@@ -0,0 +1,472 @@
+use super::error::IpError;
+use super::IpAddress;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const OPTION_END: u8 = 0;
+pub const OPTION_NOP: u8 = 1;
+pub const OPTION_RECORD_ROUTE: u8 = 7;
+pub const OPTION_TIMESTAMP: u8 = 68;
+pub const OPTION_LOOSE_SOURCE_ROUTE: u8 = 131;
+pub const OPTION_STRICT_SOURCE_ROUTE: u8 = 137;
+pub const OPTION_ROUTER_ALERT: u8 = 148;
+
+/// The flags of the Timestamp option, telling what each entry holds.
+pub const TIMESTAMP_ONLY: u8 = 0;
+pub const TIMESTAMP_WITH_ADDRESS: u8 = 1;
+pub const TIMESTAMP_PRESPECIFIED: u8 = 3;
+
+/// Options fit in the 60 bytes a header may have, beyond the first 20.
+pub const MAX_OPTIONS_LEN: usize = 40;
+/// Options with the flag are copied into every fragment (RFC 791).
+const COPIED_FLAG: u8 = 0x80;
+const MAX_OVERFLOW: u8 = 0x0F;
+
+/// An option of the IPv4 header (RFC 791, RFC 2113).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpOption {
+    End,
+    NoOperation,
+    RecordRoute(Route),
+    LooseSourceRoute(Route),
+    StrictSourceRoute(Route),
+    Timestamp(Timestamp),
+    /// The value is 0 for "examine the packet".
+    RouterAlert(u16),
+    /// Ignored on receipt (RFC 1122, 3.2.1.8), but kept to be written back as is.
+    Unknown {
+        option_type: u8,
+        data: Vec<u8>,
+    },
+}
+
+/// The slots of a route option, the first `next` of which are filled or passed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub addresses: Vec<IpAddress>,
+    pub next: usize,
+}
+
+impl Route {
+    /// An empty route to be recorded, with room for `capacity` addresses.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Route {
+            addresses: vec![IpAddress::new_be_bytes([0; 4]); capacity],
+            next: 0,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.next >= self.addresses.len()
+    }
+
+    /// Puts `addr` in the next slot, if any is left.
+    pub fn record(&mut self, addr: IpAddress) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.addresses[self.next] = addr;
+        self.next += 1;
+        true
+    }
+
+    fn parse(body: &[u8]) -> Option<Self> {
+        let (&pointer, slots) = body.split_first()?;
+        if !slots.len().is_multiple_of(4) || pointer < 4 || pointer % 4 != 0 {
+            return None;
+        }
+        let next = (pointer as usize - 4) / 4;
+        let addresses: Vec<_> = slots
+            .chunks(4)
+            .map(|slot| IpAddress::new_be_bytes([slot[0], slot[1], slot[2], slot[3]]))
+            .collect();
+        if next > addresses.len() {
+            return None;
+        }
+        Some(Route { addresses, next })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push((4 + self.next * 4) as u8);
+        for addr in &self.addresses {
+            out.extend_from_slice(&addr.to_be_bytes());
+        }
+    }
+}
+
+/// The slots of the Timestamp option, the first `next` of which are filled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timestamp {
+    pub flag: u8,
+    /// How many hosts could not record their timestamp for the lack of room.
+    pub overflow: u8,
+    /// The addresses are `None` with `TIMESTAMP_ONLY`.
+    pub entries: Vec<(Option<IpAddress>, u32)>,
+    pub next: usize,
+}
+
+impl Timestamp {
+    /// Empty slots for `capacity` entries of the kind `flag` tells.
+    pub fn with_capacity(flag: u8, capacity: usize) -> Self {
+        let addr = if flag == TIMESTAMP_ONLY {
+            None
+        } else {
+            Some(IpAddress::new_be_bytes([0; 4]))
+        };
+        Timestamp {
+            flag,
+            overflow: 0,
+            entries: vec![(addr, 0); capacity],
+            next: 0,
+        }
+    }
+
+    /// Puts the time of the host `addr` in the next slot, or counts an overflow if none
+    /// is left. With prespecified addresses, only the host of the next slot records.
+    pub fn record(&mut self, addr: IpAddress, time: u32) -> bool {
+        match self.entries.get_mut(self.next) {
+            None => {
+                self.overflow = (self.overflow + 1).min(MAX_OVERFLOW);
+                false
+            }
+            Some(entry) => {
+                match self.flag {
+                    TIMESTAMP_ONLY => *entry = (None, time),
+                    TIMESTAMP_WITH_ADDRESS => *entry = (Some(addr), time),
+                    _ if entry.0 == Some(addr) => entry.1 = time,
+                    _ => return false,
+                }
+                self.next += 1;
+                true
+            }
+        }
+    }
+
+    fn entry_len(flag: u8) -> usize {
+        if flag == TIMESTAMP_ONLY {
+            4
+        } else {
+            8
+        }
+    }
+
+    fn parse(body: &[u8]) -> Option<Self> {
+        let (&pointer, rest) = body.split_first()?;
+        let (&overflow_flag, slots) = rest.split_first()?;
+        let flag = overflow_flag & 0x0F;
+        if ![
+            TIMESTAMP_ONLY,
+            TIMESTAMP_WITH_ADDRESS,
+            TIMESTAMP_PRESPECIFIED,
+        ]
+        .contains(&flag)
+        {
+            return None;
+        }
+        let entry_len = Timestamp::entry_len(flag);
+        if !slots.len().is_multiple_of(entry_len)
+            || pointer < 5
+            || !(pointer as usize - 5).is_multiple_of(entry_len)
+        {
+            return None;
+        }
+        let next = (pointer as usize - 5) / entry_len;
+        let entries: Vec<_> = slots
+            .chunks(entry_len)
+            .map(|slot| {
+                let (addr, time) = slot.split_at(entry_len - 4);
+                let addr = if addr.is_empty() {
+                    None
+                } else {
+                    Some(IpAddress::new_be_bytes([
+                        addr[0], addr[1], addr[2], addr[3],
+                    ]))
+                };
+                (
+                    addr,
+                    u32::from_be_bytes([time[0], time[1], time[2], time[3]]),
+                )
+            })
+            .collect();
+        if next > entries.len() {
+            return None;
+        }
+        Some(Timestamp {
+            flag,
+            overflow: overflow_flag >> 4,
+            entries,
+            next,
+        })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push((5 + self.next * Timestamp::entry_len(self.flag)) as u8);
+        out.push((self.overflow << 4) | self.flag);
+        for (addr, time) in &self.entries {
+            if let Some(addr) = addr {
+                out.extend_from_slice(&addr.to_be_bytes());
+            }
+            out.extend_from_slice(&time.to_be_bytes());
+        }
+    }
+}
+
+/// Which options the host honours. Unknown ones are always ignored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OptionPolicy {
+    /// Whether the Record Route and Timestamp options of echo requests come back in the
+    /// replies, with this host recorded (RFC 1122, 3.2.2.6).
+    pub echo_route_and_timestamp: bool,
+    /// Whether datagrams source routed to this host are accepted. Those still on their
+    /// way elsewhere are dropped all the same, as the host does not forward.
+    pub accept_source_route: bool,
+}
+
+impl Default for OptionPolicy {
+    fn default() -> Self {
+        OptionPolicy {
+            echo_route_and_timestamp: true,
+            accept_source_route: false,
+        }
+    }
+}
+
+/// Milliseconds since midnight UT, as the Timestamp option holds them.
+pub fn timestamp_now() -> u32 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (since_epoch.as_millis() % (24 * 60 * 60 * 1000)) as u32
+}
+
+impl IpOption {
+    pub fn option_type(&self) -> u8 {
+        match self {
+            IpOption::End => OPTION_END,
+            IpOption::NoOperation => OPTION_NOP,
+            IpOption::RecordRoute(_) => OPTION_RECORD_ROUTE,
+            IpOption::LooseSourceRoute(_) => OPTION_LOOSE_SOURCE_ROUTE,
+            IpOption::StrictSourceRoute(_) => OPTION_STRICT_SOURCE_ROUTE,
+            IpOption::Timestamp(_) => OPTION_TIMESTAMP,
+            IpOption::RouterAlert(_) => OPTION_ROUTER_ALERT,
+            IpOption::Unknown { option_type, .. } => *option_type,
+        }
+    }
+
+    /// Whether the option goes into every fragment, or only the first one.
+    pub fn is_copied(&self) -> bool {
+        self.option_type() & COPIED_FLAG != 0
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.push(self.option_type());
+        match self {
+            IpOption::End | IpOption::NoOperation => return,
+            IpOption::RecordRoute(route)
+            | IpOption::LooseSourceRoute(route)
+            | IpOption::StrictSourceRoute(route) => {
+                out.push(0);
+                route.write(out);
+            }
+            IpOption::Timestamp(timestamp) => {
+                out.push(0);
+                timestamp.write(out);
+            }
+            IpOption::RouterAlert(value) => {
+                out.push(0);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            IpOption::Unknown { data, .. } => {
+                out.push(0);
+                out.extend_from_slice(data);
+            }
+        }
+        out[start + 1] = (out.len() - start) as u8;
+    }
+}
+
+/// Parses the options following the first 20 bytes of a header, checking their lengths
+/// and pointers. End of Option List and the padding after it are left out.
+pub fn parse_options(mut bytes: &[u8]) -> Result<Vec<IpOption>, IpError> {
+    let mut options = Vec::new();
+    while let Some(&option_type) = bytes.first() {
+        match option_type {
+            // The rest is padding.
+            OPTION_END => break,
+            OPTION_NOP => {
+                options.push(IpOption::NoOperation);
+                bytes = &bytes[1..];
+                continue;
+            }
+            _ => {}
+        }
+
+        let len = *bytes.get(1).ok_or(IpError::InvalidOption(option_type))? as usize;
+        if len < 2 || len > bytes.len() {
+            return Err(IpError::InvalidOption(option_type));
+        }
+        let body = &bytes[2..len];
+        let option = match option_type {
+            OPTION_RECORD_ROUTE => Route::parse(body).map(IpOption::RecordRoute),
+            OPTION_LOOSE_SOURCE_ROUTE => Route::parse(body).map(IpOption::LooseSourceRoute),
+            OPTION_STRICT_SOURCE_ROUTE => Route::parse(body).map(IpOption::StrictSourceRoute),
+            OPTION_TIMESTAMP => Timestamp::parse(body).map(IpOption::Timestamp),
+            OPTION_ROUTER_ALERT if body.len() == 2 => {
+                Some(IpOption::RouterAlert(u16::from_be_bytes([
+                    body[0], body[1],
+                ])))
+            }
+            OPTION_ROUTER_ALERT => None,
+            _ => Some(IpOption::Unknown {
+                option_type,
+                data: body.to_vec(),
+            }),
+        };
+        let option = option.ok_or(IpError::InvalidOption(option_type))?;
+
+        // Only one source route, and each option but the padding once (RFC 1122, 3.2.1.8).
+        let is_source_route = |option: &IpOption| {
+            matches!(
+                option,
+                IpOption::LooseSourceRoute(_) | IpOption::StrictSourceRoute(_)
+            )
+        };
+        if options.iter().any(|other| {
+            other.option_type() == option_type
+                || (is_source_route(other) && is_source_route(&option))
+        }) {
+            return Err(IpError::InvalidOption(option_type));
+        }
+
+        options.push(option);
+        bytes = &bytes[len..];
+    }
+    Ok(options)
+}
+
+/// Writes the options, padded with zeros to a multiple of 4 bytes.
+/// More than `MAX_OPTIONS_LEN` bytes do not fit in a header.
+pub fn write_options(options: &[IpOption]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for option in options {
+        option.write(&mut out);
+    }
+    out.resize(out.len().div_ceil(4) * 4, OPTION_END);
+    out
+}
+
+/// The options of `bytes` to be copied into the fragments after the first one.
+pub fn copied_options(bytes: &[u8]) -> Result<Vec<u8>, IpError> {
+    let options: Vec<_> = parse_options(bytes)?
+        .into_iter()
+        .filter(IpOption::is_copied)
+        .collect();
+    Ok(write_options(&options))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ip::error::IpError;
+    use crate::ip::options::*;
+
+    #[test]
+    fn test_parse_and_write() {
+        let a = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let b = IpAddress::new_be_bytes([10, 0, 0, 2]);
+        let mut route = Route::with_capacity(2);
+        route.record(a);
+        let mut timestamp = Timestamp::with_capacity(TIMESTAMP_WITH_ADDRESS, 2);
+        timestamp.record(b, 1000);
+        let options = vec![
+            IpOption::NoOperation,
+            IpOption::RecordRoute(route),
+            IpOption::Timestamp(timestamp),
+            IpOption::RouterAlert(0),
+            IpOption::Unknown {
+                option_type: 0x9F,
+                data: vec![1, 2],
+            },
+        ];
+
+        let bytes = write_options(&options);
+        assert_eq!(bytes.len(), 40);
+        assert_eq!(
+            &bytes[..7],
+            &[OPTION_NOP, OPTION_RECORD_ROUTE, 11, 8, 10, 0, 0]
+        );
+        assert_eq!(
+            &bytes[12..16],
+            &[OPTION_TIMESTAMP, 20, 13, TIMESTAMP_WITH_ADDRESS]
+        );
+        assert_eq!(parse_options(&bytes).unwrap(), options);
+
+        // Only the router alert and the unknown option have the copied flag.
+        let copied = copied_options(&bytes).unwrap();
+        assert_eq!(copied, [OPTION_ROUTER_ALERT, 4, 0, 0, 0x9F, 4, 1, 2]);
+    }
+
+    #[test]
+    fn test_record() {
+        let a = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let b = IpAddress::new_be_bytes([10, 0, 0, 2]);
+
+        let mut route = Route::with_capacity(1);
+        assert!(route.record(a));
+        assert!(!route.record(b));
+        assert!(route.is_full());
+        assert_eq!(route.addresses, [a]);
+
+        let mut timestamp = Timestamp::with_capacity(TIMESTAMP_ONLY, 1);
+        assert!(timestamp.record(a, 1));
+        assert!(!timestamp.record(a, 2));
+        assert_eq!(timestamp.entries, [(None, 1)]);
+        assert_eq!(timestamp.overflow, 1);
+
+        // Only the hosts asked for record their time.
+        let mut timestamp = Timestamp::with_capacity(TIMESTAMP_PRESPECIFIED, 2);
+        timestamp.entries[0].0 = Some(b);
+        assert!(!timestamp.record(a, 3));
+        assert!(timestamp.record(b, 4));
+        assert_eq!(timestamp.entries[0], (Some(b), 4));
+    }
+
+    #[test]
+    fn test_malformed() {
+        for bytes in &[
+            // Truncated.
+            &[OPTION_RECORD_ROUTE][..],
+            &[OPTION_RECORD_ROUTE, 7, 4, 0, 0, 0],
+            // Too short to hold the length.
+            &[OPTION_RECORD_ROUTE, 1, 4, 0],
+            &[0x9F, 0, 0, 0],
+            // A route not made of addresses, or pointing outside it.
+            &[OPTION_RECORD_ROUTE, 6, 4, 0, 0, 0],
+            &[OPTION_RECORD_ROUTE, 7, 3, 0, 0, 0, 0],
+            &[OPTION_RECORD_ROUTE, 7, 12, 0, 0, 0, 0],
+            &[OPTION_RECORD_ROUTE, 7, 5, 0, 0, 0, 0],
+            // An unknown flag, and a pointer in the middle of an entry.
+            &[OPTION_TIMESTAMP, 8, 5, 2, 0, 0, 0, 0],
+            &[OPTION_TIMESTAMP, 12, 9, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[OPTION_ROUTER_ALERT, 3, 0],
+            // The same option twice, and both source routes.
+            &[OPTION_RECORD_ROUTE, 3, 4, OPTION_RECORD_ROUTE, 3, 4],
+            &[
+                OPTION_LOOSE_SOURCE_ROUTE,
+                3,
+                4,
+                OPTION_STRICT_SOURCE_ROUTE,
+                3,
+                4,
+            ],
+        ] {
+            assert!(
+                matches!(parse_options(bytes), Err(IpError::InvalidOption(_))),
+                "{:?}",
+                bytes
+            );
+        }
+
+        // Anything may follow the end of the list.
+        assert!(parse_options(&[OPTION_END, 0xFF, 0xFF]).unwrap().is_empty());
+    }
+}
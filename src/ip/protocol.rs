@@ -0,0 +1,276 @@
+use super::error::IpError;
+use super::icmp::ICMP_PROTOCOL_NUMBER;
+use super::IpAddress;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Sends datagrams on behalf of a handler, through the stack of the driver.
+pub trait DatagramSender {
+    /// Sends `payload` to `dst` as a datagram of `protocol`. A reply to the sender of the
+    /// handled datagram comes from the address the datagram was sent to.
+    fn send_datagram(&mut self, protocol: u8, dst: IpAddress, payload: &[u8]);
+}
+
+/// Handles the datagrams of one protocol, like a raw IP socket. `payload` follows the IP
+/// header, and fragmented datagrams are passed once reassembled.
+pub trait ProtocolHandler: Send {
+    fn handle(
+        &mut self,
+        payload: &[u8],
+        src: IpAddress,
+        dst: IpAddress,
+        sender: &mut dyn DatagramSender,
+    );
+}
+
+impl<F> ProtocolHandler for F
+where
+    F: FnMut(&[u8], IpAddress, IpAddress, &mut dyn DatagramSender) + Send,
+{
+    fn handle(
+        &mut self,
+        payload: &[u8],
+        src: IpAddress,
+        dst: IpAddress,
+        sender: &mut dyn DatagramSender,
+    ) {
+        self(payload, src, dst, sender)
+    }
+}
+
+type SharedHandler = Arc<Mutex<Box<dyn ProtocolHandler>>>;
+
+/// Locks `mutex` even if a handler panicked while holding it, so that one panic does not
+/// make every later call panic too.
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Default)]
+struct Registry {
+    handlers: HashMap<u8, SharedHandler>,
+    unhandled: HashMap<u8, u64>,
+}
+
+/// Handlers of the protocols other than ICMP, which the driver handles itself.
+///
+/// Clones share the handlers, so they can be changed while the driver is receiving.
+#[derive(Clone, Default)]
+pub struct ProtocolRegistry {
+    inner: Arc<Mutex<Registry>>,
+}
+
+impl ProtocolRegistry {
+    pub fn new() -> Self {
+        ProtocolRegistry::default()
+    }
+
+    /// Installs `handler` for `protocol`, returning whether one was replaced.
+    /// ICMP is refused, as its datagrams never reach the registry.
+    pub fn register<H: ProtocolHandler + 'static>(
+        &self,
+        protocol: u8,
+        handler: H,
+    ) -> Result<bool, IpError> {
+        if protocol == ICMP_PROTOCOL_NUMBER {
+            return Err(IpError::ReservedProtocol(protocol));
+        }
+        Ok(lock(&self.inner)
+            .handlers
+            .insert(protocol, Arc::new(Mutex::new(Box::new(handler))))
+            .is_some())
+    }
+
+    /// Removes the handler of `protocol`, returning whether there was one.
+    pub fn unregister(&self, protocol: u8) -> bool {
+        lock(&self.inner).handlers.remove(&protocol).is_some()
+    }
+
+    /// The protocols with a handler, in ascending order.
+    pub fn protocols(&self) -> Vec<u8> {
+        let mut protocols: Vec<_> = lock(&self.inner).handlers.keys().cloned().collect();
+        protocols.sort_unstable();
+        protocols
+    }
+
+    /// How many datagrams of `protocol` were dropped for lack of a handler.
+    pub fn unhandled_count(&self, protocol: u8) -> u64 {
+        lock(&self.inner)
+            .unhandled
+            .get(&protocol)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// The numbers of dropped datagrams by protocol.
+    pub fn unhandled_counts(&self) -> HashMap<u8, u64> {
+        lock(&self.inner).unhandled.clone()
+    }
+
+    /// Passes a datagram to the handler of `protocol`, or counts it as unhandled.
+    /// Returns whether there was a handler.
+    pub(super) fn dispatch(
+        &self,
+        protocol: u8,
+        payload: &[u8],
+        src: IpAddress,
+        dst: IpAddress,
+        sender: &mut dyn DatagramSender,
+    ) -> bool {
+        let handler = {
+            let mut registry = lock(&self.inner);
+            match registry.handlers.get(&protocol) {
+                Some(handler) => handler.clone(),
+                None => {
+                    *registry.unhandled.entry(protocol).or_insert(0) += 1;
+                    return false;
+                }
+            }
+        };
+        // The registry is unlocked, so that the handler may change it.
+        lock(&handler).handle(payload, src, dst, sender);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builder::Ipv4Builder;
+    use crate::ether::hub::fixtures::{host, run_until};
+    use crate::ether::hub::VirtualHub;
+    use crate::ip::error::IpError;
+    use crate::ip::icmp::ICMP_PROTOCOL_NUMBER;
+    use crate::ip::protocol::*;
+    use crate::ip::{InterfaceAddress, IpDriver, IpParse, IpReply};
+    use crate::packet::PacketBuf;
+    use crate::Destination;
+    use futures::channel::mpsc::unbounded;
+    use futures::prelude::*;
+    use std::panic;
+
+    /// Reserved for experimentation and testing (RFC 3692).
+    const PROTOCOL_LAB: u8 = 253;
+    const PROTOCOL_OTHER_LAB: u8 = 254;
+
+    #[test]
+    fn test_dispatch() {
+        let my_addr = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let secondary = IpAddress::new_be_bytes([10, 0, 0, 101]);
+        let peer = IpAddress::new_be_bytes([10, 0, 0, 2]);
+        let mut driver = IpDriver::new(my_addr);
+        driver
+            .add_address(InterfaceAddress::new(secondary, 24))
+            .unwrap();
+        driver
+            .registry()
+            .register(
+                PROTOCOL_LAB,
+                |payload: &[u8], src, _, sender: &mut dyn DatagramSender| {
+                    sender.send_datagram(PROTOCOL_LAB, src, &payload.to_ascii_uppercase());
+                },
+            )
+            .unwrap();
+        let datagram = |protocol| {
+            Ipv4Builder::new(peer, secondary).build(protocol, PacketBuf::from_payload(b"hello"))
+        };
+
+        // Replies are sent along with the other pending datagrams.
+        assert!(matches!(
+            driver.parse(&datagram(PROTOCOL_LAB), Destination::ToMyself),
+            Ok(IpReply::Nop)
+        ));
+        match &driver.take_pending()[..] {
+            [IpReply::Reply { dst, data }] => {
+                assert_eq!(*dst, peer);
+                assert_eq!(data[9], PROTOCOL_LAB);
+                assert_eq!(&data[12..20], &[10, 0, 0, 101, 10, 0, 0, 2]);
+                assert_eq!(&data[20..], b"HELLO");
+            }
+            _ => panic!("no reply from the handler"),
+        }
+
+        assert!(matches!(
+            driver.parse(&datagram(PROTOCOL_OTHER_LAB), Destination::ToMyself),
+            Err(IpError::Unimplemented)
+        ));
+        let registry = driver.registry();
+        assert_eq!(registry.unhandled_count(PROTOCOL_OTHER_LAB), 1);
+        assert_eq!(registry.protocols(), vec![PROTOCOL_LAB]);
+        assert!(registry.unregister(PROTOCOL_LAB));
+        assert!(!registry.unregister(PROTOCOL_LAB));
+
+        // The driver answers ICMP itself.
+        assert!(matches!(
+            registry.register(
+                ICMP_PROTOCOL_NUMBER,
+                |_: &[u8], _, _, _: &mut dyn DatagramSender| {}
+            ),
+            Err(IpError::ReservedProtocol(ICMP_PROTOCOL_NUMBER))
+        ));
+    }
+
+    #[test]
+    fn test_panicking_handler() {
+        struct Discard;
+        impl DatagramSender for Discard {
+            fn send_datagram(&mut self, _: u8, _: IpAddress, _: &[u8]) {}
+        }
+
+        let registry = ProtocolRegistry::new();
+        let mut handled = 0;
+        registry
+            .register(
+                PROTOCOL_LAB,
+                move |_: &[u8], _, _, _: &mut dyn DatagramSender| {
+                    handled += 1;
+                    assert!(handled > 1, "the first datagram is fatal");
+                },
+            )
+            .unwrap();
+        let addr = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let dispatch = || registry.dispatch(PROTOCOL_LAB, b"lab", addr, addr, &mut Discard);
+
+        assert!(panic::catch_unwind(panic::AssertUnwindSafe(dispatch)).is_err());
+        // Neither the registry nor the handler is left unusable.
+        assert!(dispatch());
+        assert_eq!(registry.protocols(), vec![PROTOCOL_LAB]);
+    }
+
+    #[test]
+    fn test_raw_socket() {
+        let hub = VirtualHub::new();
+        let mut a = host(&hub, 1);
+        let mut b = host(&hub, 2);
+        let a_addr = IpAddress::new_be_bytes([10, 0, 0, 1]);
+        let b_addr = IpAddress::new_be_bytes([10, 0, 0, 2]);
+
+        // `a` echoes the lab datagrams back in upper case.
+        a.ip_parser_mut()
+            .registry()
+            .register(
+                PROTOCOL_LAB,
+                |payload: &[u8], src, _, sender: &mut dyn DatagramSender| {
+                    sender.send_datagram(PROTOCOL_LAB, src, &payload.to_ascii_uppercase());
+                },
+            )
+            .unwrap();
+        let (replies, mut received) = unbounded();
+        b.ip_parser_mut()
+            .registry()
+            .register(
+                PROTOCOL_LAB,
+                move |payload: &[u8], src, dst, _: &mut dyn DatagramSender| {
+                    replies
+                        .unbounded_send((src, dst, payload.to_vec()))
+                        .unwrap();
+                },
+            )
+            .unwrap();
+
+        let sent = b.send_ipv4(PROTOCOL_LAB, a_addr, PacketBuf::from_payload(b"hello"));
+        let reply = run_until(future::join(sent, received.next()), vec![a, b]);
+        assert_eq!(reply.0, Some(()));
+        assert_eq!(reply.1, Some((a_addr, b_addr, b"HELLO".to_vec())));
+    }
+}
@@ -0,0 +1,200 @@
+use super::error::IpError;
+use super::IpAddress;
+use std::cmp::Reverse;
+use std::fmt;
+
+/// The metric of the route to the subnet of the host, set up with its netmask.
+pub const CONNECTED_ROUTE_METRIC: u32 = 0;
+/// The metric of routes added without one.
+pub const DEFAULT_ROUTE_METRIC: u32 = 100;
+
+/// The addresses whose first `prefix_len` bits are those of `destination`, reached on the
+/// link or through `gateway`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub destination: IpAddress,
+    pub prefix_len: u8,
+    pub gateway: Option<IpAddress>,
+    /// Among the routes of the longest matching prefix, the lowest metric wins.
+    pub metric: u32,
+}
+
+impl Route {
+    /// A route to hosts on the link, resolved directly.
+    pub fn on_link(destination: IpAddress, prefix_len: u8) -> Self {
+        Route {
+            destination,
+            prefix_len,
+            gateway: None,
+            metric: DEFAULT_ROUTE_METRIC,
+        }
+    }
+
+    /// A route through `gateway`, which should itself be on the link.
+    pub fn via(destination: IpAddress, prefix_len: u8, gateway: IpAddress) -> Self {
+        Route {
+            gateway: Some(gateway),
+            ..Route::on_link(destination, prefix_len)
+        }
+    }
+
+    /// The route of every address, through `gateway`.
+    pub fn default_via(gateway: IpAddress) -> Self {
+        Route::via(IpAddress::new_be_bytes([0; 4]), 0, gateway)
+    }
+
+    pub fn metric(self, metric: u32) -> Self {
+        Route { metric, ..self }
+    }
+
+    pub fn contains(&self, addr: IpAddress) -> bool {
+        let mask = prefix_mask(self.prefix_len);
+        u32::from(addr) & mask == u32::from(self.destination)
+    }
+
+    /// The host to resolve on the link to send to `dst`.
+    pub fn next_hop(&self, dst: IpAddress) -> IpAddress {
+        self.gateway.unwrap_or(dst)
+    }
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}/{}", self.destination, self.prefix_len)?;
+        match self.gateway {
+            Some(gateway) => write!(f, " via {:?}", gateway)?,
+            None => write!(f, " on link")?,
+        }
+        write!(f, " metric {}", self.metric)
+    }
+}
+
+/// The mask of a prefix of `prefix_len` bits, at most 32.
+pub fn prefix_mask(prefix_len: u8) -> u32 {
+    u32::MAX
+        .checked_shl(32 - prefix_len.min(32) as u32)
+        .unwrap_or(0)
+}
+
+/// The length of the prefix `mask` is made of, if it is one.
+pub fn prefix_len(mask: IpAddress) -> Option<u8> {
+    let mask = u32::from(mask);
+    let len = mask.leading_ones() as u8;
+    if prefix_mask(len) == mask {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// The routes of the host, looked up by the longest matching prefix (RFC 1812, 5.2.4.3).
+#[derive(Debug, Clone, Default)]
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    pub fn new() -> Self {
+        RoutingTable::default()
+    }
+
+    /// Adds `route`, replacing the one to the same prefix through the same gateway.
+    /// The destination must have no bits beyond the prefix.
+    pub fn add(&mut self, route: Route) -> Result<(), IpError> {
+        if route.prefix_len > 32 || !route.contains(route.destination) {
+            return Err(IpError::InvalidRoute(route));
+        }
+        self.remove(&route);
+        self.routes.push(route);
+        Ok(())
+    }
+
+    /// Removes the route to the same prefix through the same gateway as `route`, whatever
+    /// its metric, returning it.
+    pub fn remove(&mut self, route: &Route) -> Option<Route> {
+        let i = self.routes.iter().position(|other| {
+            (other.destination, other.prefix_len, other.gateway)
+                == (route.destination, route.prefix_len, route.gateway)
+        })?;
+        Some(self.routes.remove(i))
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// The route to `dst`, if any.
+    pub fn lookup(&self, dst: IpAddress) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|route| route.contains(dst))
+            .min_by_key(|route| (Reverse(route.prefix_len), route.metric))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ip::error::IpError;
+    use crate::ip::route::*;
+
+    fn addr(a: u8, b: u8, c: u8, d: u8) -> IpAddress {
+        IpAddress::new_be_bytes([a, b, c, d])
+    }
+
+    fn next_hop(table: &RoutingTable, dst: IpAddress) -> IpAddress {
+        table.lookup(dst).unwrap().next_hop(dst)
+    }
+
+    #[test]
+    fn test_prefix() {
+        assert_eq!(prefix_mask(0), 0);
+        assert_eq!(prefix_mask(24), 0xFFFF_FF00);
+        assert_eq!(prefix_mask(32), u32::MAX);
+        assert_eq!(prefix_len(addr(255, 255, 240, 0)), Some(20));
+        assert_eq!(prefix_len(addr(0, 0, 0, 0)), Some(0));
+        assert_eq!(prefix_len(addr(255, 0, 255, 0)), None);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let gateway = addr(10, 0, 0, 254);
+        let backup = addr(10, 0, 0, 253);
+        let lab = addr(10, 0, 0, 100);
+        let mut table = RoutingTable::new();
+        table.add(Route::on_link(addr(10, 0, 0, 0), 24)).unwrap();
+        table.add(Route::default_via(gateway)).unwrap();
+        table
+            .add(Route::default_via(backup).metric(DEFAULT_ROUTE_METRIC + 1))
+            .unwrap();
+        table
+            .add(Route::via(addr(192, 168, 10, 0), 24, lab))
+            .unwrap();
+
+        assert_eq!(next_hop(&table, addr(10, 0, 0, 7)), addr(10, 0, 0, 7));
+        assert_eq!(next_hop(&table, addr(192, 168, 10, 1)), lab);
+        assert_eq!(next_hop(&table, addr(192, 168, 11, 1)), gateway);
+        assert_eq!(next_hop(&table, addr(8, 8, 8, 8)), gateway);
+
+        // Replacing a route changes its metric.
+        table
+            .add(Route::default_via(backup).metric(DEFAULT_ROUTE_METRIC - 1))
+            .unwrap();
+        assert_eq!(table.routes().len(), 4);
+        assert_eq!(next_hop(&table, addr(8, 8, 8, 8)), backup);
+
+        assert!(table.remove(&Route::default_via(backup)).is_some());
+        assert!(table.remove(&Route::default_via(backup)).is_none());
+        assert!(table.remove(&Route::default_via(gateway)).is_some());
+        assert!(table.lookup(addr(8, 8, 8, 8)).is_none());
+
+        assert!(matches!(
+            table.add(Route::on_link(addr(10, 0, 0, 1), 24)),
+            Err(IpError::InvalidRoute(_))
+        ));
+        assert!(table.add(Route::on_link(addr(10, 0, 0, 1), 33)).is_err());
+        assert_eq!(
+            format!("{:?}", table.routes()[1]),
+            "192.168.10.0/24 via 10.0.0.100 metric 100"
+        );
+    }
+}
@@ -5,14 +5,22 @@ extern crate failure;
 extern crate futures;
 
 pub mod arp;
+pub mod builder;
 pub mod ether;
 pub mod ip;
+pub mod packet;
+pub mod reactor;
 pub mod socket;
 pub mod utils;
+pub mod wire;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Destination {
     ToMyself,
     Broadcast,
+    /// A frame sent to an Ethernet multicast group the driver has joined.
+    Multicast,
     Promisc,
+    /// A frame this machine has sent, as seen by a packet socket.
+    Outgoing,
 }
@@ -1,38 +1,61 @@
 use virtual_ip_host::arp::EtherIpResolver;
+use virtual_ip_host::ether::device::{LinkDevice, RawSocketDevice};
 use virtual_ip_host::ether::driver::EthernetDriver;
-use virtual_ip_host::ether::MacAddress;
 use virtual_ip_host::ip::IpAddress;
 use virtual_ip_host::ip::IpDriver;
+use virtual_ip_host::reactor::block_on;
 use virtual_ip_host::socket::Socket;
-use virtual_ip_host::utils;
 
-use futures::executor::block_on;
 use futures::prelude::*;
 
-fn main() {
-    unsafe {
-        let mut s = Socket::open_raw_socket();
-        if s.fd == -1 {
-            utils::show_error_text();
-            return;
-        }
+const DEFAULT_INTERFACE_NAME: &str = "enp0s3";
+
+fn open_device(iface_name: &str) -> Result<RawSocketDevice, String> {
+    let device = RawSocketDevice::open_with(iface_name, Socket::enable_promisc_mode)
+        .map_err(|err| format!("Cannot open the interface {}: {}", iface_name, err))?;
+    println!(
+        "- Using {} ({:?}, MTU {})",
+        iface_name,
+        device.hardware_address(),
+        device.mtu()
+    );
+    Ok(device)
+}
 
-        s.limit_interface("enp0s3")
-            .unwrap_or_else(|| utils::show_error_text());
+fn main() {
+    let iface_name = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_INTERFACE_NAME.to_owned());
 
-        s.enable_promisc_mode()
-            .unwrap_or_else(|| utils::show_error_text());
+    let device = match open_device(&iface_name) {
+        Ok(device) => device,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
 
-        let mut driver = EthernetDriver::<EtherIpResolver, IpDriver>::new(
-            MacAddress::new([0x02, 0x00, 0x00, 0xEF, 0x24, 0xA8]),
-            IpAddress::new_be_bytes([192, 168, 56, 150]),
-            false,
-            s,
-        );
-        let arp_test = driver
-            .resolve(IpAddress::new_be_bytes([192, 168, 56, 1]))
-            .map(|d| println!("- ARP Resolving Result: {:?}", d));
-        let recv = driver.recv();
-        let _ = block_on(future::join(arp_test, recv.for_each(|_| future::ready(()))).map(|_| ()));
+    let mut driver = EthernetDriver::<EtherIpResolver, IpDriver, _>::new(
+        IpAddress::new_be_bytes([192, 168, 56, 150]),
+        false,
+        device,
+    );
+    // The subnet of VirtualBox's host-only network.
+    if let Err(err) = driver
+        .ip_parser_mut()
+        .set_netmask(IpAddress::new_be_bytes([255, 255, 255, 0]))
+    {
+        eprintln!("Cannot set the netmask: {}", err);
+        std::process::exit(1);
     }
+    if let Err(err) = driver.attach_default_filter() {
+        eprintln!("Cannot attach the packet filter: {}", err);
+        std::process::exit(1);
+    }
+
+    let arp_test = driver
+        .resolve(IpAddress::new_be_bytes([192, 168, 56, 1]))
+        .map(|d| println!("- ARP Resolving Result: {:?}", d));
+    let recv = driver.recv();
+    block_on(future::join(arp_test, recv.for_each(|_| future::ready(()))));
 }
@@ -0,0 +1,324 @@
+//! Packet buffers with room in front, so that each layer prepends its header in place
+//! instead of copying the packet of the layer above.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Room for an Ethernet header with two VLAN tags and an IPv4 header with options.
+pub const DEFAULT_HEADROOM: usize = 128;
+/// Size of the buffers of the default pool, enough for a frame of the usual MTU.
+pub const DEFAULT_BUFFER_SIZE: usize = 2048;
+/// Buffers the default pool keeps for reuse.
+const DEFAULT_POOL_CAPACITY: usize = 256;
+
+struct Pool {
+    free: Mutex<Vec<Vec<u8>>>,
+    buffer_size: usize,
+    capacity: usize,
+}
+
+/// Buffers of one size, handed out as `PacketBuf`s which come back when dropped.
+/// Clones share the buffers.
+#[derive(Clone)]
+pub struct BufferPool {
+    pool: Arc<Pool>,
+}
+
+impl BufferPool {
+    /// A pool of `buffer_size`-byte buffers, keeping up to `capacity` of them.
+    pub fn new(buffer_size: usize, capacity: usize) -> Self {
+        BufferPool {
+            pool: Arc::new(Pool {
+                free: Mutex::new(Vec::with_capacity(capacity)),
+                buffer_size,
+                capacity,
+            }),
+        }
+    }
+
+    /// An empty packet whose data starts after `headroom` bytes.
+    pub fn get(&self, headroom: usize) -> PacketBuf {
+        let buf = self
+            .pool
+            .free
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| vec![0; self.pool.buffer_size.max(headroom)]);
+        let headroom = headroom.min(buf.len());
+
+        PacketBuf {
+            buf,
+            start: headroom,
+            end: headroom,
+            pool: Some(self.clone()),
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.pool.buffer_size
+    }
+
+    /// Buffers ready for reuse.
+    pub fn available(&self) -> usize {
+        self.pool.free.lock().unwrap().len()
+    }
+
+    fn put(&self, buf: Vec<u8>) {
+        // A buffer grown by `prepend` or `append` is left to the allocator.
+        if buf.len() != self.pool.buffer_size {
+            return;
+        }
+        let mut free = self.pool.free.lock().unwrap();
+        if free.len() < self.pool.capacity {
+            free.push(buf);
+        }
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BufferPool({} bytes, {} available)",
+            self.pool.buffer_size,
+            self.available()
+        )
+    }
+}
+
+/// The pool behind `PacketBuf::new`.
+pub fn default_pool() -> &'static BufferPool {
+    static DEFAULT_POOL: OnceLock<BufferPool> = OnceLock::new();
+    DEFAULT_POOL.get_or_init(|| BufferPool::new(DEFAULT_BUFFER_SIZE, DEFAULT_POOL_CAPACITY))
+}
+
+/// The bytes of a packet within a larger buffer, with headroom before them and tailroom
+/// after them. It dereferences to the packet bytes.
+///
+/// Running out of room reallocates, so the sizes only matter for performance.
+pub struct PacketBuf {
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    pool: Option<BufferPool>,
+}
+
+impl PacketBuf {
+    /// An empty packet from the default pool, with `DEFAULT_HEADROOM`.
+    pub fn new() -> Self {
+        default_pool().get(DEFAULT_HEADROOM)
+    }
+
+    /// A packet from the default pool holding a copy of `data`.
+    pub fn from_payload(data: &[u8]) -> Self {
+        let mut packet = PacketBuf::new();
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    /// A packet owning `data`, without headroom and outside any pool.
+    pub fn from_vec(data: Vec<u8>) -> Self {
+        let end = data.len();
+        PacketBuf {
+            buf: data,
+            start: 0,
+            end,
+            pool: None,
+        }
+    }
+
+    pub fn headroom(&self) -> usize {
+        self.start
+    }
+
+    pub fn tailroom(&self) -> usize {
+        self.buf.len() - self.end
+    }
+
+    /// Extends the packet by `len` bytes at the front, returning them for the header to be
+    /// written. Their contents are unspecified.
+    pub fn prepend(&mut self, len: usize) -> &mut [u8] {
+        if len > self.start {
+            let extra = len - self.start + DEFAULT_HEADROOM;
+            let mut buf = vec![0; extra + self.buf.len()];
+            buf[extra + self.start..extra + self.end]
+                .copy_from_slice(&self.buf[self.start..self.end]);
+            self.buf = buf;
+            self.start += extra;
+            self.end += extra;
+        }
+        self.start -= len;
+        &mut self.buf[self.start..self.start + len]
+    }
+
+    /// Extends the packet by `len` zeroed bytes at the back, returning them.
+    pub fn append(&mut self, len: usize) -> &mut [u8] {
+        let tail = self.append_unspecified(len);
+        for b in tail.iter_mut() {
+            *b = 0;
+        }
+        tail
+    }
+
+    pub fn extend_from_slice(&mut self, data: &[u8]) {
+        self.append_unspecified(data.len()).copy_from_slice(data);
+    }
+
+    /// Like `append`, but leaves whatever the buffer held, for data about to be overwritten.
+    pub(crate) fn append_unspecified(&mut self, len: usize) -> &mut [u8] {
+        if len > self.tailroom() {
+            self.buf.resize(self.end + len, 0);
+        }
+        let end = self.end;
+        self.end += len;
+        &mut self.buf[end..end + len]
+    }
+
+    /// Removes `len` bytes from the front, e.g. a header already parsed.
+    pub fn pull(&mut self, len: usize) {
+        assert!(len <= self.len(), "pulling more than the packet");
+        self.start += len;
+    }
+
+    /// Shortens the packet to `len` bytes, doing nothing if it is not longer.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            self.end = self.start + len;
+        }
+    }
+
+    /// Resizes the packet to `len` bytes, filling any new ones with `value`.
+    pub fn resize(&mut self, len: usize, value: u8) {
+        let current = self.len();
+        if len <= current {
+            self.truncate(len);
+        } else {
+            for b in self.append_unspecified(len - current).iter_mut() {
+                *b = value;
+            }
+        }
+    }
+
+    /// Empties the packet, leaving `headroom` bytes in front.
+    pub fn clear(&mut self, headroom: usize) {
+        self.start = headroom.min(self.buf.len());
+        self.end = self.start;
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl Default for PacketBuf {
+    fn default() -> Self {
+        PacketBuf::new()
+    }
+}
+
+impl Clone for PacketBuf {
+    fn clone(&self) -> Self {
+        let mut packet = match &self.pool {
+            Some(pool) => pool.get(self.headroom()),
+            None => PacketBuf::from_vec(vec![0; self.headroom()]),
+        };
+        packet.clear(self.headroom());
+        packet.extend_from_slice(self);
+        packet
+    }
+}
+
+impl Drop for PacketBuf {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(std::mem::take(&mut self.buf));
+        }
+    }
+}
+
+impl Deref for PacketBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+}
+
+impl DerefMut for PacketBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[self.start..self.end]
+    }
+}
+
+impl PartialEq for PacketBuf {
+    fn eq(&self, other: &PacketBuf) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for PacketBuf {}
+
+impl fmt::Debug for PacketBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::packet::*;
+
+    #[test]
+    fn test_prepend_in_place() {
+        let mut packet = PacketBuf::from_payload(b"payload");
+        let buffer = packet.as_ptr();
+        packet.prepend(4).copy_from_slice(b"ip..");
+        packet.prepend(3).copy_from_slice(b"eth");
+        assert_eq!(&packet[..], b"ethip..payload");
+        // Nothing has moved.
+        assert_eq!(unsafe { packet.as_ptr().add(7) }, buffer);
+        assert_eq!(packet.headroom(), DEFAULT_HEADROOM - 7);
+
+        packet.pull(3);
+        packet.truncate(4);
+        assert_eq!(&packet[..], b"ip..");
+        packet.resize(6, b'!');
+        assert_eq!(&packet[..], b"ip..!!");
+    }
+
+    #[test]
+    fn test_out_of_room() {
+        let mut packet = PacketBuf::from_vec(b"data".to_vec());
+        packet.prepend(2).copy_from_slice(b"hd");
+        packet.append(2);
+        assert_eq!(&packet[..], b"hddata\0\0");
+        assert!(packet.headroom() >= DEFAULT_HEADROOM);
+    }
+
+    #[test]
+    fn test_pool() {
+        let pool = BufferPool::new(64, 2);
+        let first = pool.get(16);
+        let second = pool.get(16);
+        let third = pool.get(16);
+        assert_eq!(first.tailroom(), 48);
+        drop(first);
+        drop(second);
+        drop(third);
+        assert_eq!(pool.available(), 2);
+
+        // The buffers are reused, whatever headroom is asked for.
+        let reused = pool.get(0);
+        assert_eq!(pool.available(), 1);
+        assert_eq!(reused.tailroom(), 64);
+
+        // A buffer which had to grow is not taken back.
+        let mut grown = pool.get(0);
+        grown.append(65);
+        drop(grown);
+        drop(reused);
+        assert_eq!(pool.available(), 1);
+    }
+}
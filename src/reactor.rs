@@ -0,0 +1,356 @@
+//! A minimal epoll-based reactor, driven by `block_on` on the current thread.
+//!
+//! Descriptors are registered lazily with the reactor of the thread which polls them,
+//! so futures waiting on a `Registration` must be run by `block_on` of this module.
+
+use futures::pin_mut;
+use futures::task::{waker_ref, ArcWake};
+use libc::{
+    c_int, close, epoll_create1, epoll_ctl, epoll_event, epoll_wait, eventfd, itimerspec, read,
+    timerfd_create, timerfd_settime, timespec, write, CLOCK_MONOTONIC, EFD_CLOEXEC, EFD_NONBLOCK,
+    EINTR, EPOLLIN, EPOLLONESHOT, EPOLL_CLOEXEC, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD,
+    TFD_CLOEXEC, TFD_NONBLOCK,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+const NOTIFY_TOKEN: u64 = 0;
+const N_EVENTS: usize = 64;
+
+fn check(result: c_int) -> io::Result<c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Wakes `block_on` from `epoll_wait` through an eventfd.
+struct Notifier {
+    event_fd: c_int,
+}
+
+impl ArcWake for Notifier {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        let one = 1u64;
+        unsafe {
+            write(arc_self.event_fd, &one as *const _ as _, 8);
+        }
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.event_fd);
+        }
+    }
+}
+
+struct Reactor {
+    epoll_fd: c_int,
+    notifier: Arc<Notifier>,
+    wakers: Mutex<HashMap<u64, Waker>>,
+    next_token: AtomicU64,
+}
+
+impl fmt::Debug for Reactor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Reactor({})", self.epoll_fd)
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<Reactor>>> = const { RefCell::new(None) };
+}
+
+fn current() -> io::Result<Arc<Reactor>> {
+    CURRENT.with(|current| {
+        let mut current = current.borrow_mut();
+        if current.is_none() {
+            *current = Some(Arc::new(Reactor::new()?));
+        }
+        Ok(current.as_ref().unwrap().clone())
+    })
+}
+
+impl Reactor {
+    fn new() -> io::Result<Reactor> {
+        let epoll_fd = check(unsafe { epoll_create1(EPOLL_CLOEXEC) })?;
+        let event_fd = match check(unsafe { eventfd(0, EFD_CLOEXEC | EFD_NONBLOCK) }) {
+            Ok(fd) => fd,
+            Err(err) => {
+                unsafe { close(epoll_fd) };
+                return Err(err);
+            }
+        };
+
+        let reactor = Reactor {
+            epoll_fd,
+            notifier: Arc::new(Notifier { event_fd }),
+            wakers: Mutex::new(HashMap::new()),
+            next_token: AtomicU64::new(NOTIFY_TOKEN + 1),
+        };
+        reactor.control(EPOLL_CTL_ADD, event_fd, EPOLLIN, NOTIFY_TOKEN)?;
+        Ok(reactor)
+    }
+
+    fn control(&self, op: c_int, fd: c_int, events: c_int, token: u64) -> io::Result<()> {
+        let mut event = epoll_event {
+            events: events as _,
+            u64: token,
+        };
+        check(unsafe { epoll_ctl(self.epoll_fd, op, fd, &mut event) }).map(|_| ())
+    }
+
+    fn add(&self, fd: c_int) -> io::Result<u64> {
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        // Nothing is reported until the descriptor is armed.
+        self.control(EPOLL_CTL_ADD, fd, EPOLLONESHOT, token)?;
+        Ok(token)
+    }
+
+    fn arm(&self, fd: c_int, token: u64, waker: &Waker) -> io::Result<()> {
+        self.wakers.lock().unwrap().insert(token, waker.clone());
+        self.control(EPOLL_CTL_MOD, fd, EPOLLIN | EPOLLONESHOT, token)
+    }
+
+    fn remove(&self, fd: c_int, token: u64) {
+        self.wakers.lock().unwrap().remove(&token);
+        // Fails if the descriptor is already closed, which removes it from epoll anyway.
+        let _ = self.control(EPOLL_CTL_DEL, fd, 0, token);
+    }
+
+    /// Blocks until a registered descriptor is ready or `notifier` is woken.
+    fn wait(&self) -> io::Result<()> {
+        let mut events = [epoll_event { events: 0, u64: 0 }; N_EVENTS];
+        let n = unsafe { epoll_wait(self.epoll_fd, events.as_mut_ptr(), N_EVENTS as _, -1) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(EINTR) => Ok(()),
+                _ => Err(err),
+            };
+        }
+
+        for event in &events[..n as usize] {
+            let token = event.u64;
+            if token == NOTIFY_TOKEN {
+                let mut count = 0u64;
+                unsafe {
+                    read(self.notifier.event_fd, &mut count as *mut _ as _, 8);
+                }
+            } else if let Some(waker) = self.wakers.lock().unwrap().remove(&token) {
+                waker.wake();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.epoll_fd);
+        }
+    }
+}
+
+/// Readiness notification for a non-blocking descriptor, owned by the descriptor's wrapper.
+/// It must be dropped before, or together with, the descriptor.
+#[derive(Debug)]
+pub struct Registration {
+    fd: c_int,
+    current: Mutex<Option<(Arc<Reactor>, u64)>>,
+}
+
+impl Registration {
+    pub fn new(fd: c_int) -> Self {
+        Registration {
+            fd,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Arranges for `waker` to be woken once when the descriptor becomes readable.
+    pub fn wait_readable(&self, waker: &Waker) -> io::Result<()> {
+        let reactor = current()?;
+        let mut current = self.current.lock().unwrap();
+
+        let token = match current.take() {
+            Some((registered, token)) if Arc::ptr_eq(&registered, &reactor) => token,
+            other => {
+                if let Some((registered, token)) = other {
+                    registered.remove(self.fd, token);
+                }
+                reactor.add(self.fd)?
+            }
+        };
+        *current = Some((reactor.clone(), token));
+
+        reactor.arm(self.fd, token, waker)
+    }
+
+    /// Removes the descriptor from the reactor, as its wrapper must do before closing it.
+    pub fn deregister(&self) {
+        if let Some((reactor, token)) = self.current.lock().unwrap().take() {
+            reactor.remove(self.fd, token);
+        }
+    }
+
+    /// Runs the non-blocking operation `f`, waiting for readability when it would block.
+    pub fn poll_read_with<T, F>(&self, cx: &mut Context, mut f: F) -> Poll<io::Result<T>>
+    where
+        F: FnMut() -> io::Result<T>,
+    {
+        match f() {
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                match self.wait_readable(cx.waker()) {
+                    Ok(()) => Poll::Pending,
+                    Err(err) => Poll::Ready(Err(err)),
+                }
+            }
+            result => Poll::Ready(result),
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.deregister();
+    }
+}
+
+/// A timer expiring every `period`, through a timerfd.
+#[derive(Debug)]
+pub struct Interval {
+    fd: c_int,
+    registration: Registration,
+}
+
+impl Interval {
+    /// Starts the timer, which first expires after `period`. A zero `period` never expires.
+    pub fn new(period: Duration) -> io::Result<Self> {
+        let fd = check(unsafe { timerfd_create(CLOCK_MONOTONIC, TFD_NONBLOCK | TFD_CLOEXEC) })?;
+        let period = timespec {
+            tv_sec: period.as_secs() as _,
+            tv_nsec: period.subsec_nanos() as _,
+        };
+        let spec = itimerspec {
+            it_interval: period,
+            it_value: period,
+        };
+        if let Err(err) = check(unsafe { timerfd_settime(fd, 0, &spec, ptr::null_mut()) }) {
+            unsafe { close(fd) };
+            return Err(err);
+        }
+
+        Ok(Interval {
+            fd,
+            registration: Registration::new(fd),
+        })
+    }
+
+    /// Completes with the number of expiries since the last call, waking the task through
+    /// the reactor once the timer expires.
+    pub fn poll_tick(&self, cx: &mut Context) -> Poll<io::Result<u64>> {
+        self.registration.poll_read_with(cx, || {
+            let mut count = 0u64;
+            check(unsafe { read(self.fd, &mut count as *mut _ as _, 8) } as c_int)?;
+            Ok(count)
+        })
+    }
+}
+
+impl Drop for Interval {
+    fn drop(&mut self) {
+        self.registration.deregister();
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
+
+/// Runs `f` to completion on the current thread, sleeping in `epoll_wait` while it is pending.
+pub fn block_on<F: Future>(f: F) -> F::Output {
+    let reactor = current().expect("cannot create the epoll reactor");
+    let notifier = reactor.notifier.clone();
+    let waker = waker_ref(&notifier);
+    let mut cx = Context::from_waker(&waker);
+    pin_mut!(f);
+
+    loop {
+        if let Poll::Ready(output) = f.as_mut().poll(&mut cx) {
+            return output;
+        }
+        reactor.wait().expect("epoll_wait failed");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::reactor::*;
+    use futures::future;
+    use libc::{pipe2, O_NONBLOCK};
+    use std::time::Duration;
+
+    #[test]
+    fn test_block_on_readable() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { pipe2(fds.as_mut_ptr(), O_NONBLOCK) }, 0);
+        let (read_fd, write_fd) = (fds[0], fds[1]);
+        let registration = Registration::new(read_fd);
+
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            unsafe { write(write_fd, b"x".as_ptr() as _, 1) };
+        });
+
+        let byte = block_on(future::poll_fn(|cx| {
+            registration.poll_read_with(cx, || {
+                let mut byte = 0u8;
+                match unsafe { read(read_fd, &mut byte as *mut _ as _, 1) } {
+                    1 => Ok(byte),
+                    _ => Err(io::Error::last_os_error()),
+                }
+            })
+        }));
+        assert_eq!(byte.unwrap(), b'x');
+
+        writer.join().unwrap();
+        drop(registration);
+        unsafe {
+            close(read_fd);
+            close(write_fd);
+        }
+    }
+
+    #[test]
+    fn test_block_on_woken() {
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            sender.send(42).unwrap();
+        });
+        assert_eq!(block_on(receiver), Ok(42));
+    }
+
+    #[test]
+    fn test_interval() {
+        let interval = Interval::new(Duration::from_millis(20)).unwrap();
+        let start = std::time::Instant::now();
+        for _ in 0..3 {
+            let ticks = block_on(future::poll_fn(|cx| interval.poll_tick(cx))).unwrap();
+            assert!(ticks >= 1);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+}
@@ -0,0 +1,234 @@
+//! Classic BPF programs run by the kernel on each frame before it is queued to the socket.
+
+use libc::{c_ushort, ETH_ALEN};
+
+/// ./linux/bpf_common.h:#define BPF_LD 0x00
+pub const BPF_LD: u16 = 0x00;
+/// ./linux/bpf_common.h:#define BPF_JMP 0x05
+pub const BPF_JMP: u16 = 0x05;
+/// ./linux/bpf_common.h:#define BPF_RET 0x06
+pub const BPF_RET: u16 = 0x06;
+/// ./linux/bpf_common.h:#define BPF_W 0x00
+pub const BPF_W: u16 = 0x00;
+/// ./linux/bpf_common.h:#define BPF_H 0x08
+pub const BPF_H: u16 = 0x08;
+/// ./linux/bpf_common.h:#define BPF_B 0x10
+pub const BPF_B: u16 = 0x10;
+/// ./linux/bpf_common.h:#define BPF_ABS 0x20
+pub const BPF_ABS: u16 = 0x20;
+/// ./linux/bpf_common.h:#define BPF_JEQ 0x10
+pub const BPF_JEQ: u16 = 0x10;
+/// ./linux/bpf_common.h:#define BPF_K 0x00
+pub const BPF_K: u16 = 0x00;
+
+/// Return value of a program accepting the whole frame.
+pub const ACCEPT: u32 = 0xFFFF_FFFF;
+/// Return value of a program dropping the frame.
+pub const DROP: u32 = 0;
+
+/// One instruction, laid out as `struct sock_filter`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BpfInstruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+/// ./linux/filter.h: struct sock_fprog
+#[repr(C)]
+pub(super) struct SockFprog {
+    pub len: c_ushort,
+    pub filter: *const BpfInstruction,
+}
+
+impl BpfInstruction {
+    pub fn stmt(code: u16, k: u32) -> Self {
+        BpfInstruction {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    pub fn jump(code: u16, k: u32, jt: u8, jf: u8) -> Self {
+        BpfInstruction { code, jt, jf, k }
+    }
+}
+
+const DST_MAC_OFFSET: u32 = 0;
+const ETHER_TYPE_OFFSET: u32 = 2 * ETH_ALEN as u32;
+
+/// Destinations `ether_filter` can check, within the reach of a conditional jump.
+pub const MAX_DESTINATIONS: usize = 63;
+/// Ether types `ether_filter` can check, within the reach of a conditional jump.
+pub const MAX_ETHER_TYPES: usize = 255;
+
+/// A program admitting every frame.
+pub fn accept_all() -> Vec<BpfInstruction> {
+    vec![BpfInstruction::stmt(BPF_RET | BPF_K, ACCEPT)]
+}
+
+/// Builds a program admitting only the frames sent to one of `destinations` and carrying one
+/// of `ether_types`. `None` admits any destination, as needed in promiscuous mode, or any
+/// ether type.
+///
+/// Returns `None` with more than `MAX_DESTINATIONS` destinations or `MAX_ETHER_TYPES`
+/// ether types.
+pub fn ether_filter(
+    destinations: Option<&[[u8; ETH_ALEN as usize]]>,
+    ether_types: Option<&[u16]>,
+) -> Option<Vec<BpfInstruction>> {
+    let mut program = Vec::new();
+
+    if ether_types.is_some_and(|ether_types| ether_types.len() > MAX_ETHER_TYPES) {
+        return None;
+    }
+
+    if let Some(destinations) = destinations {
+        let n = destinations.len();
+        if n > MAX_DESTINATIONS {
+            return None;
+        }
+
+        // Each address takes four instructions and jumps to the ether type check,
+        // which starts after all of them and the final drop.
+        for (i, mac) in destinations.iter().enumerate() {
+            let hi = u32::from_be_bytes([mac[0], mac[1], mac[2], mac[3]]);
+            let lo = u16::from_be_bytes([mac[4], mac[5]]) as u32;
+            program.push(BpfInstruction::stmt(
+                BPF_LD | BPF_W | BPF_ABS,
+                DST_MAC_OFFSET,
+            ));
+            program.push(BpfInstruction::jump(BPF_JMP | BPF_JEQ | BPF_K, hi, 0, 2));
+            program.push(BpfInstruction::stmt(
+                BPF_LD | BPF_H | BPF_ABS,
+                DST_MAC_OFFSET + 4,
+            ));
+            program.push(BpfInstruction::jump(
+                BPF_JMP | BPF_JEQ | BPF_K,
+                lo,
+                (4 * (n - i) - 3) as u8,
+                0,
+            ));
+        }
+        program.push(BpfInstruction::stmt(BPF_RET | BPF_K, DROP));
+    }
+
+    let ether_types = match ether_types {
+        Some(ether_types) => ether_types,
+        None => {
+            program.push(BpfInstruction::stmt(BPF_RET | BPF_K, ACCEPT));
+            return Some(program);
+        }
+    };
+    let m = ether_types.len();
+    program.push(BpfInstruction::stmt(
+        BPF_LD | BPF_H | BPF_ABS,
+        ETHER_TYPE_OFFSET,
+    ));
+    for (j, &ether_type) in ether_types.iter().enumerate() {
+        program.push(BpfInstruction::jump(
+            BPF_JMP | BPF_JEQ | BPF_K,
+            ether_type as u32,
+            (m - j) as u8,
+            0,
+        ));
+    }
+    program.push(BpfInstruction::stmt(BPF_RET | BPF_K, DROP));
+    program.push(BpfInstruction::stmt(BPF_RET | BPF_K, ACCEPT));
+
+    Some(program)
+}
+
+/// Runs `program` on `frame` like the kernel, for devices filtering in userspace.
+/// Only the subset of classic BPF that `ether_filter` emits is supported, and any other
+/// instruction, or a load beyond the frame, drops it.
+pub fn run(program: &[BpfInstruction], frame: &[u8]) -> u32 {
+    let mut a = 0u32;
+    let mut pc = 0;
+    while let Some(&ins) = program.get(pc) {
+        pc += 1;
+        let k = ins.k as usize;
+        match ins.code {
+            c if c == BPF_LD | BPF_W | BPF_ABS => match frame.get(k..k + 4) {
+                Some(word) => a = u32::from_be_bytes([word[0], word[1], word[2], word[3]]),
+                None => return DROP,
+            },
+            c if c == BPF_LD | BPF_H | BPF_ABS => match frame.get(k..k + 2) {
+                Some(half) => a = u16::from_be_bytes([half[0], half[1]]) as u32,
+                None => return DROP,
+            },
+            c if c == BPF_JMP | BPF_JEQ | BPF_K => {
+                pc += if a == ins.k { ins.jt } else { ins.jf } as usize;
+            }
+            c if c == BPF_RET | BPF_K => return ins.k,
+            _ => return DROP,
+        }
+    }
+    DROP
+}
+
+#[cfg(test)]
+mod test {
+    use crate::socket::filter::*;
+    use crate::socket::Socket;
+    use std::io;
+
+    fn frame(dst: [u8; 6], ether_type: u16) -> Vec<u8> {
+        let mut frame = vec![0u8; 60];
+        frame[..6].copy_from_slice(&dst);
+        frame[12..14].copy_from_slice(&ether_type.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_ether_filter() {
+        let mine = [0x02, 0x00, 0x00, 0xEF, 0x24, 0xA8];
+        let broadcast = [0xFF; 6];
+        let multicast = [0x01, 0x00, 0x5E, 0x00, 0x00, 0x01];
+        let other = [0x02, 0x00, 0x00, 0xEF, 0x24, 0xA9];
+        let program =
+            ether_filter(Some(&[mine, broadcast, multicast]), Some(&[0x0800, 0x0806])).unwrap();
+
+        assert_eq!(run(&program, &frame(mine, 0x0800)), ACCEPT);
+        assert_eq!(run(&program, &frame(broadcast, 0x0806)), ACCEPT);
+        assert_eq!(run(&program, &frame(multicast, 0x0800)), ACCEPT);
+        assert_eq!(run(&program, &frame(mine, 0x86DD)), DROP);
+        assert_eq!(run(&program, &frame(other, 0x0800)), DROP);
+
+        let promisc = ether_filter(None, Some(&[0x0806])).unwrap();
+        assert_eq!(run(&promisc, &frame(other, 0x0806)), ACCEPT);
+        assert_eq!(run(&promisc, &frame(other, 0x0800)), DROP);
+        assert_eq!(run(&accept_all(), &frame(other, 0x86DD)), ACCEPT);
+
+        // Beyond the reach of the jumps, no program is built.
+        let destinations = vec![mine; MAX_DESTINATIONS];
+        assert!(ether_filter(Some(&destinations), Some(&[0x0800])).is_some());
+        let destinations = vec![mine; MAX_DESTINATIONS + 1];
+        assert!(ether_filter(Some(&destinations), Some(&[0x0800])).is_none());
+        let ether_types: Vec<u16> = (0..=MAX_ETHER_TYPES as u16).collect();
+        assert!(ether_filter(None, Some(&ether_types[..MAX_ETHER_TYPES])).is_some());
+        assert!(ether_filter(None, Some(&ether_types)).is_none());
+
+        let any_type = ether_filter(Some(&[mine]), None).unwrap();
+        assert_eq!(run(&any_type, &frame(mine, 0x86DD)), ACCEPT);
+        assert_eq!(run(&any_type, &frame(other, 0x86DD)), DROP);
+        assert_eq!(run(&program, &frame(mine, 0x0800)[..13]), DROP);
+    }
+
+    #[test]
+    fn test_attach() {
+        let socket = match Socket::open_raw_socket() {
+            Ok(socket) => socket,
+            // Raw sockets need CAP_NET_RAW.
+            Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(err) => panic!("{}", err),
+        };
+        let program = ether_filter(Some(&[[0xFF; 6]]), Some(&[0x0806])).unwrap();
+        socket.attach_filter(&program).unwrap();
+        socket.detach_filter().unwrap();
+    }
+}
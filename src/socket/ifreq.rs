@@ -1,7 +1,18 @@
 use libc::{c_char, c_int, c_short, sockaddr};
+use std::io;
+use std::mem;
 
 pub const IFNAMSIZ: usize = 16;
 
+/// ./x86_64-linux-gnu/bits/ioctls.h:#define SIOCGIFFLAGS 0x8913 /* get flags */
+pub const SIOCGIFFLAGS: usize = 0x8913;
+/// ./x86_64-linux-gnu/bits/ioctls.h:#define SIOCGIFMTU 0x8921 /* get MTU size */
+pub const SIOCGIFMTU: usize = 0x8921;
+/// ./x86_64-linux-gnu/bits/ioctls.h:#define SIOCGIFHWADDR 0x8927 /* Get hardware address */
+pub const SIOCGIFHWADDR: usize = 0x8927;
+/// ./x86_64-linux-gnu/bits/ioctls.h:#define SIOCGIFINDEX 0x8933 /* name -> if_index mapping */
+pub const SIOCGIFINDEX: usize = 0x8933;
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub union IfreqParam {
@@ -36,3 +47,24 @@ pub struct ifreq {
     pub ifr_name: [c_char; IFNAMSIZ],
     pub param: IfreqParam,
 }
+
+impl ifreq {
+    /// Zero-filled request for the interface `iface_name`, whose name is always NUL-terminated.
+    pub fn with_name(iface_name: &str) -> io::Result<ifreq> {
+        let chars = iface_name.as_bytes();
+
+        // The last byte is left for the terminating NUL.
+        if chars.len() + 1 > IFNAMSIZ || chars.contains(&0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid interface name",
+            ));
+        }
+
+        let mut if_req: ifreq = unsafe { mem::zeroed() };
+        for (dst, &c) in if_req.ifr_name.iter_mut().zip(chars) {
+            *dst = c as _;
+        }
+        Ok(if_req)
+    }
+}
@@ -0,0 +1,140 @@
+use super::{check, ifreq};
+use libc::{
+    c_int, close, if_freenameindex, if_nameindex, ioctl, socket, AF_INET, ETH_ALEN, IFF_LOOPBACK,
+    IFF_PROMISC, IFF_RUNNING, IFF_UP, SOCK_DGRAM,
+};
+use std::ffi::CStr;
+use std::io;
+
+/// Properties of a network interface, as reported by the kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub name: String,
+    pub index: c_int,
+    pub hardware_address: [u8; ETH_ALEN as usize],
+    pub mtu: usize,
+    pub flags: c_int,
+}
+
+/// A datagram socket only used as the target of interface ioctls.
+struct ControlSocket(c_int);
+
+impl ControlSocket {
+    fn open() -> io::Result<ControlSocket> {
+        check(unsafe { socket(AF_INET, SOCK_DGRAM, 0) }).map(ControlSocket)
+    }
+
+    fn query(&self, iface_name: &str, request: usize) -> io::Result<ifreq::ifreq> {
+        let mut if_req = ifreq::ifreq::with_name(iface_name)?;
+        check(unsafe { ioctl(self.0, request as _, &mut if_req as *mut _) })?;
+        Ok(if_req)
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.0);
+        }
+    }
+}
+
+impl Interface {
+    pub fn query(iface_name: &str) -> io::Result<Interface> {
+        let control = ControlSocket::open()?;
+
+        let index = unsafe {
+            control
+                .query(iface_name, ifreq::SIOCGIFINDEX)?
+                .param
+                .ifr_ifindex
+        };
+        let mtu = unsafe { control.query(iface_name, ifreq::SIOCGIFMTU)?.param.ifr_mtu };
+        let flags = unsafe {
+            control
+                .query(iface_name, ifreq::SIOCGIFFLAGS)?
+                .param
+                .ifr_flags
+        };
+        let hwaddr = unsafe {
+            control
+                .query(iface_name, ifreq::SIOCGIFHWADDR)?
+                .param
+                .ifr_hwaddr
+        };
+
+        let mut hardware_address = [0; ETH_ALEN as usize];
+        for (dst, &c) in hardware_address.iter_mut().zip(hwaddr.sa_data.iter()) {
+            *dst = c as _;
+        }
+
+        Ok(Interface {
+            name: iface_name.to_owned(),
+            index,
+            hardware_address,
+            mtu: mtu as _,
+            flags: flags as u16 as _,
+        })
+    }
+
+    /// All the interfaces of the system, in the order of their indices. Those which cannot
+    /// be queried, e.g. because they disappeared meanwhile, are left out.
+    pub fn list() -> io::Result<Vec<Interface>> {
+        let names = unsafe {
+            let head = if_nameindex();
+            if head.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut names = Vec::new();
+            let mut entry = head;
+            while (*entry).if_index != 0 {
+                names.push(
+                    CStr::from_ptr((*entry).if_name)
+                        .to_string_lossy()
+                        .into_owned(),
+                );
+                entry = entry.offset(1);
+            }
+            if_freenameindex(head);
+            names
+        };
+
+        Ok(names
+            .iter()
+            .filter_map(|name| Interface::query(name).ok())
+            .collect())
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.flags & IFF_UP != 0
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.flags & IFF_RUNNING != 0
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.flags & IFF_LOOPBACK != 0
+    }
+
+    pub fn is_promisc(&self) -> bool {
+        self.flags & IFF_PROMISC != 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::socket::interface::*;
+
+    #[test]
+    fn test_query_loopback() {
+        let lo = Interface::query("lo").unwrap();
+        assert!(lo.is_loopback());
+        assert!(lo.index > 0);
+        assert!(lo.mtu > 0);
+        assert_eq!(lo.hardware_address, [0; 6]);
+        assert!(Interface::list().unwrap().contains(&lo));
+        assert!(Interface::query("no-such-iface").is_err());
+    }
+}
@@ -1,117 +1,621 @@
 use libc::{
-    bind, c_int, ioctl, packet_mreq, recv, sendto, setsockopt, sockaddr_ll, socket, write,
-    AF_PACKET, ETH_ALEN, ETH_P_ALL, PACKET_ADD_MEMBERSHIP, PACKET_MR_PROMISC, PF_PACKET, SOCK_RAW,
-    SOL_PACKET,
+    bind, c_int, close, cmsghdr, ioctl, iovec, msghdr, packet_mreq, recvmsg, sendmsg, setsockopt,
+    sockaddr_ll, socket, AF_PACKET, CMSG_DATA, CMSG_FIRSTHDR, CMSG_NXTHDR, ETH_ALEN, ETH_P_ALL,
+    MSG_TRUNC, PACKET_ADD_MEMBERSHIP, PACKET_DROP_MEMBERSHIP, PACKET_MR_MULTICAST,
+    PACKET_MR_PROMISC, PF_PACKET, SOCK_NONBLOCK, SOCK_RAW, SOL_PACKET, SOL_SOCKET,
 };
+use std::io;
+use std::mem;
+use std::task::{Context, Poll};
+use std::time::Duration;
 
+use crate::packet::{BufferPool, PacketBuf};
+use crate::reactor::Registration;
+use filter::{BpfInstruction, SockFprog};
+
+pub mod filter;
 mod ifreq;
+pub mod interface;
+pub mod ring;
+pub mod tap;
 
-/// ./x86_64-linux-gnu/bits/ioctls.h:#define SIOCGIFINDEX	0x8933		/* name -> if_index mapping	*/
-const SIOCGIFINDEX: usize = 0x8933;
+const RECV_BUFFER_SIZE: usize = 2048;
+/// Large enough for a GRO super-frame of the largest IPv4 packet, with its virtio_net_hdr.
+const GSO_RECV_BUFFER_SIZE: usize = 0x10000 + 64;
+const CMSG_BUFFER_SIZE: usize = 64;
+/// Receive buffers kept for reuse, which bounds the frames in flight without allocating.
+const RECV_POOL_CAPACITY: usize = 256;
+const GSO_RECV_POOL_CAPACITY: usize = 16;
 
-#[derive(Debug, Clone)]
-pub struct Socket {
-    pub fd: c_int,
-    pub ifindex: i32,
+/// ./asm-generic/socket.h:#define SO_ATTACH_FILTER 26
+const SO_ATTACH_FILTER: c_int = 26;
+/// ./asm-generic/socket.h:#define SO_DETACH_FILTER 27
+const SO_DETACH_FILTER: c_int = 27;
+
+/// ./linux/if_packet.h:#define PACKET_HOST 0 /* To us */
+const PACKET_HOST: u8 = 0;
+/// ./linux/if_packet.h:#define PACKET_BROADCAST 1 /* To all */
+const PACKET_BROADCAST: u8 = 1;
+/// ./linux/if_packet.h:#define PACKET_MULTICAST 2 /* To group */
+const PACKET_MULTICAST: u8 = 2;
+/// ./linux/if_packet.h:#define PACKET_OTHERHOST 3 /* To someone else */
+const PACKET_OTHERHOST: u8 = 3;
+/// ./linux/if_packet.h:#define PACKET_OUTGOING 4 /* Outgoing of any type */
+const PACKET_OUTGOING: u8 = 4;
+
+/// ./linux/if_packet.h:#define PACKET_AUXDATA 8
+const PACKET_AUXDATA: c_int = 8;
+/// ./linux/if_packet.h:#define PACKET_VNET_HDR 15
+const PACKET_VNET_HDR: c_int = 15;
+
+/// ./linux/if_packet.h:#define TP_STATUS_CSUMNOTREADY (1 << 3)
+const TP_STATUS_CSUMNOTREADY: u32 = 1 << 3;
+/// ./linux/if_packet.h:#define TP_STATUS_VLAN_VALID (1 << 4)
+const TP_STATUS_VLAN_VALID: u32 = 1 << 4;
+/// ./linux/if_packet.h:#define TP_STATUS_VLAN_TPID_VALID (1 << 6)
+pub(crate) const TP_STATUS_VLAN_TPID_VALID: u32 = 1 << 6;
+/// ./linux/if_packet.h:#define TP_STATUS_CSUM_VALID (1 << 7)
+const TP_STATUS_CSUM_VALID: u32 = 1 << 7;
+
+/// ./linux/virtio_net.h:#define VIRTIO_NET_HDR_F_NEEDS_CSUM 1
+const VIRTIO_NET_HDR_F_NEEDS_CSUM: u8 = 1;
+/// ./linux/virtio_net.h:#define VIRTIO_NET_HDR_F_DATA_VALID 2
+const VIRTIO_NET_HDR_F_DATA_VALID: u8 = 2;
+/// ./linux/virtio_net.h:#define VIRTIO_NET_HDR_GSO_NONE 0
+const VIRTIO_NET_HDR_GSO_NONE: u8 = 0;
+/// ./linux/virtio_net.h:#define VIRTIO_NET_HDR_GSO_TCPV4 1
+const VIRTIO_NET_HDR_GSO_TCPV4: u8 = 1;
+/// ./linux/virtio_net.h:#define VIRTIO_NET_HDR_GSO_UDP 3
+const VIRTIO_NET_HDR_GSO_UDP: u8 = 3;
+/// ./linux/virtio_net.h:#define VIRTIO_NET_HDR_GSO_TCPV6 4
+const VIRTIO_NET_HDR_GSO_TCPV6: u8 = 4;
+/// ./linux/virtio_net.h:#define VIRTIO_NET_HDR_GSO_UDP_L4 5
+const VIRTIO_NET_HDR_GSO_UDP_L4: u8 = 5;
+/// ./linux/virtio_net.h:#define VIRTIO_NET_HDR_GSO_ECN 0x80
+const VIRTIO_NET_HDR_GSO_ECN: u8 = 0x80;
+
+/// ./linux/if_packet.h: struct tpacket_auxdata
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct TpacketAuxdata {
+    tp_status: u32,
+    tp_len: u32,
+    tp_snaplen: u32,
+    tp_mac: u16,
+    tp_net: u16,
+    tp_vlan_tci: u16,
+    tp_vlan_tpid: u16,
 }
 
-impl Socket {
-    pub unsafe fn open_raw_socket() -> Socket {
-        Socket {
-            fd: socket(PF_PACKET, SOCK_RAW, u16::from_be(ETH_P_ALL as _) as _),
-            ifindex: -1,
+/// ./linux/virtio_net.h: struct virtio_net_hdr, in host byte order as used by packet sockets.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct VirtioNetHdr {
+    flags: u8,
+    gso_type: u8,
+    hdr_len: u16,
+    gso_size: u16,
+    csum_start: u16,
+    csum_offset: u16,
+}
+
+const VNET_HDR_LEN: usize = mem::size_of::<VirtioNetHdr>();
+
+/// How the kernel classified a received frame (`sll_pkttype`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PacketType {
+    Host,
+    Broadcast,
+    Multicast,
+    OtherHost,
+    /// A frame sent from this machine, looped back to the packet socket.
+    Outgoing,
+    Other(u8),
+}
+
+impl From<u8> for PacketType {
+    fn from(pkttype: u8) -> Self {
+        match pkttype {
+            PACKET_HOST => PacketType::Host,
+            PACKET_BROADCAST => PacketType::Broadcast,
+            PACKET_MULTICAST => PacketType::Multicast,
+            PACKET_OTHERHOST => PacketType::OtherHost,
+            PACKET_OUTGOING => PacketType::Outgoing,
+            other => PacketType::Other(other),
         }
     }
+}
 
-    // Should be Result
-    pub unsafe fn limit_interface(&mut self, iface_name: &str) -> Option<()> {
-        let mut if_req: ifreq::ifreq = std::mem::uninitialized();
-        let chars = iface_name.as_bytes();
+/// Where the transport checksum of a frame is, counted from the start of the frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChecksumPosition {
+    /// Start of the data covered by the checksum.
+    pub start: usize,
+    /// Offset of the checksum field from `start`.
+    pub offset: usize,
+}
 
-        if chars.len() + 1 > ifreq::IFNAMSIZ {
-            return None;
-        }
+/// State of the checksums of a received frame, which differs from what is on the wire
+/// when the NIC or the kernel offloads them.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    /// The checksums are as received and must be verified.
+    #[default]
+    Unknown,
+    /// The NIC or the kernel verified the checksums.
+    Valid,
+    /// The frame comes from the local stack, whose transport checksum field only holds
+    /// the sum of the pseudo header. The position is `None` if the kernel did not report it.
+    Partial(Option<ChecksumPosition>),
+}
 
-        for i in 0..chars.len() {
-            if_req.ifr_name[i] = chars[i] as _;
+/// Segmentation the kernel deferred to the NIC, or undid by GRO.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GsoType {
+    TcpV4,
+    TcpV6,
+    /// UDP fragmentation offload: the datagram is split into IP fragments.
+    Udp,
+    /// UDP segmentation offload: the payload is split into datagrams.
+    UdpL4,
+    Other(u8),
+}
+
+/// A super-frame whose payload is to be split into segments of `size` bytes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Gso {
+    pub gso_type: GsoType,
+    /// Payload bytes in each segment, the MSS for TCP.
+    pub size: usize,
+    /// Explicit congestion notification was negotiated (`VIRTIO_NET_HDR_GSO_ECN`).
+    pub ecn: bool,
+}
+
+impl VirtioNetHdr {
+    fn checksum_status(&self) -> ChecksumStatus {
+        if self.flags & VIRTIO_NET_HDR_F_NEEDS_CSUM != 0 {
+            ChecksumStatus::Partial(Some(ChecksumPosition {
+                start: self.csum_start as _,
+                offset: self.csum_offset as _,
+            }))
+        } else if self.flags & VIRTIO_NET_HDR_F_DATA_VALID != 0 {
+            ChecksumStatus::Valid
+        } else {
+            ChecksumStatus::Unknown
         }
+    }
+
+    fn gso(&self) -> Option<Gso> {
+        let gso_type = match self.gso_type & !VIRTIO_NET_HDR_GSO_ECN {
+            VIRTIO_NET_HDR_GSO_NONE => return None,
+            VIRTIO_NET_HDR_GSO_TCPV4 => GsoType::TcpV4,
+            VIRTIO_NET_HDR_GSO_TCPV6 => GsoType::TcpV6,
+            VIRTIO_NET_HDR_GSO_UDP => GsoType::Udp,
+            VIRTIO_NET_HDR_GSO_UDP_L4 => GsoType::UdpL4,
+            other => GsoType::Other(other),
+        };
+        Some(Gso {
+            gso_type,
+            size: self.gso_size as _,
+            ecn: self.gso_type & VIRTIO_NET_HDR_GSO_ECN != 0,
+        })
+    }
+}
 
-        let io_result = ioctl(self.fd, SIOCGIFINDEX as _, &mut if_req as *mut _);
+/// Checksum state reported in `tp_status`, by auxdata or a ring frame header.
+pub(crate) fn checksum_status_from_tp_status(status: u32) -> ChecksumStatus {
+    if status & TP_STATUS_CSUMNOTREADY != 0 {
+        ChecksumStatus::Partial(None)
+    } else if status & TP_STATUS_CSUM_VALID != 0 {
+        ChecksumStatus::Valid
+    } else {
+        ChecksumStatus::Unknown
+    }
+}
+
+/// What the kernel reports about a received frame besides its bytes.
+/// Fields are `None` where the receiving path does not provide them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Reception time since the UNIX epoch.
+    pub timestamp: Option<Duration>,
+    /// Length of the frame on the wire, which may exceed the captured bytes.
+    pub original_length: Option<usize>,
+    /// 802.1Q tag control information removed from the frame by the kernel.
+    pub vlan_tci: Option<u16>,
+    /// Tag protocol identifier of the removed tag, if the kernel reported it.
+    pub vlan_tpid: Option<u16>,
+    pub packet_type: Option<PacketType>,
+    /// Index of the interface the frame was received on.
+    pub ifindex: Option<c_int>,
+    pub checksum: ChecksumStatus,
+    /// Set on a super-frame built by GRO or left unsegmented by the local stack.
+    pub gso: Option<Gso>,
+}
+
+impl FrameInfo {
+    fn from_sockaddr(sa: &sockaddr_ll) -> Self {
+        FrameInfo {
+            packet_type: Some(sa.sll_pkttype.into()),
+            ifindex: Some(sa.sll_ifindex),
+            ..FrameInfo::default()
+        }
+    }
 
-        if io_result < 0 {
-            return None;
+    fn apply_auxdata(&mut self, aux: &TpacketAuxdata) {
+        self.original_length = Some(aux.tp_len as _);
+        if aux.tp_status & TP_STATUS_VLAN_VALID != 0 {
+            self.vlan_tci = Some(aux.tp_vlan_tci);
+            if aux.tp_status & TP_STATUS_VLAN_TPID_VALID != 0 {
+                self.vlan_tpid = Some(aux.tp_vlan_tpid);
+            }
         }
+        if self.checksum == ChecksumStatus::Unknown {
+            self.checksum = checksum_status_from_tp_status(aux.tp_status);
+        }
+    }
+}
+
+/// Non-blocking `AF_PACKET` raw socket. The descriptor is closed, and the promiscuous mode
+/// enabled through it is left, when the socket is dropped.
+#[derive(Debug)]
+pub struct Socket {
+    fd: c_int,
+    ifindex: Option<c_int>,
+    promisc: bool,
+    vnet_hdr: bool,
+    /// Buffers of received frames, large enough for super-frames once `vnet_hdr` is set.
+    pool: BufferPool,
+    registration: Registration,
+}
 
-        let mut sa: sockaddr_ll = std::mem::uninitialized();
+fn check(result: c_int) -> io::Result<c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+impl Socket {
+    pub fn open_raw_socket() -> io::Result<Socket> {
+        let fd = check(unsafe {
+            socket(
+                PF_PACKET,
+                SOCK_RAW | SOCK_NONBLOCK,
+                u16::to_be(ETH_P_ALL as _) as _,
+            )
+        })?;
 
+        Ok(Socket {
+            fd,
+            ifindex: None,
+            promisc: false,
+            vnet_hdr: false,
+            pool: BufferPool::new(RECV_BUFFER_SIZE, RECV_POOL_CAPACITY),
+            registration: Registration::new(fd),
+        })
+    }
+
+    pub fn fd(&self) -> c_int {
+        self.fd
+    }
+
+    /// The index of the interface bound by `limit_interface`.
+    pub fn ifindex(&self) -> Option<c_int> {
+        self.ifindex
+    }
+
+    pub fn limit_interface(&mut self, iface_name: &str) -> io::Result<()> {
+        let mut if_req = ifreq::ifreq::with_name(iface_name)?;
+        check(unsafe { ioctl(self.fd, ifreq::SIOCGIFINDEX as _, &mut if_req as *mut _) })?;
+        let ifindex = unsafe { if_req.param.ifr_ifindex };
+
+        let mut sa: sockaddr_ll = unsafe { mem::zeroed() };
         sa.sll_family = AF_PACKET as _;
-        sa.sll_protocol = u16::from_be(ETH_P_ALL as _) as _;
-        sa.sll_ifindex = if_req.param.ifr_ifindex;
-        self.ifindex = if_req.param.ifr_ifindex;
-
-        let bind_result = bind(
-            self.fd,
-            &sa as *const _ as _,
-            std::mem::size_of::<sockaddr_ll>() as _,
-        );
-
-        if bind_result < 0 {
-            None
-        } else {
-            Some(())
-        }
+        sa.sll_protocol = u16::to_be(ETH_P_ALL as _);
+        sa.sll_ifindex = ifindex;
+
+        check(unsafe {
+            bind(
+                self.fd,
+                &sa as *const _ as _,
+                mem::size_of::<sockaddr_ll>() as _,
+            )
+        })?;
+
+        self.ifindex = Some(ifindex);
+        Ok(())
+    }
+
+    fn membership(&self, operation: c_int, mr_type: c_int, address: &[u8]) -> io::Result<()> {
+        let mut opt: packet_mreq = unsafe { mem::zeroed() };
+
+        opt.mr_ifindex = self.ifindex.unwrap_or(0);
+        opt.mr_type = mr_type as _;
+        opt.mr_alen = address.len() as _;
+        opt.mr_address[..address.len()].copy_from_slice(address);
+
+        check(unsafe {
+            setsockopt(
+                self.fd,
+                SOL_PACKET, // socket level API
+                operation,  // control physical layer
+                &opt as *const _ as _,
+                mem::size_of::<packet_mreq>() as _,
+            )
+        })
+        .map(|_| ())
+    }
+
+    pub fn enable_promisc_mode(&mut self) -> io::Result<()> {
+        self.membership(PACKET_ADD_MEMBERSHIP, PACKET_MR_PROMISC, &[])?;
+        self.promisc = true;
+        Ok(())
+    }
+
+    pub fn disable_promisc_mode(&mut self) -> io::Result<()> {
+        self.membership(PACKET_DROP_MEMBERSHIP, PACKET_MR_PROMISC, &[])?;
+        self.promisc = false;
+        Ok(())
+    }
+
+    fn set_int_option(&self, option: c_int, value: c_int) -> io::Result<()> {
+        check(unsafe {
+            setsockopt(
+                self.fd,
+                SOL_PACKET,
+                option,
+                &value as *const _ as _,
+                mem::size_of::<c_int>() as _,
+            )
+        })
+        .map(|_| ())
+    }
+
+    /// Has `recv` report the checksum state, the length on the wire and the VLAN tag
+    /// stripped by the kernel through `PACKET_AUXDATA`.
+    pub fn enable_auxdata(&self) -> io::Result<()> {
+        self.set_int_option(PACKET_AUXDATA, 1)
+    }
+
+    /// Exchanges a virtio_net_hdr with the kernel in front of every frame, so that `recv`
+    /// reports where partial checksums are and how super-frames are to be segmented.
+    /// Frames passed to `send` get an empty header, asking for no offload.
+    ///
+    /// This must be enabled before the socket is used for a ring.
+    pub fn enable_vnet_hdr(&mut self) -> io::Result<()> {
+        self.set_int_option(PACKET_VNET_HDR, 1)?;
+        self.vnet_hdr = true;
+        self.pool = BufferPool::new(GSO_RECV_BUFFER_SIZE, GSO_RECV_POOL_CAPACITY);
+        Ok(())
+    }
+
+    /// Enables both `enable_auxdata` and `enable_vnet_hdr`, which lets the stack work
+    /// on a NIC with checksum offload and GRO left enabled.
+    pub fn enable_offload_info(&mut self) -> io::Result<()> {
+        self.enable_auxdata()?;
+        self.enable_vnet_hdr()
+    }
+
+    /// Has the interface bound by `limit_interface` receive the frames sent to the Ethernet
+    /// multicast `group`. The kernel leaves the groups when the socket is closed.
+    pub fn join_multicast(&self, group: [u8; ETH_ALEN as usize]) -> io::Result<()> {
+        self.membership(PACKET_ADD_MEMBERSHIP, PACKET_MR_MULTICAST, &group)
     }
 
-    pub unsafe fn enable_promisc_mode(&self) -> Option<()> {
-        let mut opt: packet_mreq = std::mem::uninitialized();
+    pub fn leave_multicast(&self, group: [u8; ETH_ALEN as usize]) -> io::Result<()> {
+        self.membership(PACKET_DROP_MEMBERSHIP, PACKET_MR_MULTICAST, &group)
+    }
+
+    /// Lets the kernel drop the frames `program` rejects before they are queued to the socket.
+    /// Attaching another program replaces it.
+    pub fn attach_filter(&self, program: &[BpfInstruction]) -> io::Result<()> {
+        let fprog = SockFprog {
+            len: program.len() as _,
+            filter: program.as_ptr(),
+        };
+
+        check(unsafe {
+            setsockopt(
+                self.fd,
+                SOL_SOCKET,
+                SO_ATTACH_FILTER,
+                &fprog as *const _ as _,
+                mem::size_of::<SockFprog>() as _,
+            )
+        })
+        .map(|_| ())
+    }
+
+    pub fn detach_filter(&self) -> io::Result<()> {
+        let dummy: c_int = 0;
+        check(unsafe {
+            setsockopt(
+                self.fd,
+                SOL_SOCKET,
+                SO_DETACH_FILTER,
+                &dummy as *const _ as _,
+                mem::size_of::<c_int>() as _,
+            )
+        })
+        .map(|_| ())
+    }
 
-        if self.ifindex >= 0 {
-            opt.mr_ifindex = self.ifindex;
+    /// Receives a frame if one is queued, failing with `WouldBlock` otherwise.
+    pub fn recv(&self) -> io::Result<(PacketBuf, FrameInfo)> {
+        let mut packet = self.pool.get(0);
+        let buf_size = packet.tailroom();
+        let buf = packet.append_unspecified(buf_size);
+        let mut sa: sockaddr_ll = unsafe { mem::zeroed() };
+        // u64 keeps the control messages aligned.
+        let mut control = [0u64; CMSG_BUFFER_SIZE / 8];
+        let mut iov = iovec {
+            iov_base: buf.as_mut_ptr() as _,
+            iov_len: buf.len(),
+        };
+        let mut msg: msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut sa as *mut _ as _;
+        msg.msg_namelen = mem::size_of::<sockaddr_ll>() as _;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as _;
+        msg.msg_controllen = CMSG_BUFFER_SIZE as _;
+
+        // With MSG_TRUNC the length of the whole frame is returned even if it does not fit.
+        let l_recv = unsafe { recvmsg(self.fd, &mut msg, MSG_TRUNC) };
+        if l_recv < 0 {
+            return Err(io::Error::last_os_error());
         }
+        let l_recv = l_recv as usize;
+        packet.truncate(l_recv.min(buf_size));
 
-        opt.mr_type = PACKET_MR_PROMISC as _; // the other fields are not used
-
-        if setsockopt(
-            self.fd,
-            SOL_PACKET,            // socket level API
-            PACKET_ADD_MEMBERSHIP, // control physical layer
-            &opt as *const _ as _,
-            std::mem::size_of::<packet_mreq>() as _,
-        ) >= 0
-        {
-            Some(())
-        } else {
-            None
+        let mut info = FrameInfo::from_sockaddr(&sa);
+        if self.vnet_hdr {
+            if packet.len() < VNET_HDR_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "frame without virtio_net_hdr",
+                ));
+            }
+            let hdr: VirtioNetHdr =
+                unsafe { std::ptr::read_unaligned(packet.as_ptr() as *const VirtioNetHdr) };
+            info.checksum = hdr.checksum_status();
+            info.gso = hdr.gso();
+            packet.pull(VNET_HDR_LEN);
+        }
+        info.original_length = Some(l_recv - if self.vnet_hdr { VNET_HDR_LEN } else { 0 });
+
+        unsafe {
+            let mut cmsg: *const cmsghdr = CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == SOL_PACKET && (*cmsg).cmsg_type == PACKET_AUXDATA {
+                    let aux = std::ptr::read_unaligned(CMSG_DATA(cmsg) as *const TpacketAuxdata);
+                    info.apply_auxdata(&aux);
+                }
+                cmsg = CMSG_NXTHDR(&msg, cmsg);
+            }
         }
+
+        Ok((packet, info))
     }
 
-    pub unsafe fn recv(&self) -> Vec<u8> {
-        let length = 2048;
-        let mut buf = vec![0u8; length];
-        let l_recv = recv(self.fd, buf.as_mut_ptr() as _, length, 0) as usize;
-        buf.truncate(l_recv);
-        buf
+    /// Receives a frame, waking the task through the reactor when one arrives.
+    pub fn poll_recv(&self, cx: &mut Context) -> Poll<io::Result<(PacketBuf, FrameInfo)>> {
+        self.registration.poll_read_with(cx, || self.recv())
     }
 
-    pub unsafe fn send(&self, buf: &[u8]) -> isize {
-        let mut sa: sockaddr_ll = std::mem::uninitialized();
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut sa: sockaddr_ll = unsafe { mem::zeroed() };
 
         sa.sll_family = AF_PACKET as _;
-        if self.ifindex >= 0 {
-            sa.sll_ifindex = self.ifindex;
-        }
+        sa.sll_ifindex = self.ifindex.unwrap_or(0);
         sa.sll_halen = ETH_ALEN as _;
         for i in 0..ETH_ALEN as _ {
             sa.sll_addr[i] = 0xFF;
         }
 
-        sendto(
-            self.fd,
-            buf.as_ptr() as _,
-            buf.len(),
-            0,
-            &sa as *const _ as _,
-            std::mem::size_of::<sockaddr_ll>() as _,
-        )
+        // An empty virtio_net_hdr goes in front of the frame without copying it.
+        let vnet_hdr = [0u8; VNET_HDR_LEN];
+        let mut iov = [
+            iovec {
+                iov_base: vnet_hdr.as_ptr() as _,
+                iov_len: VNET_HDR_LEN,
+            },
+            iovec {
+                iov_base: buf.as_ptr() as _,
+                iov_len: buf.len(),
+            },
+        ];
+        let iov = if self.vnet_hdr {
+            &mut iov[..]
+        } else {
+            &mut iov[1..]
+        };
+        let mut msg: msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut sa as *mut _ as _;
+        msg.msg_namelen = mem::size_of::<sockaddr_ll>() as _;
+        msg.msg_iov = iov.as_mut_ptr();
+        msg.msg_iovlen = iov.len() as _;
+
+        let l_sent = unsafe { sendmsg(self.fd, &msg, 0) };
+
+        if l_sent < 0 {
+            Err(io::Error::last_os_error())
+        } else if self.vnet_hdr {
+            Ok((l_sent as usize).saturating_sub(VNET_HDR_LEN))
+        } else {
+            Ok(l_sent as _)
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if self.promisc {
+            let _ = self.disable_promisc_mode();
+        }
+        self.registration.deregister();
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::socket::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_offload_info() {
+        let mut socket = match Socket::open_raw_socket() {
+            Ok(socket) => socket,
+            // Raw sockets need CAP_NET_RAW.
+            Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(err) => panic!("{}", err),
+        };
+        socket.enable_offload_info().unwrap();
+        socket.limit_interface("lo").unwrap();
+
+        let mut frame = vec![0u8; 60];
+        frame[..6].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x11]);
+        frame[12..14].copy_from_slice(&0x88B5u16.to_be_bytes());
+        frame[14..18].copy_from_slice(b"vnet");
+        assert_eq!(socket.send(&frame).unwrap(), frame.len());
+
+        // The looped-back frame may not be queued yet.
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let (received, info) = loop {
+            match socket.recv() {
+                Ok((received, info)) if received[12..18] == frame[12..18] => {
+                    break (received, info)
+                }
+                Ok(_) => continue,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    assert!(Instant::now() < deadline, "the frame is not looped back");
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Err(err) => panic!("{}", err),
+            }
+        };
+        assert_eq!(&received[..], &frame[..]);
+        assert_eq!(info.original_length, Some(frame.len()));
+        assert_eq!(info.gso, None);
+    }
+
+    #[test]
+    fn test_multicast_membership() {
+        let mut socket = match Socket::open_raw_socket() {
+            Ok(socket) => socket,
+            Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(err) => panic!("{}", err),
+        };
+        socket.limit_interface("lo").unwrap();
+
+        let group = [0x01, 0x00, 0x5E, 0x01, 0x02, 0x03];
+        socket.join_multicast(group).unwrap();
+        socket.leave_multicast(group).unwrap();
     }
 }
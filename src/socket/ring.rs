@@ -0,0 +1,427 @@
+//! `PACKET_MMAP` rings with the `TPACKET_V3` block-based layout.
+//!
+//! Received frames are copied straight out of the blocks the kernel hands over, and frames to
+//! send are written into the TX ring and flushed by an empty `send`, so neither direction
+//! takes a system call per frame.
+
+use super::{check, checksum_status_from_tp_status, FrameInfo, Socket, TP_STATUS_VLAN_TPID_VALID};
+use crate::packet::{self, PacketBuf};
+use libc::{
+    c_int, c_uint, c_void, mmap, munmap, send, setsockopt, sockaddr_ll, MAP_FAILED, MAP_SHARED,
+    PROT_READ, PROT_WRITE, SOL_PACKET,
+};
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{fence, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// ./linux/if_packet.h:#define PACKET_RX_RING 5
+const PACKET_RX_RING: c_int = 5;
+/// ./linux/if_packet.h:#define PACKET_VERSION 10
+const PACKET_VERSION: c_int = 10;
+/// ./linux/if_packet.h:#define PACKET_TX_RING 13
+const PACKET_TX_RING: c_int = 13;
+/// ./linux/if_packet.h:#define PACKET_LOSS 14
+const PACKET_LOSS: c_int = 14;
+/// ./linux/if_packet.h: enum tpacket_versions { TPACKET_V1, TPACKET_V2, TPACKET_V3 };
+const TPACKET_V3: c_int = 2;
+
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1 << 0;
+const TP_STATUS_VLAN_VALID: u32 = 1 << 4;
+const TP_STATUS_AVAILABLE: u32 = 0;
+const TP_STATUS_SEND_REQUEST: u32 = 1 << 0;
+const TP_STATUS_WRONG_FORMAT: u32 = 1 << 2;
+
+/// ./linux/if_packet.h:#define TPACKET_ALIGNMENT 16
+const TPACKET_ALIGNMENT: usize = 16;
+
+fn tpacket_align(x: usize) -> usize {
+    (x + TPACKET_ALIGNMENT - 1) & !(TPACKET_ALIGNMENT - 1)
+}
+
+#[repr(C)]
+struct TpacketReq3 {
+    tp_block_size: c_uint,
+    tp_block_nr: c_uint,
+    tp_frame_size: c_uint,
+    tp_frame_nr: c_uint,
+    tp_retire_blk_tov: c_uint,
+    tp_sizeof_priv: c_uint,
+    tp_feature_req_word: c_uint,
+}
+
+#[repr(C)]
+struct TpacketBdTs {
+    ts_sec: u32,
+    ts_nsec: u32,
+}
+
+#[repr(C)]
+struct TpacketHdrV1 {
+    block_status: u32,
+    num_pkts: u32,
+    offset_to_first_pkt: u32,
+    blk_len: u32,
+    seq_num: u64,
+    ts_first_pkt: TpacketBdTs,
+    ts_last_pkt: TpacketBdTs,
+}
+
+#[repr(C)]
+struct TpacketBlockDesc {
+    version: u32,
+    offset_to_priv: u32,
+    hdr: TpacketHdrV1,
+}
+
+#[repr(C)]
+struct TpacketHdrVariant1 {
+    tp_rxhash: u32,
+    tp_vlan_tci: u32,
+    tp_vlan_tpid: u16,
+    tp_padding: u16,
+}
+
+#[repr(C)]
+struct Tpacket3Hdr {
+    tp_next_offset: u32,
+    tp_sec: u32,
+    tp_nsec: u32,
+    tp_snaplen: u32,
+    tp_len: u32,
+    tp_status: u32,
+    tp_mac: u16,
+    tp_net: u16,
+    hv1: TpacketHdrVariant1,
+    tp_padding: [u8; 8],
+}
+
+/// Geometry of one ring. Blocks must be a multiple of the page size,
+/// and frames a multiple of 16 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingConfig {
+    pub block_size: usize,
+    pub block_count: usize,
+    pub frame_size: usize,
+    /// How long the kernel keeps a partially filled RX block before handing it over.
+    pub retire_timeout: Duration,
+}
+
+impl Default for RingConfig {
+    fn default() -> Self {
+        RingConfig {
+            block_size: 1 << 20,
+            block_count: 16,
+            frame_size: 2048,
+            retire_timeout: Duration::from_millis(10),
+        }
+    }
+}
+
+impl RingConfig {
+    fn frame_count(&self) -> usize {
+        self.block_size / self.frame_size * self.block_count
+    }
+
+    fn size(&self) -> usize {
+        self.block_size * self.block_count
+    }
+
+    fn request(&self, rx: bool) -> TpacketReq3 {
+        TpacketReq3 {
+            tp_block_size: self.block_size as _,
+            tp_block_nr: self.block_count as _,
+            tp_frame_size: self.frame_size as _,
+            tp_frame_nr: self.frame_count() as _,
+            // The TX ring refuses the RX-only parameters.
+            tp_retire_blk_tov: if rx {
+                self.retire_timeout.as_millis() as _
+            } else {
+                0
+            },
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        }
+    }
+}
+
+/// Position in the RX ring: the block being read and the next frame in it.
+struct RxCursor {
+    block: usize,
+    remaining: u32,
+    offset: usize,
+}
+
+struct Mapping {
+    base: *mut u8,
+    size: usize,
+}
+
+// The mapping is only touched under the locks of `PacketRing`.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.base as *mut c_void, self.size);
+        }
+    }
+}
+
+/// A raw socket whose frames are exchanged through memory-mapped RX and TX rings.
+pub struct PacketRing {
+    mapping: Mapping,
+    rx_config: RingConfig,
+    tx_config: RingConfig,
+    rx: Mutex<RxCursor>,
+    tx: Mutex<usize>,
+    // Declared last so that the rings are unmapped before the socket is closed.
+    socket: Socket,
+}
+
+fn set_option<T>(socket: &Socket, name: c_int, value: &T) -> io::Result<()> {
+    check(unsafe {
+        setsockopt(
+            socket.fd(),
+            SOL_PACKET,
+            name,
+            value as *const _ as _,
+            mem::size_of::<T>() as _,
+        )
+    })
+    .map(|_| ())
+}
+
+impl PacketRing {
+    /// Sets up the rings on `socket`, which is usually already bound by `limit_interface`.
+    pub fn new(socket: Socket, rx_config: RingConfig, tx_config: RingConfig) -> io::Result<Self> {
+        set_option(&socket, PACKET_VERSION, &TPACKET_V3)?;
+        // The kernel skips the frames it refuses instead of stopping the TX ring at them.
+        // It cannot be changed once the rings are set up.
+        set_option(&socket, PACKET_LOSS, &(1 as c_int))?;
+        set_option(&socket, PACKET_RX_RING, &rx_config.request(true))?;
+        set_option(&socket, PACKET_TX_RING, &tx_config.request(false))?;
+
+        // The TX ring is mapped right after the RX ring.
+        let size = rx_config.size() + tx_config.size();
+        let base = unsafe {
+            mmap(
+                ptr::null_mut(),
+                size,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                socket.fd(),
+                0,
+            )
+        };
+        if base == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(PacketRing {
+            mapping: Mapping {
+                base: base as _,
+                size,
+            },
+            rx_config,
+            tx_config,
+            rx: Mutex::new(RxCursor {
+                block: 0,
+                remaining: 0,
+                offset: 0,
+            }),
+            tx: Mutex::new(0),
+            socket,
+        })
+    }
+
+    pub fn socket(&self) -> &Socket {
+        &self.socket
+    }
+
+    fn block(&self, index: usize) -> *mut TpacketBlockDesc {
+        unsafe { self.mapping.base.add(index * self.rx_config.block_size) as _ }
+    }
+
+    fn tx_frame(&self, index: usize) -> *mut Tpacket3Hdr {
+        let frames_per_block = self.tx_config.block_size / self.tx_config.frame_size;
+        let offset = self.rx_config.size()
+            + index / frames_per_block * self.tx_config.block_size
+            + index % frames_per_block * self.tx_config.frame_size;
+        unsafe { self.mapping.base.add(offset) as _ }
+    }
+
+    /// Takes the next frame out of the RX ring, failing with `WouldBlock` if the kernel
+    /// has not handed over any block yet.
+    pub fn recv(&self) -> io::Result<(PacketBuf, FrameInfo)> {
+        let mut cursor = self.rx.lock().unwrap();
+
+        unsafe {
+            let block = self.block(cursor.block);
+
+            if cursor.remaining == 0 {
+                let status = ptr::read_volatile(&(*block).hdr.block_status);
+                if status & TP_STATUS_USER == 0 {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                fence(Ordering::Acquire);
+
+                cursor.remaining = (*block).hdr.num_pkts;
+                cursor.offset = (*block).hdr.offset_to_first_pkt as _;
+                if cursor.remaining == 0 {
+                    self.release_block(&mut cursor);
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+            }
+
+            let header = (block as *const u8).add(cursor.offset) as *const Tpacket3Hdr;
+            let data = (header as *const u8).add((*header).tp_mac as _);
+            let mut frame = packet::default_pool().get(0);
+            frame.extend_from_slice(std::slice::from_raw_parts(data, (*header).tp_snaplen as _));
+
+            // The kernel places the link-layer address right after the aligned header.
+            let sa = (header as *const u8).add(tpacket_align(mem::size_of::<Tpacket3Hdr>()))
+                as *const sockaddr_ll;
+
+            let info = FrameInfo {
+                timestamp: Some(Duration::new((*header).tp_sec as _, (*header).tp_nsec as _)),
+                original_length: Some((*header).tp_len as _),
+                vlan_tci: if (*header).tp_status & TP_STATUS_VLAN_VALID != 0 {
+                    Some((*header).hv1.tp_vlan_tci as _)
+                } else {
+                    None
+                },
+                vlan_tpid: if (*header).tp_status & TP_STATUS_VLAN_TPID_VALID != 0 {
+                    Some((*header).hv1.tp_vlan_tpid)
+                } else {
+                    None
+                },
+                // A ring carries no virtio_net_hdr, so a partial checksum has no position.
+                checksum: checksum_status_from_tp_status((*header).tp_status),
+                ..FrameInfo::from_sockaddr(&*sa)
+            };
+
+            cursor.remaining -= 1;
+            cursor.offset += (*header).tp_next_offset as usize;
+            if cursor.remaining == 0 {
+                self.release_block(&mut cursor);
+            }
+
+            Ok((frame, info))
+        }
+    }
+
+    unsafe fn release_block(&self, cursor: &mut RxCursor) {
+        let block = self.block(cursor.block);
+        fence(Ordering::Release);
+        ptr::write_volatile(&mut (*block).hdr.block_status, TP_STATUS_KERNEL);
+        cursor.block = (cursor.block + 1) % self.rx_config.block_count;
+    }
+
+    /// Receives a frame, waking the task through the reactor when a block is handed over.
+    pub fn poll_recv(&self, cx: &mut Context) -> Poll<io::Result<(PacketBuf, FrameInfo)>> {
+        self.socket.registration.poll_read_with(cx, || self.recv())
+    }
+
+    /// Queues `buf` in the TX ring and asks the kernel to transmit the pending frames.
+    /// Fails with `WouldBlock` when the ring is full.
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let data_offset = tpacket_align(mem::size_of::<Tpacket3Hdr>());
+        if data_offset + buf.len() > self.tx_config.frame_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame is larger than the TX ring slot",
+            ));
+        }
+
+        let mut index = self.tx.lock().unwrap();
+        unsafe {
+            let header = self.tx_frame(*index);
+            let status = ptr::read_volatile(&(*header).tp_status);
+            if status & TP_STATUS_WRONG_FORMAT != 0 {
+                // The slot is given up, so that the ring goes on with the next frames.
+                ptr::write_volatile(&mut (*header).tp_status, TP_STATUS_AVAILABLE);
+                *index = (*index + 1) % self.tx_config.frame_count();
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the kernel refused a frame in the TX ring",
+                ));
+            }
+            if status != TP_STATUS_AVAILABLE {
+                self.flush();
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            fence(Ordering::Acquire);
+
+            let data = (header as *mut u8).add(data_offset);
+            ptr::copy_nonoverlapping(buf.as_ptr(), data, buf.len());
+            (*header).tp_next_offset = 0;
+            (*header).tp_len = buf.len() as _;
+            (*header).tp_snaplen = buf.len() as _;
+
+            fence(Ordering::Release);
+            ptr::write_volatile(&mut (*header).tp_status, TP_STATUS_SEND_REQUEST);
+        }
+        *index = (*index + 1) % self.tx_config.frame_count();
+
+        self.flush();
+        Ok(buf.len())
+    }
+
+    fn flush(&self) {
+        // The socket is non-blocking, so this only kicks off the transmission.
+        unsafe {
+            send(self.socket.fd(), ptr::null(), 0, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::reactor::block_on;
+    use crate::socket::ring::*;
+    use futures::future;
+
+    #[test]
+    fn test_loopback() {
+        let mut socket = match Socket::open_raw_socket() {
+            Ok(socket) => socket,
+            // Raw sockets need CAP_NET_RAW.
+            Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(err) => panic!("{}", err),
+        };
+        socket.limit_interface("lo").unwrap();
+        let config = RingConfig {
+            block_size: 1 << 16,
+            block_count: 4,
+            ..RingConfig::default()
+        };
+        let ring = PacketRing::new(socket, config, config).unwrap();
+
+        let mut frame = vec![0u8; 60];
+        frame[12..14].copy_from_slice(&[0x88, 0xB5]); // local experimental ethertype
+        frame[14..22].copy_from_slice(b"ringtest");
+        assert_eq!(ring.send(&frame).unwrap(), frame.len());
+
+        let (received, info) = block_on(future::poll_fn(|cx| loop {
+            match ring.poll_recv(cx) {
+                Poll::Ready(Ok((received, info))) if received[..] == frame[..] => {
+                    return Poll::Ready((received, info))
+                }
+                Poll::Ready(Ok(_)) => continue,
+                Poll::Ready(Err(err)) => panic!("{}", err),
+                Poll::Pending => return Poll::Pending,
+            }
+        }));
+        assert_eq!(&received[..], &frame[..]);
+        assert_eq!(info.original_length, Some(frame.len()));
+        assert!(info.timestamp.is_some());
+        assert_eq!(info.ifindex, ring.socket().ifindex());
+        assert!(info.packet_type.is_some());
+    }
+}
@@ -0,0 +1,107 @@
+use super::ifreq;
+use libc::{c_int, c_short, close, ioctl, open, read, write, O_NONBLOCK, O_RDWR};
+use std::ffi::CStr;
+use std::io;
+use std::task::{Context, Poll};
+
+use crate::packet::{BufferPool, PacketBuf};
+use crate::reactor::Registration;
+
+/// ./linux/if_tun.h:#define TUNSETIFF     _IOW('T', 202, int)
+const TUNSETIFF: usize = 0x4004_54CA;
+/// ./linux/if_tun.h:#define IFF_TAP        0x0002
+const IFF_TAP: c_short = 0x0002;
+/// ./linux/if_tun.h:#define IFF_NO_PI    0x1000
+const IFF_NO_PI: c_short = 0x1000;
+
+const TUN_DEVICE_PATH: &[u8] = b"/dev/net/tun\0";
+const RECV_BUFFER_SIZE: usize = 2048;
+const RECV_POOL_CAPACITY: usize = 256;
+
+/// A TAP interface, which exchanges whole Ethernet frames (without the packet information
+/// header) with the kernel. The interface still has to be brought up, e.g. by `ip link set`.
+/// The descriptor is closed when dropped, which also removes a non-persistent interface.
+#[derive(Debug)]
+pub struct Tap {
+    fd: c_int,
+    name: String,
+    pool: BufferPool,
+    registration: Registration,
+}
+
+impl Tap {
+    /// Creates the tap interface `iface_name`, or attaches to it if it already exists.
+    /// A name such as `tap%d` lets the kernel choose the number.
+    pub fn open(iface_name: &str) -> io::Result<Tap> {
+        let mut if_req = ifreq::ifreq::with_name(iface_name)?;
+        if_req.param.ifr_flags = IFF_TAP | IFF_NO_PI;
+
+        unsafe {
+            let fd: c_int = open(TUN_DEVICE_PATH.as_ptr() as _, O_RDWR | O_NONBLOCK);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if ioctl(fd, TUNSETIFF as _, &mut if_req as *mut _) < 0 {
+                let err = io::Error::last_os_error();
+                close(fd);
+                return Err(err);
+            }
+
+            let name = CStr::from_ptr(if_req.ifr_name.as_ptr())
+                .to_string_lossy()
+                .into_owned();
+
+            Ok(Tap {
+                fd,
+                name,
+                pool: BufferPool::new(RECV_BUFFER_SIZE, RECV_POOL_CAPACITY),
+                registration: Registration::new(fd),
+            })
+        }
+    }
+
+    pub fn fd(&self) -> c_int {
+        self.fd
+    }
+
+    /// The name the kernel gave to the interface.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Receives a frame if one is queued, failing with `WouldBlock` otherwise.
+    pub fn recv(&self) -> io::Result<PacketBuf> {
+        let mut packet = self.pool.get(0);
+        let buf = packet.append_unspecified(RECV_BUFFER_SIZE);
+        let l_recv = unsafe { read(self.fd, buf.as_mut_ptr() as _, buf.len()) };
+        if l_recv < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        packet.truncate(l_recv as _);
+        Ok(packet)
+    }
+
+    /// Receives a frame, waking the task through the reactor when one arrives.
+    pub fn poll_recv(&self, cx: &mut Context) -> Poll<io::Result<PacketBuf>> {
+        self.registration.poll_read_with(cx, || self.recv())
+    }
+
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let l_sent = unsafe { write(self.fd, buf.as_ptr() as _, buf.len()) };
+        if l_sent < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(l_sent as _)
+        }
+    }
+}
+
+impl Drop for Tap {
+    fn drop(&mut self) {
+        self.registration.deregister();
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
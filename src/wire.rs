@@ -0,0 +1,104 @@
+//! Integers in network byte order, for the fields of headers mapped onto packets.
+//!
+//! They are byte arrays, so a header made of them has no padding and an alignment of 1,
+//! and their value is only reachable through `get`, which does the conversion.
+
+use map_struct::Mappable;
+use std::fmt;
+
+/// A `u16` in network byte order.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct U16Be([u8; 2]);
+
+impl U16Be {
+    pub const fn new(value: u16) -> Self {
+        U16Be(value.to_be_bytes())
+    }
+
+    pub fn get(self) -> u16 {
+        u16::from_be_bytes(self.0)
+    }
+
+    pub fn set(&mut self, value: u16) {
+        self.0 = value.to_be_bytes();
+    }
+}
+
+impl From<u16> for U16Be {
+    fn from(value: u16) -> Self {
+        U16Be::new(value)
+    }
+}
+
+impl From<U16Be> for u16 {
+    fn from(value: U16Be) -> Self {
+        value.get()
+    }
+}
+
+impl fmt::Debug for U16Be {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get())
+    }
+}
+
+unsafe impl Mappable for U16Be {}
+
+/// A `u32` in network byte order.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct U32Be([u8; 4]);
+
+impl U32Be {
+    pub const fn new(value: u32) -> Self {
+        U32Be(value.to_be_bytes())
+    }
+
+    pub fn get(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub fn set(&mut self, value: u32) {
+        self.0 = value.to_be_bytes();
+    }
+}
+
+impl From<u32> for U32Be {
+    fn from(value: u32) -> Self {
+        U32Be::new(value)
+    }
+}
+
+impl From<U32Be> for u32 {
+    fn from(value: U32Be) -> Self {
+        value.get()
+    }
+}
+
+impl fmt::Debug for U32Be {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get())
+    }
+}
+
+unsafe impl Mappable for U32Be {}
+
+#[cfg(test)]
+mod test {
+    use crate::wire::*;
+
+    #[test]
+    fn test_byte_order() {
+        let data = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+        let (short, rest) = U16Be::mapped(&data).unwrap();
+        let (long, _) = U32Be::mapped(rest).unwrap();
+        assert_eq!(short.get(), 0x1234);
+        assert_eq!(long.get(), 0x5678_9ABC);
+
+        let mut field = U16Be::default();
+        field.set(0x0800);
+        assert_eq!(field.as_bytes(), &[0x08, 0x00]);
+        assert_eq!(u16::from(field), 0x0800);
+    }
+}